@@ -24,7 +24,6 @@ fn load_world(path: impl AsRef<Path>) -> PlotWorld {
         z: 0,
         chunks,
         to_be_ticked: data.pending_ticks,
-        packet_senders: Vec::new(),
     }
 }
 
@@ -32,9 +31,9 @@ fn init_compiler() -> (PlotWorld, Compiler) {
     let mut world = load_world("./benches/chungus_mandelbrot_plot");
     let mut compiler: Compiler = Default::default();
 
-    let options = CompilerOptions::parse("-O");
+    let options = CompilerOptions::parse("-O").unwrap();
     compiler.compile(&mut world, options, Vec::new());
-    compiler.on_use_block(&mut world, START_BUTTON);
+    compiler.on_use_block(&mut world, START_BUTTON).unwrap();
     (world, compiler)
 }
 
@@ -42,7 +41,24 @@ fn chungus_mandelbrot(c: &mut Criterion) {
     let (mut world, mut compiler) = init_compiler();
 
     c.bench_function("chungus-mandelbrot-tick", |b| {
-        b.iter(|| compiler.tick(&mut world));
+        b.iter(|| compiler.tick(&mut world).unwrap());
+    });
+}
+
+fn chungus_compile(c: &mut Criterion) {
+    let mut world = load_world("./benches/chungus_mandelbrot_plot");
+    let options = CompilerOptions::parse("-O").unwrap();
+
+    let mut compiler: Compiler = Default::default();
+    let report = compiler.compile(&mut world, options.clone(), Vec::new());
+    compiler.reset(&mut world);
+    println!("{}", report);
+
+    c.bench_function("chungus-compile", |b| {
+        b.iter(|| {
+            let mut compiler: Compiler = Default::default();
+            compiler.compile(&mut world, options.clone(), Vec::new());
+        });
     });
 }
 
@@ -56,11 +72,18 @@ fn mandelbrot_full(_c: &mut Criterion) {
     println!("Running full chungus mandelbrot, this can take a while!");
     let (mut world, mut compiler) = init_compiler();
     let start = Instant::now();
-    for _ in 0..12411975 {
-        compiler.tick(&mut world);
-    }
+    let stats = compiler.run_ticks(&mut world, 12411975).unwrap();
     println!("Mandelbrot benchmark completed in {:?}", start.elapsed());
+    println!(
+        "Simulated {} ticks and skipped {} empty ticks",
+        stats.simulated, stats.skipped
+    );
 }
 
-criterion_group!(chungus, chungus_mandelbrot, mandelbrot_full);
+criterion_group!(
+    chungus,
+    chungus_mandelbrot,
+    chungus_compile,
+    mandelbrot_full
+);
 criterion_main!(chungus);
@@ -0,0 +1,80 @@
+//! Compares ticking long repeater chains folded into delay lines against ticking every repeater as
+//! its own node. Marking the repeaters as observable keeps them from being folded.
+
+use criterion::*;
+use mchprs_blocks::{BlockDirection, BlockPos};
+use mchprs_core::blocks::{Block, Lever, LeverFace, RedstoneRepeater};
+use mchprs_core::plot::{PlotWorld, PLOT_WIDTH};
+use mchprs_core::redpiler::{Compiler, CompilerOptions};
+use mchprs_core::world::storage::Chunk;
+use mchprs_core::world::World;
+
+const LINES: i32 = 32;
+const LINE_LENGTH: i32 = 120;
+
+fn lever_pos(line: i32) -> BlockPos {
+    BlockPos::new(0, 1, line * 2)
+}
+
+/// Builds lines of a lever, repeaters with delays cycling from 1 to 4 and a lamp
+fn build_world() -> PlotWorld {
+    let chunks = (0..PLOT_WIDTH * PLOT_WIDTH)
+        .map(|i| Chunk::empty(i / PLOT_WIDTH, i % PLOT_WIDTH))
+        .collect();
+    let mut world = PlotWorld {
+        x: 0,
+        z: 0,
+        chunks,
+        to_be_ticked: Vec::new(),
+    };
+    for line in 0..LINES {
+        let lever = Lever::new(LeverFace::Floor, Default::default(), false);
+        world.set_block(lever_pos(line), Block::Lever { lever });
+        for x in 1..=LINE_LENGTH {
+            let delay = (x - 1) as u8 % 4 + 1;
+            let repeater = RedstoneRepeater::new(delay, BlockDirection::West, false, false);
+            world.set_block(
+                BlockPos::new(x, 1, line * 2),
+                Block::RedstoneRepeater { repeater },
+            );
+        }
+        let lamp_pos = BlockPos::new(LINE_LENGTH + 1, 1, line * 2);
+        world.set_block(lamp_pos, Block::RedstoneLamp { lit: false });
+    }
+    world
+}
+
+fn delay_line_tick(c: &mut Criterion, name: &str, fold: bool) {
+    let mut world = build_world();
+    let mut compiler: Compiler = Default::default();
+    if !fold {
+        for line in 0..LINES {
+            for x in 1..=LINE_LENGTH {
+                compiler.add_observable(BlockPos::new(x, 1, line * 2));
+            }
+        }
+    }
+    let options = CompilerOptions::parse("-O").unwrap();
+    let report = compiler.compile(&mut world, options, Vec::new());
+    println!("{}", report);
+
+    c.bench_function(name, |b| {
+        b.iter(|| {
+            for line in 0..LINES {
+                compiler.on_use_block(&mut world, lever_pos(line)).unwrap();
+            }
+            compiler.run_ticks(&mut world, 8).unwrap();
+        })
+    });
+}
+
+fn delay_line_folded(c: &mut Criterion) {
+    delay_line_tick(c, "delay-line-folded", true);
+}
+
+fn delay_line_unfolded(c: &mut Criterion) {
+    delay_line_tick(c, "delay-line-unfolded", false);
+}
+
+criterion_group!(delay_line, delay_line_folded, delay_line_unfolded);
+criterion_main!(delay_line);
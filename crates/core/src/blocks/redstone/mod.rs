@@ -6,6 +6,7 @@ use crate::blocks::{
 use crate::world::World;
 use mchprs_world::TickPriority;
 pub use redstone_wire::{RedstoneWire, RedstoneWireSide};
+use serde::{Deserialize, Serialize};
 use std::cmp;
 use std::str::FromStr;
 
@@ -182,12 +183,7 @@ impl Default for RedstoneRepeater {
 }
 
 impl RedstoneRepeater {
-    pub fn new(
-        delay: u8,
-        facing: BlockDirection,
-        locked: bool,
-        powered: bool,
-    ) -> RedstoneRepeater {
+    pub fn new(delay: u8, facing: BlockDirection, locked: bool, powered: bool) -> RedstoneRepeater {
         RedstoneRepeater {
             delay,
             facing,
@@ -288,7 +284,7 @@ impl RedstoneRepeater {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ComparatorMode {
     Compare,
     Subtract,
@@ -5,8 +5,9 @@ mod monitor;
 use crate::blocks::Block;
 use crate::world::storage::Chunk;
 use crate::world::World;
-use mchprs_blocks::BlockPos;
 use mchprs_blocks::block_entities::BlockEntity;
+use mchprs_blocks::BlockPos;
+use mchprs_network::packets::clientbound::CMultiBlockChange;
 use mchprs_world::{TickEntry, TickPriority};
 use std::time::Duration;
 
@@ -21,6 +22,7 @@ pub const NUM_CHUNKS: usize = PLOT_WIDTH.pow(2) as usize;
 
 pub const WORLD_SEND_RATE: Duration = Duration::from_millis(15);
 
+#[derive(Clone)]
 pub struct PlotWorld {
     pub x: i32,
     pub z: i32,
@@ -50,6 +52,29 @@ impl PlotWorld {
         let second_pos = BlockPos::new((self.x + 1) * W - 1, 255, (self.z + 1) * W - 1);
         (first_pos, second_pos)
     }
+
+    /// Runs a single redstone tick without redpiler, ticking every block whose scheduled tick is
+    /// due in order of priority
+    pub fn tick(&mut self) {
+        self.to_be_ticked
+            .sort_by_key(|e| (e.ticks_left, e.tick_priority));
+        for pending in &mut self.to_be_ticked {
+            pending.ticks_left = pending.ticks_left.saturating_sub(1);
+        }
+        while self.to_be_ticked.first().is_some_and(|e| e.ticks_left == 0) {
+            let entry = self.to_be_ticked.remove(0);
+            self.get_block(entry.pos).tick(self, entry.pos);
+        }
+    }
+
+    /// Returns multi block change packets for every block changed since the last call, these
+    /// should be sent to everyone viewing the plot.
+    pub fn drain_multi_block_changes(&mut self) -> Vec<CMultiBlockChange> {
+        self.chunks
+            .iter_mut()
+            .flat_map(Chunk::drain_multi_block_changes)
+            .collect()
+    }
 }
 
 impl World for PlotWorld {
@@ -1,9 +1,15 @@
 //! The direct backend does not do code generation and operates on the `CompileNode` graph directly
 
-use super::JITBackend;
+use super::{
+    BackendError, FinalGraphStats, InspectedLink, JITBackend, NodeInspection, NodeSnapshot,
+    TickRunStats,
+};
 use crate::blocks::{Block, ComparatorMode};
 use crate::plot::PlotWorld;
-use crate::redpiler::compile_graph::{CompileGraph, LinkType, NodeIdx};
+use crate::redpiler::compile_graph::{
+    CompileGraph, CompileNode, LinkType, NodeIdx, NodeType as CNodeType,
+    StageSource as CStageSource,
+};
 use crate::redpiler::{block_powered_mut, bool_to_ss};
 use crate::world::World;
 use mchprs_blocks::block_entities::BlockEntity;
@@ -13,17 +19,9 @@ use nodes::{NodeId, Nodes};
 use petgraph::visit::EdgeRef;
 use petgraph::Direction;
 use smallvec::SmallVec;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::{fmt, mem};
-use tracing::{debug, trace, warn};
-
-#[derive(Debug, Default)]
-struct FinalGraphStats {
-    update_link_count: usize,
-    side_link_count: usize,
-    default_link_count: usize,
-    nodes_bytes: usize,
-}
+use tracing::{debug, info, trace, trace_span, warn};
 
 mod nodes {
     use super::Node;
@@ -104,7 +102,10 @@ enum NodeType {
     /// A non-locking repeater
     SimpleRepeater(u8),
     Torch,
+    /// A torch with multiple inputs
+    Not,
     Comparator(ComparatorMode),
+    BinaryComparator,
     Lamp,
     Button,
     Lever,
@@ -112,9 +113,34 @@ enum NodeType {
     Trapdoor,
     Wire,
     Constant,
+    /// Index into `DirectBackend::gates`
+    Gate(usize),
+    /// Index into `DirectBackend::delay_lines`
+    DelayLine(usize),
 }
 
 impl NodeType {
+    fn to_compile_type(self, gates: &[LogicGate], delay_lines: &[DelayLine]) -> CNodeType {
+        match self {
+            NodeType::Repeater(delay) | NodeType::SimpleRepeater(delay) => {
+                CNodeType::Repeater(delay)
+            }
+            NodeType::Torch => CNodeType::Torch,
+            NodeType::Not => CNodeType::Not { delay: 1 },
+            NodeType::Comparator(mode) => CNodeType::Comparator(mode),
+            NodeType::BinaryComparator => CNodeType::BinaryComparator,
+            NodeType::Lamp => CNodeType::Lamp,
+            NodeType::Button => CNodeType::Button,
+            NodeType::Lever => CNodeType::Lever,
+            NodeType::PressurePlate => CNodeType::PressurePlate,
+            NodeType::Trapdoor => CNodeType::Trapdoor,
+            NodeType::Wire => CNodeType::Wire,
+            NodeType::Constant => CNodeType::Constant,
+            NodeType::Gate(gate) => gates[gate].ty,
+            NodeType::DelayLine(line) => delay_lines[line].ty,
+        }
+    }
+
     fn is_io_block(self) -> bool {
         matches!(
             self,
@@ -151,6 +177,8 @@ impl Node {
         node_idx: NodeIdx,
         nodes_len: usize,
         nodes_map: &HashMap<NodeIdx, usize>,
+        gates: &mut Vec<LogicGate>,
+        delay_lines: &mut Vec<DelayLine>,
         stats: &mut FinalGraphStats,
     ) -> Self {
         let node = &graph[node_idx];
@@ -176,7 +204,6 @@ impl Node {
         stats.default_link_count += default_inputs.len();
         stats.side_link_count += side_inputs.len();
 
-        use crate::redpiler::compile_graph::NodeType as CNodeType;
         let updates: SmallVec<[NodeId; 2]> = if node.ty != CNodeType::Constant {
             graph
                 .neighbors_directed(node_idx, Direction::Outgoing)
@@ -201,7 +228,9 @@ impl Node {
                 }
             }
             CNodeType::Torch => NodeType::Torch,
+            CNodeType::Not { .. } => NodeType::Not,
             CNodeType::Comparator(mode) => NodeType::Comparator(mode),
+            CNodeType::BinaryComparator => NodeType::BinaryComparator,
             CNodeType::Lamp => NodeType::Lamp,
             CNodeType::Button => NodeType::Button,
             CNodeType::Lever => NodeType::Lever,
@@ -209,6 +238,17 @@ impl Node {
             CNodeType::Trapdoor => NodeType::Trapdoor,
             CNodeType::Wire => NodeType::Wire,
             CNodeType::Constant => NodeType::Constant,
+            CNodeType::DelayLine { .. } => {
+                delay_lines.push(DelayLine::new(node));
+                NodeType::DelayLine(delay_lines.len() - 1)
+            }
+            CNodeType::Or { .. }
+            | CNodeType::And { .. }
+            | CNodeType::Xor { .. }
+            | CNodeType::Latch { .. } => {
+                gates.push(LogicGate::new(node, nodes_len, nodes_map));
+                NodeType::Gate(gates.len() - 1)
+            }
         };
 
         Node {
@@ -227,6 +267,528 @@ impl Node {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StageKind {
+    Repeater(u8),
+    Torch,
+}
+
+/// An input torch of an `Or` or `And` gate, one of the comparators of a `Xor` gate or a locking
+/// repeater of a `Latch`. These read straight from the inputs of the gate node.
+#[derive(Debug, Clone)]
+struct GatePart {
+    default_inputs: SmallVec<[DirectLink; 1]>,
+    side_inputs: SmallVec<[DirectLink; 1]>,
+    /// The weight of the link to the output torch of an `Or` or `And` gate or to the locked
+    /// repeater of a `Latch`
+    weight: u8,
+    /// Only used by the repeaters of a `Latch`
+    delay: u8,
+    facing_diode: bool,
+
+    /// Powered or lit
+    powered: bool,
+    output_power: u8,
+    pending_tick: bool,
+    changed: bool,
+    /// `None` if the part uses the block of the node itself
+    block: Option<(BlockPos, Block)>,
+}
+
+/// The delay and priority of a tick
+type TickTiming = (u64, TickPriority);
+
+#[derive(Debug, Clone)]
+enum GateTick {
+    /// The parts which were updated together
+    Parts(SmallVec<[usize; 2]>),
+    /// The output torch of an `Or` or `And` gate or the locked repeater of a `Latch`
+    Output,
+}
+
+/// A torch OR or AND gate, a comparator XOR gate or a repeater-locked latch. The node itself is
+/// the output torch of an `Or` or `And` gate and the locked repeater of a `Latch`, the output of a
+/// `Xor` gate is the strongest output of its two comparators.
+///
+/// Parts which are updated together and tick with the same delay and priority share a single
+/// tick, in which they tick in the order they would have been updated in.
+#[derive(Debug, Clone)]
+struct LogicGate {
+    ty: CNodeType,
+    /// The priority of every tick of an `Or`, `And` or `Xor` gate
+    priority: TickPriority,
+    parts: Box<[GatePart]>,
+    output_pending: bool,
+    /// Pending ticks in the order the `TickScheduler` runs them: by the tick they are due on,
+    /// their priority and the order they were scheduled in
+    ticks: BTreeMap<(u64, TickPriority, u64), GateTick>,
+    scheduled: u64,
+}
+
+impl LogicGate {
+    fn new(node: &CompileNode, nodes_len: usize, nodes_map: &HashMap<NodeIdx, usize>) -> LogicGate {
+        let (parts, priority) = match node.ty {
+            // The last stage is the output torch or the locked repeater
+            CNodeType::Or { .. } | CNodeType::And { .. } | CNodeType::Latch { .. } => {
+                (&node.stages[..node.stages.len() - 1], TickPriority::Normal)
+            }
+            CNodeType::Xor { .. } if node.stages[0].facing_diode => {
+                (&node.stages[..], TickPriority::High)
+            }
+            CNodeType::Xor { .. } => (&node.stages[..], TickPriority::Normal),
+            ty => panic!("{:?} is not a logic gate", ty),
+        };
+        let weight = |part: usize| {
+            let output = &node.stages[node.stages.len() - 1];
+            output
+                .inputs
+                .iter()
+                .find(|input| input.source == CStageSource::Stage(part))
+                .map_or(0, |input| input.ss)
+        };
+        let parts = parts
+            .iter()
+            .enumerate()
+            .map(|(i, stage)| {
+                let mut default_inputs = SmallVec::new();
+                let mut side_inputs = SmallVec::new();
+                for input in &stage.inputs {
+                    let CStageSource::Input(source) = input.source else {
+                        panic!("{:?} is not an input of the gate", input.source);
+                    };
+                    let idx = nodes_map[&source];
+                    assert!(idx < nodes_len);
+                    let link = DirectLink {
+                        // Safety: bounds checked
+                        to: unsafe { NodeId::from_index(idx) },
+                        weight: input.ss,
+                    };
+                    match input.ty {
+                        LinkType::Default => default_inputs.push(link),
+                        LinkType::Side => side_inputs.push(link),
+                    }
+                }
+                let delay = match stage.ty {
+                    CNodeType::Repeater(delay) => delay,
+                    _ => 1,
+                };
+                GatePart {
+                    default_inputs,
+                    side_inputs,
+                    weight: weight(i),
+                    delay,
+                    facing_diode: stage.facing_diode,
+                    powered: stage.state.powered,
+                    output_power: stage.state.output_strength,
+                    pending_tick: false,
+                    changed: false,
+                    block: stage.block.map(|(pos, id)| (pos, Block::from_id(id))),
+                }
+            })
+            .collect();
+
+        LogicGate {
+            ty: node.ty,
+            priority,
+            parts,
+            output_pending: false,
+            ticks: BTreeMap::new(),
+            scheduled: 0,
+        }
+    }
+
+    fn schedule(
+        &mut self,
+        scheduler: &mut TickScheduler,
+        node_id: NodeId,
+        (delay, priority): TickTiming,
+        tick: GateTick,
+    ) {
+        scheduler.schedule_tick(node_id, delay as usize, priority);
+        let due = scheduler.current_tick + delay;
+        self.ticks.insert((due, priority, self.scheduled), tick);
+        self.scheduled += 1;
+    }
+
+    /// Removes the next pending tick if it is due now
+    fn take_due_tick(&mut self, now: u64) -> Option<GateTick> {
+        let entry = self.ticks.first_entry()?;
+        if entry.key().0 != now {
+            return None;
+        }
+        Some(entry.remove())
+    }
+
+    /// The state the part would change to if it ticked now
+    fn part_target(&self, part: &GatePart, nodes: &Nodes) -> u8 {
+        let max = |links: &[DirectLink]| {
+            links
+                .iter()
+                .map(|&link| link_strength(link, nodes))
+                .max()
+                .unwrap_or(0)
+        };
+        match self.ty {
+            CNodeType::Or { .. } | CNodeType::And { .. } => {
+                bool_to_ss(max(&part.default_inputs) == 0)
+            }
+            CNodeType::Latch { .. } => bool_to_ss(max(&part.default_inputs) > 0),
+            // The comparators of a `Xor` gate are always in subtract mode
+            _ => calculate_comparator_output(
+                ComparatorMode::Subtract,
+                max(&part.default_inputs),
+                max(&part.side_inputs),
+            ),
+        }
+    }
+
+    /// The delay and priority of the tick a part needs if its input doesn't match its state
+    fn part_tick(&self, part: &GatePart, nodes: &Nodes) -> Option<TickTiming> {
+        let target = self.part_target(part, nodes);
+        if target == part.output_power {
+            return None;
+        }
+        Some(match self.ty {
+            CNodeType::Latch { .. } => {
+                let kind = StageKind::Repeater(part.delay);
+                let priority = stage_tick_priority(kind, part.facing_diode, target > 0);
+                (part.delay as u64, priority)
+            }
+            _ => (1, self.priority),
+        })
+    }
+
+    /// Schedules the ticks of every part whose input doesn't match its state
+    fn update(&mut self, scheduler: &mut TickScheduler, node_id: NodeId, nodes: &Nodes) {
+        let mut ticks: SmallVec<[(TickTiming, SmallVec<[usize; 2]>); 1]> = SmallVec::new();
+        for (i, part) in self.parts.iter().enumerate() {
+            if part.pending_tick {
+                continue;
+            }
+            let Some(key) = self.part_tick(part, nodes) else {
+                continue;
+            };
+            match ticks.iter_mut().find(|(tick_key, _)| *tick_key == key) {
+                Some((_, parts)) => parts.push(i),
+                None => ticks.push((key, SmallVec::from_elem(i, 1))),
+            }
+        }
+        for (key, parts) in ticks {
+            for &i in &parts {
+                self.parts[i].pending_tick = true;
+            }
+            self.schedule(scheduler, node_id, key, GateTick::Parts(parts));
+        }
+    }
+
+    /// Runs the tick of a part, returning true if it changed
+    fn tick_part(&mut self, idx: usize, nodes: &Nodes) -> bool {
+        let part = &self.parts[idx];
+        let target = match self.ty {
+            // A repeater stays powered for its delay even if its input turned off again
+            CNodeType::Latch { .. } if !part.powered => 15,
+            _ => self.part_target(part, nodes),
+        };
+        let part = &mut self.parts[idx];
+        part.pending_tick = false;
+        if target == part.output_power {
+            return false;
+        }
+        part.powered = target > 0;
+        part.output_power = target;
+        part.changed = true;
+        true
+    }
+
+    /// True if any of the parts powers the output torch of an `Or` or `And` gate or locks the
+    /// repeater of a `Latch`
+    fn parts_powered(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|part| part.output_power.saturating_sub(part.weight) > 0)
+    }
+
+    /// Schedules a tick for the output torch of an `Or` or `And` gate if it doesn't match its
+    /// input
+    fn update_output(&mut self, scheduler: &mut TickScheduler, node_id: NodeId, lit: bool) {
+        if !self.output_pending && lit == self.parts_powered() {
+            self.output_pending = true;
+            self.schedule(scheduler, node_id, (1, self.priority), GateTick::Output);
+        }
+    }
+
+    /// Updates the locked repeater of a `Latch`, which is the node itself
+    fn update_latch(&mut self, scheduler: &mut TickScheduler, node_id: NodeId, nodes: &mut Nodes) {
+        let CNodeType::Latch { delay } = self.ty else {
+            return;
+        };
+        let locked = self.parts_powered();
+        let should_be_powered = get_bool_input(&nodes[node_id], nodes);
+        let node = &mut nodes[node_id];
+        if node.locked != locked {
+            set_node_locked(node, locked);
+        }
+        if !node.locked && !self.output_pending && should_be_powered != node.powered {
+            let kind = StageKind::Repeater(delay);
+            let priority = stage_tick_priority(kind, node.facing_diode, should_be_powered);
+            self.output_pending = true;
+            self.schedule(
+                scheduler,
+                node_id,
+                (delay as u64, priority),
+                GateTick::Output,
+            );
+        }
+    }
+
+    /// The strongest output of the comparators of a `Xor` gate
+    fn xor_output_power(&self) -> u8 {
+        self.parts
+            .iter()
+            .map(|part| part.output_power)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn has_pending_ticks(&self) -> bool {
+        !self.ticks.is_empty()
+    }
+}
+
+/// The priority of the tick in which a repeater or torch stage changes to `powered`
+fn stage_tick_priority(kind: StageKind, facing_diode: bool, powered: bool) -> TickPriority {
+    match kind {
+        StageKind::Repeater(_) if facing_diode => TickPriority::Highest,
+        StageKind::Repeater(_) if powered => TickPriority::High,
+        StageKind::Repeater(_) => TickPriority::Higher,
+        _ => TickPriority::Normal,
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LineStage {
+    kind: StageKind,
+    facing_diode: bool,
+    /// The number of ticks this stage changes after the first stage
+    offset: u64,
+    /// True if this stage is inverted compared to the first stage
+    inverted: bool,
+    /// Powered or lit, as last written to the world
+    powered: bool,
+    /// `None` if the stage uses the block of the node itself
+    block: Option<(BlockPos, Block)>,
+}
+
+impl LineStage {
+    fn tick_priority(&self, first_powered: bool) -> TickPriority {
+        stage_tick_priority(self.kind, self.facing_diode, first_powered != self.inverted)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LineEvent {
+    /// A tick of the first stage
+    First,
+    /// The second to last stage changes, which schedules the tick of the last stage. This
+    /// carries the new state of the first stage.
+    Hop(bool),
+    /// The last stage changes, which is the output of the delay line. This carries the new state
+    /// of the first stage.
+    Output(bool),
+}
+
+/// A chain of repeaters and torches. Only the first stage is simulated, the `DelayLineFold` pass
+/// guarantees that the other stages just delay its output. Each change of the first stage is
+/// queued as a pulse which reaches the output after the delay of the other stages.
+#[derive(Debug, Clone)]
+struct DelayLine {
+    ty: CNodeType,
+    /// The first stage is at index 0 and the output stage is the last one
+    stages: Box<[LineStage]>,
+    first_powered: bool,
+    first_pending: bool,
+    /// Changes of the first stage that have not reached the output yet, along with the tick
+    /// they happened in
+    pulses: VecDeque<(u64, bool)>,
+    /// The state of the first stage before the oldest pulse
+    settled: bool,
+    /// Pending ticks in the order the `TickScheduler` runs them: by the tick they are due on,
+    /// their priority and the order they were scheduled in
+    events: BTreeMap<(u64, TickPriority, u64), LineEvent>,
+    scheduled: u64,
+}
+
+impl DelayLine {
+    fn new(node: &CompileNode) -> DelayLine {
+        let CNodeType::DelayLine { delay, inverted } = node.ty else {
+            panic!("{:?} is not a delay line", node.ty);
+        };
+        let mut offset = 0;
+        let mut torches = 0;
+        let stages: Box<[LineStage]> = node
+            .stages
+            .iter()
+            .enumerate()
+            .map(|(i, stage)| {
+                let kind = match stage.ty {
+                    CNodeType::Repeater(delay) => StageKind::Repeater(delay),
+                    CNodeType::Torch => StageKind::Torch,
+                    ty => panic!("{:?} cannot be a delay line stage", ty),
+                };
+                if i > 0 {
+                    offset += match kind {
+                        StageKind::Repeater(delay) => delay as u64,
+                        _ => 1,
+                    };
+                    torches += (kind == StageKind::Torch) as u32;
+                }
+                LineStage {
+                    kind,
+                    facing_diode: stage.facing_diode,
+                    offset,
+                    inverted: torches & 1 == 1,
+                    powered: stage.state.powered,
+                    block: stage.block.map(|(pos, id)| (pos, Block::from_id(id))),
+                }
+            })
+            .collect();
+
+        let first = &stages[0];
+        let first_delay = match first.kind {
+            StageKind::Repeater(delay) => delay as u64,
+            _ => 1,
+        };
+        let output = &stages[stages.len() - 1];
+        assert_eq!(output.offset, delay as u64 - first_delay);
+        assert_eq!(
+            output.inverted,
+            inverted != (first.kind == StageKind::Torch)
+        );
+        DelayLine {
+            ty: node.ty,
+            first_powered: first.powered,
+            settled: first.powered,
+            stages,
+            first_pending: false,
+            pulses: VecDeque::new(),
+            events: BTreeMap::new(),
+            scheduled: 0,
+        }
+    }
+
+    fn schedule(
+        &mut self,
+        scheduler: &mut TickScheduler,
+        node_id: NodeId,
+        delay: u64,
+        priority: TickPriority,
+        event: LineEvent,
+    ) {
+        scheduler.schedule_tick(node_id, delay as usize, priority);
+        let due = scheduler.current_tick + delay;
+        self.events.insert((due, priority, self.scheduled), event);
+        self.scheduled += 1;
+    }
+
+    /// Removes the next pending tick if it is due now
+    fn take_due_event(&mut self, now: u64) -> Option<LineEvent> {
+        let entry = self.events.first_entry()?;
+        if entry.key().0 != now {
+            return None;
+        }
+        Some(entry.remove())
+    }
+
+    /// Schedules a tick for the first stage if its input doesn't match its state
+    fn update_first(&mut self, scheduler: &mut TickScheduler, node_id: NodeId, nodes: &Nodes) {
+        if self.first_pending {
+            return;
+        }
+        let should_be_powered = get_bool_input(&nodes[node_id], nodes);
+        let first = &self.stages[0];
+        let delay = match first.kind {
+            StageKind::Repeater(delay) if self.first_powered != should_be_powered => delay,
+            StageKind::Torch if self.first_powered == should_be_powered => 1,
+            _ => return,
+        };
+        let priority = stage_tick_priority(first.kind, first.facing_diode, !self.first_powered);
+        self.first_pending = true;
+        self.schedule(scheduler, node_id, delay as u64, priority, LineEvent::First);
+    }
+
+    /// Runs the tick of the first stage, returning its new state if it changed
+    fn tick_first(&mut self, node_id: NodeId, nodes: &Nodes) -> Option<bool> {
+        self.first_pending = false;
+        let should_be_powered = get_bool_input(&nodes[node_id], nodes);
+        let powered = self.first_powered;
+        let powered = match self.stages[0].kind {
+            StageKind::Repeater(_) if powered && !should_be_powered => false,
+            StageKind::Repeater(_) if !powered => true,
+            StageKind::Torch if powered && should_be_powered => false,
+            StageKind::Torch if !powered && !should_be_powered => true,
+            _ => return None,
+        };
+        self.first_powered = powered;
+        Some(powered)
+    }
+
+    /// Sends a change of the first stage down the line. The tick of the last stage is scheduled
+    /// when the stage before it changes, just like it would be without folding.
+    fn send(&mut self, scheduler: &mut TickScheduler, node_id: NodeId, powered: bool) {
+        self.pulses.push_back((scheduler.current_tick, powered));
+        let [.., before_output, output] = &*self.stages else {
+            unreachable!("delay lines have at least two stages");
+        };
+        let (hop, output_delay) = (before_output.offset, output.offset - before_output.offset);
+        if hop == 0 {
+            let priority = output.tick_priority(powered);
+            let event = LineEvent::Output(powered);
+            self.schedule(scheduler, node_id, output_delay, priority, event);
+        } else {
+            let priority = before_output.tick_priority(powered);
+            self.schedule(scheduler, node_id, hop, priority, LineEvent::Hop(powered));
+        }
+    }
+
+    /// Runs a hop, scheduling the tick of the last stage
+    fn hop(&mut self, scheduler: &mut TickScheduler, node_id: NodeId, powered: bool) {
+        let [.., before_output, output] = &*self.stages else {
+            unreachable!("delay lines have at least two stages");
+        };
+        let priority = output.tick_priority(powered);
+        let delay = output.offset - before_output.offset;
+        self.schedule(
+            scheduler,
+            node_id,
+            delay,
+            priority,
+            LineEvent::Output(powered),
+        );
+    }
+
+    /// Runs the tick of the last stage, returning the new output state
+    fn output(&mut self, powered: bool) -> bool {
+        self.pulses.pop_front();
+        self.settled = powered;
+        powered != self.stages[self.stages.len() - 1].inverted
+    }
+
+    /// The state of a stage once every tick before `now` has run
+    fn stage_powered(&self, stage: &LineStage, now: u64) -> bool {
+        let first_powered = self
+            .pulses
+            .iter()
+            .rev()
+            .find(|&&(tick, _)| tick + stage.offset < now)
+            .map_or(self.settled, |&(_, powered)| powered);
+        first_powered != stage.inverted
+    }
+
+    fn has_pending_ticks(&self) -> bool {
+        self.first_pending || !self.pulses.is_empty()
+    }
+}
+
 #[derive(Default, Clone)]
 struct Queues([Vec<NodeId>; TickScheduler::NUM_PRIORITIES]);
 
@@ -241,15 +803,22 @@ impl Queues {
 #[derive(Default)]
 struct TickScheduler {
     queues_deque: VecDeque<Queues>,
+    /// The number of ticks that have ended. A tick scheduled with a delay of `n` will run when
+    /// this reaches `current_tick + n`.
+    current_tick: u64,
 }
 
 impl TickScheduler {
     const NUM_PRIORITIES: usize = 4;
 
-    fn reset(&mut self, plot: &mut PlotWorld, blocks: &[Option<(BlockPos, Block)>]) {
+    fn reset(&mut self, plot: &mut PlotWorld, nodes: &Nodes, blocks: &[Option<(BlockPos, Block)>]) {
         for (delay, queues) in self.queues_deque.iter().enumerate() {
             for (entries, priority) in queues.0.iter().zip(Self::priorities()) {
                 for node in entries {
+                    // Logic gates and delay lines keep track of their own ticks
+                    if matches!(nodes[*node].ty, NodeType::Gate(_) | NodeType::DelayLine(_)) {
+                        continue;
+                    }
                     let Some((pos, _)) = blocks[node.index()] else {
                         warn!("Cannot schedule tick for node {:?} because block information is missing", node);
                         continue;
@@ -278,6 +847,7 @@ impl TickScheduler {
 
     fn end_tick(&mut self, mut queues: Queues) {
         self.queues_deque.pop_front();
+        self.current_tick += 1;
 
         for queue in &mut queues.0 {
             queue.clear();
@@ -285,6 +855,35 @@ impl TickScheduler {
         self.queues_deque.push_back(queues);
     }
 
+    /// Returns the number of ticks before the next tick that has anything scheduled, or `None`
+    /// if nothing is scheduled at all.
+    fn empty_ticks(&self) -> Option<usize> {
+        self.queues_deque
+            .iter()
+            .position(|queues| queues.0.iter().any(|queue| !queue.is_empty()))
+    }
+
+    /// Advances the scheduler by `ticks` ticks, which must not have anything scheduled.
+    /// This is the same as ending that many empty ticks.
+    fn skip_ticks(&mut self, ticks: u64) {
+        let len = self.queues_deque.len() as u64;
+        if len > 0 {
+            self.queues_deque.rotate_left((ticks % len) as usize);
+        }
+        self.current_tick += ticks;
+    }
+
+    /// Returns the number of ticks waiting in the scheduler for each priority
+    fn pending_per_priority(&self) -> [usize; Self::NUM_PRIORITIES] {
+        let mut pending = [0; Self::NUM_PRIORITIES];
+        for queues in &self.queues_deque {
+            for (count, queue) in pending.iter_mut().zip(&queues.0) {
+                *count += queue.len();
+            }
+        }
+        pending
+    }
+
     fn priorities() -> [TickPriority; Self::NUM_PRIORITIES] {
         [
             TickPriority::Highest,
@@ -304,44 +903,398 @@ impl TickScheduler {
     }
 }
 
+fn node_snapshot(node: &Node) -> NodeSnapshot {
+    NodeSnapshot {
+        powered: node.powered,
+        locked: node.locked,
+        output_power: node.output_power,
+    }
+}
+
+#[derive(Debug, Default)]
+struct TickStats {
+    nodes_ticked: usize,
+    nodes_updated: usize,
+    state_changes: usize,
+}
+
+/// Opt-in tracing state. When this is present, every tick is run inside a `redpiler_tick` span
+/// and reports how much work was done.
+#[derive(Debug, Default)]
+struct TickTrace {
+    tick: u64,
+    stats: TickStats,
+    /// Stats of the last tick that completed
+    last_stats: TickStats,
+    /// Positions being watched along with the state they were last seen in
+    watched: HashMap<BlockPos, Option<NodeSnapshot>>,
+}
+
 #[derive(Default)]
 pub struct DirectBackend {
     nodes: Nodes,
     blocks: Vec<Option<(BlockPos, Block)>>,
     pos_map: HashMap<BlockPos, NodeId>,
+    gates: Vec<LogicGate>,
+    delay_lines: Vec<DelayLine>,
     scheduler: TickScheduler,
+    trace: Option<TickTrace>,
 }
 
 impl DirectBackend {
+    fn node_at(&self, pos: BlockPos) -> Result<NodeId, BackendError> {
+        self.pos_map
+            .get(&pos)
+            .copied()
+            .ok_or(BackendError::NoNode(pos))
+    }
+
     fn schedule_tick(&mut self, node_id: NodeId, delay: usize, priority: TickPriority) {
         self.scheduler.schedule_tick(node_id, delay, priority);
     }
 
     fn set_node(&mut self, node_id: NodeId, powered: bool, new_power: u8) {
         let node = &mut self.nodes[node_id];
+        let before = node_snapshot(node);
         node.changed = true;
         node.powered = powered;
         node.output_power = new_power;
-        for i in 0..node.updates.len() {
+        for i in 0..self.nodes[node_id].updates.len() {
             let update = self.nodes[node_id].updates[i];
-            update_node(&mut self.scheduler, &mut self.nodes, update);
+            self.update_node(update);
         }
-        update_node(&mut self.scheduler, &mut self.nodes, node_id);
+        // The change is counted together with anything the update changes about the node
+        self.update_node_since(node_id, before);
     }
-}
 
-impl JITBackend for DirectBackend {
-    fn inspect(&mut self, pos: BlockPos) {
-        let Some(node_id) = self.pos_map.get(&pos) else {
-            println!("could not find node at pos {}", pos);
+    fn update_node(&mut self, node_id: NodeId) {
+        let before = node_snapshot(&self.nodes[node_id]);
+        self.update_node_since(node_id, before);
+    }
+
+    /// Updates the node and counts a state change if it no longer matches `before`
+    fn update_node_since(&mut self, node_id: NodeId, before: NodeSnapshot) {
+        update_node(
+            &mut self.scheduler,
+            &mut self.nodes,
+            &mut self.gates,
+            &mut self.delay_lines,
+            node_id,
+        );
+        if let Some(trace) = &mut self.trace {
+            trace.stats.nodes_updated += 1;
+            if node_snapshot(&self.nodes[node_id]) != before {
+                trace.stats.state_changes += 1;
+            }
+        }
+    }
+
+    fn tick_gate(&mut self, node_id: NodeId, gate_idx: usize) {
+        let now = self.scheduler.current_tick;
+        let gate = &mut self.gates[gate_idx];
+        let Some(tick) = gate.take_due_tick(now) else {
+            warn!("Logic gate {:?} was ticked without a pending tick", node_id);
+            return;
+        };
+        match tick {
+            // The parts would have ticked right after each other, so every change is passed on
+            // before the next part ticks
+            GateTick::Parts(parts) => {
+                for part in parts {
+                    self.tick_gate_part(node_id, gate_idx, part);
+                }
+            }
+            GateTick::Output if matches!(gate.ty, CNodeType::Latch { .. }) => {
+                gate.output_pending = false;
+                let node = &self.nodes[node_id];
+                if node.locked {
+                    return;
+                }
+                let should_be_powered = get_bool_input(node, &self.nodes);
+                if node.powered && !should_be_powered {
+                    self.set_node(node_id, false, 0);
+                } else if !node.powered {
+                    self.set_node(node_id, true, 15);
+                }
+            }
+            GateTick::Output => {
+                gate.output_pending = false;
+                let should_be_off = gate.parts_powered();
+                let lit = self.nodes[node_id].powered;
+                if lit && should_be_off {
+                    self.set_node(node_id, false, 0);
+                } else if !lit && !should_be_off {
+                    self.set_node(node_id, true, 15);
+                }
+            }
+        }
+    }
+
+    fn tick_gate_part(&mut self, node_id: NodeId, gate_idx: usize, part: usize) {
+        let gate = &mut self.gates[gate_idx];
+        if !gate.tick_part(part, &self.nodes) {
+            return;
+        }
+        if let Some(trace) = &mut self.trace {
+            trace.stats.state_changes += 1;
+        }
+        let node = &mut self.nodes[node_id];
+        match gate.ty {
+            CNodeType::Or { .. } | CNodeType::And { .. } => {
+                gate.update_output(&mut self.scheduler, node_id, node.powered);
+                return;
+            }
+            CNodeType::Latch { .. } => {
+                gate.update_latch(&mut self.scheduler, node_id, &mut self.nodes);
+                // A repeater is updated again after it changed, in case its input changed while
+                // its tick was pending
+                gate.update(&mut self.scheduler, node_id, &self.nodes);
+                return;
+            }
+            _ => {}
+        }
+        // Both comparators update the same nodes, so these are updated even if the strongest
+        // output stays the same. The comparators write their own blocks, so the node isn't marked
+        // as changed.
+        let output_power = gate.xor_output_power();
+        node.powered = output_power > 0;
+        node.output_power = output_power;
+        for i in 0..self.nodes[node_id].updates.len() {
+            let update = self.nodes[node_id].updates[i];
+            self.update_node(update);
+        }
+    }
+
+    fn tick_delay_line(&mut self, node_id: NodeId, line_idx: usize) {
+        let now = self.scheduler.current_tick;
+        let line = &mut self.delay_lines[line_idx];
+        let Some(event) = line.take_due_event(now) else {
+            warn!("Delay line {:?} was ticked without a pending tick", node_id);
+            return;
+        };
+        match event {
+            LineEvent::First => {
+                let Some(powered) = line.tick_first(node_id, &self.nodes) else {
+                    return;
+                };
+                if let Some(trace) = &mut self.trace {
+                    trace.stats.state_changes += 1;
+                }
+                line.send(&mut self.scheduler, node_id, powered);
+                line.update_first(&mut self.scheduler, node_id, &self.nodes);
+            }
+            LineEvent::Hop(powered) => line.hop(&mut self.scheduler, node_id, powered),
+            LineEvent::Output(powered) => {
+                let powered = line.output(powered);
+                let node = &mut self.nodes[node_id];
+                node.changed = true;
+                node.powered = powered;
+                node.output_power = bool_to_ss(powered);
+                if let Some(trace) = &mut self.trace {
+                    trace.stats.state_changes += 1;
+                }
+                for i in 0..self.nodes[node_id].updates.len() {
+                    let update = self.nodes[node_id].updates[i];
+                    self.update_node(update);
+                }
+            }
+        }
+    }
+
+    /// Reports the stats of the tick that just ran and logs transitions of watched nodes.
+    fn finish_trace(&mut self, pending_before: [usize; TickScheduler::NUM_PRIORITIES]) {
+        let Some(trace) = &mut self.trace else {
             return;
         };
+        let pending_after = self.scheduler.pending_per_priority();
+        let [highest, higher, high, normal] =
+            [0, 1, 2, 3].map(|i| pending_after[i].saturating_sub(pending_before[i]));
+        trace.last_stats = mem::take(&mut trace.stats);
+        let stats = &trace.last_stats;
+        debug!(
+            tick = trace.tick,
+            nodes_ticked = stats.nodes_ticked,
+            nodes_updated = stats.nodes_updated,
+            state_changes = stats.state_changes,
+            scheduled_highest = highest,
+            scheduled_higher = higher,
+            scheduled_high = high,
+            scheduled_normal = normal,
+            "redpiler tick completed"
+        );
+
+        for (pos, last) in &mut trace.watched {
+            let Some(&node_id) = self.pos_map.get(pos) else {
+                continue;
+            };
+            let current = node_snapshot(&self.nodes[node_id]);
+            if let Some(last) = *last {
+                if last != current {
+                    info!(
+                        tick = trace.tick,
+                        %pos,
+                        from = ?last,
+                        to = ?current,
+                        "watched {:?} node changed",
+                        self.nodes[node_id].ty
+                    );
+                }
+            }
+            *last = Some(current);
+        }
+        trace.tick += 1;
+    }
+}
+
+impl JITBackend for DirectBackend {
+    fn new_backend(&self) -> Box<dyn JITBackend + Send> {
+        Box::<DirectBackend>::default()
+    }
+
+    fn snapshot(&self, pos: BlockPos) -> Result<NodeSnapshot, BackendError> {
+        Ok(node_snapshot(&self.nodes[self.node_at(pos)?]))
+    }
+
+    fn inspect(&self, pos: BlockPos) -> Result<NodeInspection, BackendError> {
+        let node_id = self.node_at(pos)?;
+        let node = &self.nodes[node_id];
+        let pos_of = |id: NodeId| self.blocks[id.index()].map(|(pos, _)| pos);
+
+        let inputs = node
+            .default_inputs
+            .iter()
+            .map(|link| (LinkType::Default, link))
+            .chain(node.side_inputs.iter().map(|link| (LinkType::Side, link)))
+            .map(|(ty, &link)| InspectedLink {
+                ty,
+                weight: link.weight,
+                source: pos_of(link.to),
+                strength: link_strength(link, &self.nodes),
+            })
+            .collect();
+
+        Ok(NodeInspection {
+            pos,
+            ty: node.ty.to_compile_type(&self.gates, &self.delay_lines),
+            output_power: node.output_power,
+            powered: node.powered,
+            locked: node.locked,
+            pending_tick: match node.ty {
+                NodeType::Gate(gate) => self.gates[gate].has_pending_ticks(),
+                NodeType::DelayLine(line) => self.delay_lines[line].has_pending_ticks(),
+                _ => node.pending_tick,
+            },
+            inputs,
+            updates: node.updates.iter().filter_map(|&id| pos_of(id)).collect(),
+        })
+    }
 
-        // println!("Node {:?}: {:#?}", node_id, self.nodes[*node_id]);
+    fn set_tracing(&mut self, enabled: bool) {
+        match (enabled, &self.trace) {
+            (true, None) => self.trace = Some(Default::default()),
+            (false, _) => self.trace = None,
+            _ => {}
+        }
+    }
+
+    fn watch(&mut self, pos: BlockPos) -> Result<(), BackendError> {
+        let node_id = self.node_at(pos)?;
+        let snapshot = node_snapshot(&self.nodes[node_id]);
+        self.trace
+            .get_or_insert_with(Default::default)
+            .watched
+            .insert(pos, Some(snapshot));
+        Ok(())
+    }
+
+    fn unwatch(&mut self, pos: BlockPos) {
+        if let Some(trace) = &mut self.trace {
+            trace.watched.remove(&pos);
+        }
     }
 
     fn reset(&mut self, plot: &mut PlotWorld, io_only: bool) {
-        self.scheduler.reset(plot, &self.blocks);
+        for (i, node) in self.nodes.inner().iter().enumerate() {
+            let NodeType::Gate(gate) = node.ty else {
+                continue;
+            };
+            let gate = &self.gates[gate];
+            let node_pos = self.blocks[i].map(|(pos, _)| pos);
+            let part_pos = |part: &GatePart| part.block.map(|(pos, _)| pos).or(node_pos);
+            let now = self.scheduler.current_tick;
+            for (&(due, priority, _), tick) in &gate.ticks {
+                let ticks_left = (due - now) as u32 + 1;
+                match tick {
+                    GateTick::Parts(parts) => {
+                        for pos in parts.iter().filter_map(|&part| part_pos(&gate.parts[part])) {
+                            plot.schedule_tick(pos, ticks_left, priority);
+                        }
+                    }
+                    GateTick::Output => {
+                        if let Some(pos) = node_pos {
+                            plot.schedule_tick(pos, ticks_left, priority);
+                        }
+                    }
+                }
+            }
+            for part in gate.parts.iter() {
+                let Some((pos, block)) = part.block else {
+                    continue;
+                };
+                if let CNodeType::Xor { .. } = gate.ty {
+                    let block_entity = BlockEntity::Comparator {
+                        output_strength: part.output_power,
+                    };
+                    plot.set_block_entity(pos, block_entity);
+                }
+                if io_only {
+                    plot.set_block(pos, block);
+                }
+            }
+        }
+        self.gates.clear();
+        for (i, node) in self.nodes.inner().iter().enumerate() {
+            let NodeType::DelayLine(line) = node.ty else {
+                continue;
+            };
+            let line = &self.delay_lines[line];
+            let stage_pos = |stage: &LineStage| match stage.block {
+                Some((pos, _)) => Some(pos),
+                None => self.blocks[i].map(|(pos, _)| pos),
+            };
+            let now = self.scheduler.current_tick;
+            if line.first_pending {
+                let (&(due, priority, _), _) = line
+                    .events
+                    .iter()
+                    .find(|(_, event)| matches!(event, LineEvent::First))
+                    .unwrap();
+                if let Some(pos) = stage_pos(&line.stages[0]) {
+                    plot.schedule_tick(pos, (due - now) as u32 + 1, priority);
+                }
+            }
+            // A stage has a pending tick if the stage before it already changed
+            for &(tick, powered) in &line.pulses {
+                for pair in line.stages.windows(2) {
+                    let (previous, stage) = (&pair[0], &pair[1]);
+                    let due = tick + stage.offset;
+                    if tick + previous.offset >= now || due < now {
+                        continue;
+                    }
+                    if let Some(pos) = stage_pos(stage) {
+                        let priority = stage.tick_priority(powered);
+                        plot.schedule_tick(pos, (due - now) as u32 + 1, priority);
+                    }
+                }
+            }
+            if io_only {
+                for (pos, block) in line.stages.iter().filter_map(|stage| stage.block) {
+                    plot.set_block(pos, block);
+                }
+            }
+        }
+        self.delay_lines.clear();
+        self.scheduler.reset(plot, &self.nodes, &self.blocks);
 
         let nodes = std::mem::take(&mut self.nodes);
 
@@ -349,7 +1302,10 @@ impl JITBackend for DirectBackend {
             let Some((pos, block)) = self.blocks[i] else {
                 continue;
             };
-            if matches!(node.ty, NodeType::Comparator(_)) {
+            if matches!(
+                node.ty,
+                NodeType::Comparator(_) | NodeType::BinaryComparator
+            ) {
                 let block_entity = BlockEntity::Comparator {
                     output_strength: node.output_power,
                 };
@@ -364,39 +1320,66 @@ impl JITBackend for DirectBackend {
         self.pos_map.clear();
     }
 
-    fn on_use_block(&mut self, _plot: &mut PlotWorld, pos: BlockPos) {
-        // println!("pos_map: {:#?}\nnodes: {:#?}\nblocks: {:#?}", &self.pos_map, &self.nodes, &self.blocks);
-        let node_id = self.pos_map[&pos];
+    fn on_use_block(&mut self, _plot: &mut PlotWorld, pos: BlockPos) -> Result<(), BackendError> {
+        let node_id = self.node_at(pos)?;
         let node = &self.nodes[node_id];
-        // println!("we did on use block: {:?}", node);
         match node.ty {
             NodeType::Button => {
                 let powered = !node.powered;
                 self.schedule_tick(node_id, 10, TickPriority::Normal);
-                self.set_node(node_id, powered, bool_to_ss(powered));            }
+                self.set_node(node_id, powered, bool_to_ss(powered));
+            }
             NodeType::Lever => {
                 self.set_node(node_id, !node.powered, bool_to_ss(!node.powered));
             }
-            _ => warn!("Tried to use a {:?} redpiler node", node.ty),
+            ty => {
+                return Err(BackendError::WrongNodeType {
+                    pos,
+                    node_type: format!("{:?}", ty),
+                    expected: "button or lever",
+                })
+            }
         }
-
-        // println!("pos_map: {:#?}\nnodes: {:#?}\nblocks: {:#?}", &self.pos_map, &self.nodes, &self.blocks);
+        Ok(())
     }
 
-    fn set_pressure_plate(&mut self, _plot: &mut PlotWorld, pos: BlockPos, powered: bool) {
-        let node_id = self.pos_map[&pos];
+    fn set_pressure_plate(
+        &mut self,
+        _plot: &mut PlotWorld,
+        pos: BlockPos,
+        powered: bool,
+    ) -> Result<(), BackendError> {
+        let node_id = self.node_at(pos)?;
         let node = &self.nodes[node_id];
         match node.ty {
             NodeType::PressurePlate => {
                 self.set_node(node_id, powered, bool_to_ss(powered));
+                Ok(())
             }
-            _ => warn!("Tried to set pressure plate state for a {:?}", node.ty),
+            ty => Err(BackendError::WrongNodeType {
+                pos,
+                node_type: format!("{:?}", ty),
+                expected: "pressure plate",
+            }),
         }
     }
 
     fn tick(&mut self, _plot: &mut PlotWorld) {
         let mut queues = self.scheduler.queues_this_tick();
 
+        let span = self.trace.as_mut().map(|trace| {
+            // Changes made between ticks, such as using a lever, are not part of the tick
+            trace.stats = TickStats {
+                nodes_ticked: queues.0.iter().map(Vec::len).sum(),
+                ..Default::default()
+            };
+            trace_span!("redpiler_tick", tick = trace.tick).entered()
+        });
+        let pending_before = match span {
+            Some(_) => self.scheduler.pending_per_priority(),
+            None => Default::default(),
+        };
+
         for node_id in queues.drain_iter() {
             self.nodes[node_id].pending_tick = false;
             let node = &self.nodes[node_id];
@@ -414,7 +1397,7 @@ impl JITBackend for DirectBackend {
                         self.set_node(node_id, true, 15);
                     }
                 }
-                NodeType::SimpleRepeater(_delay) => {
+                NodeType::SimpleRepeater(_) => {
                     let should_be_powered = get_bool_input(node, &self.nodes);
                     if node.powered && !should_be_powered {
                         self.set_node(node_id, false, 0);
@@ -422,7 +1405,7 @@ impl JITBackend for DirectBackend {
                         self.set_node(node_id, true, 15);
                     }
                 }
-                NodeType::Torch => {
+                NodeType::Torch | NodeType::Not => {
                     let should_be_off = get_bool_input(node, &self.nodes);
                     let lit = node.powered;
                     if lit && should_be_off {
@@ -445,6 +1428,12 @@ impl JITBackend for DirectBackend {
                         self.set_node(node_id, new_strength > 0, new_strength);
                     }
                 }
+                NodeType::BinaryComparator => {
+                    let should_be_powered = get_bool_input(node, &self.nodes);
+                    if node.powered != should_be_powered {
+                        self.set_node(node_id, should_be_powered, bool_to_ss(should_be_powered));
+                    }
+                }
                 NodeType::Lamp => {
                     let should_be_lit = get_bool_input(node, &self.nodes);
                     if node.powered && !should_be_lit {
@@ -456,16 +1445,41 @@ impl JITBackend for DirectBackend {
                         self.set_node(node_id, false, 0);
                     }
                 }
+                NodeType::Gate(gate) => self.tick_gate(node_id, gate),
+                NodeType::DelayLine(line) => self.tick_delay_line(node_id, line),
                 _ => warn!("Node {:?} should not be ticked!", node.ty),
             }
         }
 
+        self.finish_trace(pending_before);
+        drop(span);
         self.scheduler.end_tick(queues);
-        println!("{:#?}", self.nodes);
-        // println!("{}", self);
     }
 
-    fn compile(&mut self, graph: CompileGraph, ticks: Vec<TickEntry>) {
+    fn run_ticks(&mut self, plot: &mut PlotWorld, ticks: u64) -> TickRunStats {
+        let mut stats = TickRunStats::default();
+        while stats.simulated + stats.skipped < ticks {
+            let left = ticks - stats.simulated - stats.skipped;
+            let empty = match self.scheduler.empty_ticks() {
+                Some(empty) => (empty as u64).min(left),
+                None => left,
+            };
+            if empty == 0 {
+                self.tick(plot);
+                stats.simulated += 1;
+                continue;
+            }
+
+            self.scheduler.skip_ticks(empty);
+            if let Some(trace) = &mut self.trace {
+                trace.tick += empty;
+            }
+            stats.skipped += empty;
+        }
+        stats
+    }
+
+    fn compile(&mut self, graph: CompileGraph, ticks: Vec<TickEntry>) -> FinalGraphStats {
         let mut nodes_map = HashMap::with_capacity(graph.node_count());
         for node in graph.node_indices() {
             nodes_map.insert(node, nodes_map.len());
@@ -473,9 +1487,21 @@ impl JITBackend for DirectBackend {
         let nodes_len = nodes_map.len();
 
         let mut stats = FinalGraphStats::default();
+        let mut gates = Vec::new();
+        let mut delay_lines = Vec::new();
         let nodes = graph
             .node_indices()
-            .map(|idx| Node::from_compile_node(&graph, idx, nodes_len, &nodes_map, &mut stats))
+            .map(|idx| {
+                Node::from_compile_node(
+                    &graph,
+                    idx,
+                    nodes_len,
+                    &nodes_map,
+                    &mut gates,
+                    &mut delay_lines,
+                    &mut stats,
+                )
+            })
             .collect();
         stats.nodes_bytes = nodes_len * std::mem::size_of::<Node>();
         trace!("{:#?}", stats);
@@ -485,6 +1511,8 @@ impl JITBackend for DirectBackend {
             .map(|node| node.block.map(|(pos, id)| (pos, Block::from_id(id))))
             .collect();
         self.nodes = Nodes::new(nodes);
+        self.gates = gates;
+        self.delay_lines = delay_lines;
 
         for i in 0..self.blocks.len() {
             if let Some((pos, _)) = self.blocks[i] {
@@ -502,6 +1530,7 @@ impl JITBackend for DirectBackend {
         self.scheduler.end_tick(queues);
         // Dot file output
         // println!("{}", self);
+        stats
     }
 
     fn flush(&mut self, plot: &mut PlotWorld, io_only: bool) {
@@ -520,6 +1549,44 @@ impl JITBackend for DirectBackend {
             }
             node.changed = false;
         }
+
+        for part in self.gates.iter_mut().flat_map(|gate| gate.parts.iter_mut()) {
+            let Some((pos, block)) = &mut part.block else {
+                continue;
+            };
+            if part.changed && !io_only {
+                if let Some(powered) = block_powered_mut(block) {
+                    *powered = part.powered
+                }
+                plot.set_block(*pos, *block);
+            }
+            part.changed = false;
+        }
+
+        if io_only {
+            return;
+        }
+        let now = self.scheduler.current_tick;
+        for line in &mut self.delay_lines {
+            for i in 0..line.stages.len() {
+                let stage = &line.stages[i];
+                let powered = match i {
+                    0 => line.first_powered,
+                    _ => line.stage_powered(stage, now),
+                };
+                let stage = &mut line.stages[i];
+                let Some((pos, block)) = &mut stage.block else {
+                    continue;
+                };
+                if stage.powered != powered {
+                    stage.powered = powered;
+                    if let Some(block_powered) = block_powered_mut(block) {
+                        *block_powered = powered
+                    }
+                    plot.set_block(*pos, *block);
+                }
+            }
+        }
     }
 }
 
@@ -577,7 +1644,13 @@ fn get_all_input(node: &Node, nodes: &Nodes) -> (u8, u8) {
     (input_power, side_input_power)
 }
 
-fn update_node(scheduler: &mut TickScheduler, nodes: &mut Nodes, node_id: NodeId) {
+fn update_node(
+    scheduler: &mut TickScheduler,
+    nodes: &mut Nodes,
+    gates: &mut [LogicGate],
+    delay_lines: &mut [DelayLine],
+    node_id: NodeId,
+) {
     let node = &nodes[node_id];
 
     match node.ty {
@@ -622,7 +1695,7 @@ fn update_node(scheduler: &mut TickScheduler, nodes: &mut Nodes, node_id: NodeId
                 schedule_tick(scheduler, node_id, node, delay as usize, priority);
             }
         }
-        NodeType::Torch => {
+        NodeType::Torch | NodeType::Not => {
             if node.pending_tick {
                 return;
             }
@@ -655,6 +1728,20 @@ fn update_node(scheduler: &mut TickScheduler, nodes: &mut Nodes, node_id: NodeId
                 schedule_tick(scheduler, node_id, node, 1, priority);
             }
         }
+        NodeType::BinaryComparator => {
+            if node.pending_tick {
+                return;
+            }
+            if get_bool_input(node, nodes) != node.powered {
+                let priority = if node.facing_diode {
+                    TickPriority::High
+                } else {
+                    TickPriority::Normal
+                };
+                let node = &mut nodes[node_id];
+                schedule_tick(scheduler, node_id, node, 1, priority);
+            }
+        }
         NodeType::Lamp => {
             let should_be_lit = get_bool_input(node, nodes);
             let lit = node.powered;
@@ -680,6 +1767,12 @@ fn update_node(scheduler: &mut TickScheduler, nodes: &mut Nodes, node_id: NodeId
                 node.changed = true;
             }
         }
+        NodeType::Gate(gate) => {
+            let gate = &mut gates[gate];
+            gate.update(scheduler, node_id, nodes);
+            gate.update_latch(scheduler, node_id, nodes);
+        }
+        NodeType::DelayLine(line) => delay_lines[line].update_first(scheduler, node_id, nodes),
         _ => {} // panic!("Node {:?} should not be updated!", node.state),
     }
 }
@@ -744,3 +1837,217 @@ fn calculate_comparator_output(mode: ComparatorMode, input_strength: u8, power_o
         ComparatorMode::Subtract => input_strength.saturating_sub(power_on_sides),
     }
 }
+
+#[test]
+fn inspect_lever_lamp() {
+    use crate::blocks::Lever;
+    use crate::redpiler::compile_graph::{CompileLink, CompileNode, NodeState};
+
+    let lever_pos = BlockPos::new(0, 0, 0);
+    let lamp_pos = BlockPos::new(1, 0, 0);
+    let mut graph = CompileGraph::new();
+    let lever = graph.add_node(CompileNode {
+        ty: CNodeType::Lever,
+        block: Some((
+            lever_pos,
+            Block::Lever {
+                lever: Lever::default(),
+            }
+            .get_id(),
+        )),
+        state: NodeState::simple(false),
+        facing_diode: false,
+        comparator_far_input: None,
+        stages: Vec::new(),
+    });
+    let lamp = graph.add_node(CompileNode {
+        ty: CNodeType::Lamp,
+        block: Some((lamp_pos, Block::RedstoneLamp { lit: false }.get_id())),
+        state: NodeState::simple(false),
+        facing_diode: false,
+        comparator_far_input: None,
+        stages: Vec::new(),
+    });
+    graph.add_edge(lever, lamp, CompileLink::default(2));
+
+    let mut backend = DirectBackend::default();
+    backend.compile(graph, Vec::new());
+    let mut plot = PlotWorld {
+        x: 0,
+        z: 0,
+        chunks: Vec::new(),
+        to_be_ticked: Vec::new(),
+    };
+    backend.on_use_block(&mut plot, lever_pos).unwrap();
+
+    let lever = backend.inspect(lever_pos).unwrap();
+    assert_eq!(lever.ty, CNodeType::Lever);
+    assert!(lever.powered);
+    assert_eq!(lever.updates, vec![lamp_pos]);
+
+    let lamp = backend.inspect(lamp_pos).unwrap();
+    assert!(lamp.powered);
+    assert_eq!(
+        lamp.inputs,
+        vec![InspectedLink {
+            ty: LinkType::Default,
+            weight: 2,
+            source: Some(lever_pos),
+            strength: 13,
+        }]
+    );
+    assert_eq!(
+        backend.inspect(BlockPos::new(2, 0, 0)),
+        Err(BackendError::NoNode(BlockPos::new(2, 0, 0)))
+    );
+}
+
+#[test]
+fn run_ticks_skips_empty_ticks() {
+    use crate::blocks::RedstoneRepeater;
+    use crate::redpiler::compile_graph::{CompileLink, CompileNode, NodeState};
+
+    // A slow clock: a torch powering itself through two repeaters
+    let build = || {
+        let mut graph = CompileGraph::new();
+        let mut add = |x: i32, ty: CNodeType, block: Block, powered: bool| {
+            graph.add_node(CompileNode {
+                ty,
+                block: Some((BlockPos::new(x, 0, 0), block.get_id())),
+                state: NodeState::simple(powered),
+                facing_diode: false,
+                comparator_far_input: None,
+                stages: Vec::new(),
+            })
+        };
+        let repeater = Block::RedstoneRepeater {
+            repeater: RedstoneRepeater {
+                delay: 4,
+                ..Default::default()
+            },
+        };
+        let torch = add(
+            0,
+            CNodeType::Torch,
+            Block::RedstoneTorch { lit: true },
+            true,
+        );
+        let first = add(1, CNodeType::Repeater(4), repeater, false);
+        let second = add(2, CNodeType::Repeater(4), repeater, false);
+        graph.add_edge(torch, first, CompileLink::default(0));
+        graph.add_edge(first, second, CompileLink::default(0));
+        graph.add_edge(second, torch, CompileLink::default(0));
+
+        let mut backend = DirectBackend::default();
+        let start = TickEntry {
+            ticks_left: 1,
+            tick_priority: TickPriority::Normal,
+            pos: BlockPos::new(1, 0, 0),
+        };
+        backend.compile(graph, vec![start]);
+        backend
+    };
+
+    let mut plot = PlotWorld {
+        x: 0,
+        z: 0,
+        chunks: Vec::new(),
+        to_be_ticked: Vec::new(),
+    };
+    let mut stepped = build();
+    let mut skipping = build();
+    let mut total = TickRunStats::default();
+    for ticks in [1, 3, 7, 20, 2, 50, 100] {
+        for _ in 0..ticks {
+            stepped.tick(&mut plot);
+        }
+        let stats = skipping.run_ticks(&mut plot, ticks);
+        assert_eq!(stats.simulated + stats.skipped, ticks);
+        total.simulated += stats.simulated;
+        total.skipped += stats.skipped;
+
+        for x in 0..3 {
+            let pos = BlockPos::new(x, 0, 0);
+            assert_eq!(stepped.snapshot(pos), skipping.snapshot(pos));
+        }
+    }
+    assert!(total.skipped > total.simulated);
+}
+
+#[test]
+fn trace_counts_state_changes() {
+    use crate::blocks::{Lever, RedstoneRepeater, RedstoneWire};
+    use crate::redpiler::compile_graph::{CompileLink, CompileNode, NodeState};
+
+    let mut graph = CompileGraph::new();
+    let mut add = |x: i32, ty: CNodeType, block: Block, powered: bool| {
+        graph.add_node(CompileNode {
+            ty,
+            block: Some((BlockPos::new(x, 0, 0), block.get_id())),
+            state: NodeState::simple(powered),
+            facing_diode: false,
+            comparator_far_input: None,
+            stages: Vec::new(),
+        })
+    };
+    let lever = Block::Lever {
+        lever: Lever::default(),
+    };
+    let repeater = Block::RedstoneRepeater {
+        repeater: RedstoneRepeater::default(),
+    };
+    let wire = Block::RedstoneWire {
+        wire: RedstoneWire::default(),
+    };
+    let lever = add(0, CNodeType::Lever, lever, false);
+    let repeater = add(1, CNodeType::Repeater(1), repeater, false);
+    let lamp = add(
+        2,
+        CNodeType::Lamp,
+        Block::RedstoneLamp { lit: false },
+        false,
+    );
+    let torch = add(
+        3,
+        CNodeType::Torch,
+        Block::RedstoneTorch { lit: true },
+        true,
+    );
+    // The repeater locks itself through the wire once it turns on
+    let wire = add(4, CNodeType::Wire, wire, false);
+    graph.add_edge(lever, repeater, CompileLink::default(0));
+    graph.add_edge(repeater, lamp, CompileLink::default(0));
+    graph.add_edge(repeater, torch, CompileLink::default(0));
+    graph.add_edge(repeater, wire, CompileLink::default(0));
+    graph.add_edge(wire, repeater, CompileLink::side(0));
+
+    let mut backend = DirectBackend::default();
+    backend.compile(graph, Vec::new());
+    backend.set_tracing(true);
+    let mut plot = PlotWorld {
+        x: 0,
+        z: 0,
+        chunks: Vec::new(),
+        to_be_ticked: Vec::new(),
+    };
+    let stats = |backend: &DirectBackend| {
+        let stats = &backend.trace.as_ref().unwrap().last_stats;
+        (stats.nodes_ticked, stats.nodes_updated, stats.state_changes)
+    };
+
+    // Using the lever is not part of the next tick
+    backend
+        .on_use_block(&mut plot, BlockPos::new(0, 0, 0))
+        .unwrap();
+    backend.tick(&mut plot);
+    assert_eq!(stats(&backend), (0, 0, 0));
+    backend.tick(&mut plot);
+    // The repeater turning on and getting locked is a single change, along with the lamp and the
+    // wire
+    assert_eq!(stats(&backend), (1, 4, 3));
+    let repeater = backend.snapshot(BlockPos::new(1, 0, 0)).unwrap();
+    assert!(repeater.powered && repeater.locked);
+    backend.tick(&mut plot);
+    assert_eq!(stats(&backend), (1, 1, 1));
+    assert!(!backend.snapshot(BlockPos::new(3, 0, 0)).unwrap().powered);
+}
@@ -3,18 +3,138 @@ pub mod cranelift;
 pub mod direct;
 // pub mod par_direct;
 
-use super::compile_graph::CompileGraph;
+use super::compile_graph::{CompileGraph, LinkType, NodeType};
 use crate::plot::PlotWorld;
 use mchprs_blocks::BlockPos;
 use mchprs_world::TickEntry;
+use serde::Serialize;
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum BackendError {
+    #[error("there is no redpiler node at {0}")]
+    NoNode(BlockPos),
+
+    #[error("the redpiler node at {pos} is a {node_type}, expected a {expected}")]
+    WrongNodeType {
+        pos: BlockPos,
+        node_type: String,
+        expected: &'static str,
+    },
+}
+
+/// The observable state of a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeSnapshot {
+    pub powered: bool,
+    pub locked: bool,
+    pub output_power: u8,
+}
+
+/// An input link of an inspected node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InspectedLink {
+    pub ty: LinkType,
+    pub weight: u8,
+    /// The position of the node this link comes from. Generic constants have no position.
+    pub source: Option<BlockPos>,
+    /// The signal strength currently carried by this link
+    pub strength: u8,
+}
+
+/// A snapshot of a redpiler node, returned by [`JITBackend::inspect`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeInspection {
+    pub pos: BlockPos,
+    pub ty: NodeType,
+    pub output_power: u8,
+    pub powered: bool,
+    pub locked: bool,
+    pub pending_tick: bool,
+    pub inputs: Vec<InspectedLink>,
+    /// Positions of the nodes that get updated when this node changes
+    pub updates: Vec<BlockPos>,
+}
+
+impl fmt::Display for NodeInspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:?} at {}", self.ty, self.pos)?;
+        writeln!(
+            f,
+            "  output_power: {}, powered: {}, locked: {}, pending_tick: {}",
+            self.output_power, self.powered, self.locked, self.pending_tick
+        )?;
+        for input in &self.inputs {
+            let source = match input.source {
+                Some(pos) => pos.to_string(),
+                None => "constant".to_string(),
+            };
+            writeln!(
+                f,
+                "  input {:?} from {} (weight: {}, strength: {})",
+                input.ty, source, input.weight, input.strength
+            )?;
+        }
+        for update in &self.updates {
+            writeln!(f, "  updates {}", update)?;
+        }
+        Ok(())
+    }
+}
+
+/// Statistics about the graph a backend was compiled with
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct FinalGraphStats {
+    pub update_link_count: usize,
+    pub side_link_count: usize,
+    pub default_link_count: usize,
+    /// Size of the node array in bytes
+    pub nodes_bytes: usize,
+}
+
+/// Returned by [`JITBackend::run_ticks`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TickRunStats {
+    /// Ticks which had something scheduled and were simulated
+    pub simulated: u64,
+    /// Ticks which had nothing scheduled and were skipped
+    pub skipped: u64,
+}
 
 pub trait JITBackend {
-    fn compile(&mut self, graph: CompileGraph, ticks: Vec<TickEntry>);
+    /// Creates a new backend of the same kind which has not compiled anything yet. Background
+    /// compiles use this to compile with the selected backend on another thread.
+    fn new_backend(&self) -> Box<dyn JITBackend + Send>;
+    fn compile(&mut self, graph: CompileGraph, ticks: Vec<TickEntry>) -> FinalGraphStats;
     fn tick(&mut self, plot: &mut PlotWorld);
-    fn on_use_block(&mut self, plot: &mut PlotWorld, pos: BlockPos);
-    fn set_pressure_plate(&mut self, plot: &mut PlotWorld, pos: BlockPos, powered: bool);
+    /// Runs `ticks` ticks. Backends may jump over ticks in which nothing is scheduled, as nothing
+    /// can happen during them.
+    fn run_ticks(&mut self, plot: &mut PlotWorld, ticks: u64) -> TickRunStats {
+        for _ in 0..ticks {
+            self.tick(plot);
+        }
+        TickRunStats {
+            simulated: ticks,
+            skipped: 0,
+        }
+    }
+    fn on_use_block(&mut self, plot: &mut PlotWorld, pos: BlockPos) -> Result<(), BackendError>;
+    fn set_pressure_plate(
+        &mut self,
+        plot: &mut PlotWorld,
+        pos: BlockPos,
+        powered: bool,
+    ) -> Result<(), BackendError>;
     fn flush(&mut self, plot: &mut PlotWorld, io_only: bool);
     fn reset(&mut self, plot: &mut PlotWorld, io_only: bool);
+    /// Enables or disables per-tick tracing. Disabling tracing also removes all watched positions.
+    fn set_tracing(&mut self, enabled: bool);
+    /// Logs every state transition of the node at `pos`. This implicitly enables tracing.
+    fn watch(&mut self, pos: BlockPos) -> Result<(), BackendError>;
+    fn unwatch(&mut self, pos: BlockPos);
+    /// Returns the current state of the node at `pos`. This is cheap enough to call every tick.
+    fn snapshot(&self, pos: BlockPos) -> Result<NodeSnapshot, BackendError>;
     /// Inspect block for debugging
-    fn inspect(&mut self, pos: BlockPos);
+    fn inspect(&self, pos: BlockPos) -> Result<NodeInspection, BackendError>;
 }
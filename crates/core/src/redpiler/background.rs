@@ -0,0 +1,115 @@
+//! Compiling on a separate thread, see [`Compiler::compile_in_background`].
+
+use super::backend::JITBackend;
+use super::cache::PlotContents;
+#[cfg(doc)]
+use super::Compiler;
+use super::{CompileReport, CompilerOptions};
+use mchprs_world::TickEntry;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// Progress of a running compile, shared between the compiling thread and its [`CompileHandle`].
+/// This is also used by synchronous compiles, where nothing ever reads it.
+#[derive(Debug, Default)]
+pub struct CompileProgress {
+    pass: Mutex<Option<&'static str>>,
+    positions_scanned: AtomicUsize,
+    positions_total: AtomicUsize,
+    cancelled: AtomicBool,
+}
+
+impl CompileProgress {
+    /// The name of the pass that is currently running
+    pub fn current_pass(&self) -> Option<&'static str> {
+        *self.pass.lock().unwrap()
+    }
+
+    /// The fraction of plot positions which have been scanned for nodes, from 0 to 1
+    pub fn scanned(&self) -> f32 {
+        let total = self.positions_total.load(Ordering::Relaxed);
+        if total == 0 {
+            return 0.0;
+        }
+        self.positions_scanned.load(Ordering::Relaxed) as f32 / total as f32
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Requests the compile to stop. Passes check this periodically, so the compile will not stop
+    /// immediately.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub(super) fn start_pass(&self, name: &'static str) {
+        *self.pass.lock().unwrap() = Some(name);
+    }
+
+    pub(super) fn start_scan(&self, total: usize) {
+        self.positions_scanned.store(0, Ordering::Relaxed);
+        self.positions_total.store(total, Ordering::Relaxed);
+    }
+
+    pub(super) fn add_scanned(&self, positions: usize) {
+        self.positions_scanned
+            .fetch_add(positions, Ordering::Relaxed);
+    }
+}
+
+/// The result of a compile that finished on another thread
+pub(super) struct CompiledBackend {
+    pub jit: Box<dyn JITBackend + Send>,
+    pub options: CompilerOptions,
+    pub report: CompileReport,
+    /// The contents of the plot snapshot that was compiled
+    pub contents: PlotContents,
+    /// The pending ticks the compile was started with
+    pub ticks: Vec<TickEntry>,
+}
+
+/// A compile running on another thread, returned by [`Compiler::compile_in_background`].
+///
+/// Once the compile has finished, the handle must be passed to [`Compiler::install`] to start
+/// using the compiled backend. Dropping the handle detaches the thread, which will keep running
+/// until the compile completes unless it is cancelled first.
+pub struct CompileHandle {
+    progress: Arc<CompileProgress>,
+    thread: JoinHandle<Option<CompiledBackend>>,
+}
+
+impl CompileHandle {
+    pub(super) fn new(
+        progress: Arc<CompileProgress>,
+        thread: JoinHandle<Option<CompiledBackend>>,
+    ) -> CompileHandle {
+        CompileHandle { progress, thread }
+    }
+
+    pub fn progress(&self) -> &CompileProgress {
+        &self.progress
+    }
+
+    pub fn cancel(&self) {
+        self.progress.cancel()
+    }
+
+    /// Returns true once the compiling thread has stopped, either because the compile completed
+    /// or because it was cancelled.
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Waits for the compiling thread to stop. Returns `None` if the compile was cancelled.
+    pub(super) fn join(self) -> Option<CompiledBackend> {
+        let compiled = match self.thread.join() {
+            Ok(compiled) => compiled,
+            Err(panic) => std::panic::resume_unwind(panic),
+        };
+        // A compile that was cancelled after it already completed is still discarded
+        compiled.filter(|_| !self.progress.is_cancelled())
+    }
+}
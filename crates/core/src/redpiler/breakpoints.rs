@@ -0,0 +1,59 @@
+//! Breakpoints let a caller run the simulation until something interesting happens, such as a
+//! halt light turning on, instead of guessing how many ticks a circuit needs.
+//!
+//! Every condition is edge triggered: it fires on the tick where it goes from false to true, so a
+//! lamp that stays on does not stop the simulation again on every following tick.
+
+use super::backend::NodeSnapshot;
+use mchprs_blocks::BlockPos;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakCondition {
+    /// The node at the position becomes powered (a lamp turns on, a torch lights up, ...)
+    PoweredOn(BlockPos),
+    /// The node at the position stops being powered
+    PoweredOff(BlockPos),
+    /// The output power of the node at the position becomes greater than the value
+    OutputAbove(BlockPos, u8),
+    /// The output power of the node at the position becomes less than the value
+    OutputBelow(BlockPos, u8),
+    /// The node at the position changes in any way
+    Changed(BlockPos),
+}
+
+impl BreakCondition {
+    pub fn pos(self) -> BlockPos {
+        match self {
+            BreakCondition::PoweredOn(pos)
+            | BreakCondition::PoweredOff(pos)
+            | BreakCondition::OutputAbove(pos, _)
+            | BreakCondition::OutputBelow(pos, _)
+            | BreakCondition::Changed(pos) => pos,
+        }
+    }
+
+    fn holds(self, state: NodeSnapshot) -> bool {
+        match self {
+            BreakCondition::PoweredOn(_) => state.powered,
+            BreakCondition::PoweredOff(_) => !state.powered,
+            BreakCondition::OutputAbove(_, ss) => state.output_power > ss,
+            BreakCondition::OutputBelow(_, ss) => state.output_power < ss,
+            BreakCondition::Changed(_) => false,
+        }
+    }
+
+    pub(super) fn triggered(self, old: NodeSnapshot, new: NodeSnapshot) -> bool {
+        match self {
+            BreakCondition::Changed(_) => old != new,
+            _ => !self.holds(old) && self.holds(new),
+        }
+    }
+}
+
+/// Reported by [`super::Compiler::tick`] when one or more breakpoints fire
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BreakpointHit {
+    /// The number of ticks since compilation, including the tick that triggered the breakpoint
+    pub tick: u64,
+    pub conditions: Vec<BreakCondition>,
+}
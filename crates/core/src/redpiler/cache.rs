@@ -0,0 +1,284 @@
+//! On-disk cache of optimized graphs, see [`GraphCache`].
+
+use super::compile_graph::CompileGraph;
+use super::{CompilerInput, CompilerOptions};
+use crate::plot::PlotWorld;
+use crate::world::storage::ChunkSection;
+use mchprs_blocks::BlockPos;
+use mchprs_world::TickEntry;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use thiserror::Error;
+use tracing::warn;
+
+/// Bump this whenever the passes or the graph format change, so old cache entries are ignored.
+const CACHE_VERSION: u32 = 6;
+
+#[derive(Error, Debug)]
+enum CacheError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Encode(#[from] bincode::Error),
+}
+
+/// A directory of optimized graphs, named by a hash of the plot contents and the compiler options.
+/// When the key of a compile matches an entry, the cached graph is used instead of running the
+/// passes. Each entry stores its full [`CacheKey`], so a hash collision is never mistaken for a
+/// match.
+///
+/// Entries are never removed, the directory can be deleted at any time to clear the cache.
+#[derive(Debug, Clone)]
+pub struct GraphCache {
+    dir: PathBuf,
+}
+
+impl GraphCache {
+    pub fn new(dir: impl Into<PathBuf>) -> GraphCache {
+        GraphCache { dir: dir.into() }
+    }
+
+    /// Uses a `redpiler_cache` directory next to the plot save at `save_path`
+    pub fn next_to_save(save_path: impl AsRef<Path>) -> GraphCache {
+        let parent = save_path
+            .as_ref()
+            .parent()
+            .unwrap_or_else(|| Path::new("."));
+        GraphCache::new(parent.join("redpiler_cache"))
+    }
+
+    fn entry_path(&self, key: &CacheKey) -> PathBuf {
+        self.dir.join(format!("{:x}.graph", key.digest()))
+    }
+
+    /// Returns the cached graph for `key`. Unreadable entries and entries stored for a different
+    /// key are treated as missing.
+    pub(super) fn load(&self, key: &CacheKey) -> Option<CompileGraph> {
+        let path = self.entry_path(key);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return None,
+            Err(err) => {
+                warn!(
+                    "Could not open graph cache entry {}: {}",
+                    path.display(),
+                    err
+                );
+                return None;
+            }
+        };
+        let mut reader = BufReader::new(file);
+        let entry = bincode::deserialize_from(&mut reader).and_then(|stored: CacheKey| {
+            if stored != *key {
+                return Ok(None);
+            }
+            bincode::deserialize_from(&mut reader).map(Some)
+        });
+        match entry {
+            Ok(Some(graph)) => Some(graph),
+            Ok(None) => {
+                warn!(
+                    "Ignoring graph cache entry {} stored for a different key",
+                    path.display()
+                );
+                None
+            }
+            Err(err) => {
+                warn!(
+                    "Ignoring invalid graph cache entry {}: {}",
+                    path.display(),
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// Stores `graph` under `key`. Failing to write the cache does not fail the compile.
+    pub(super) fn store(&self, key: &CacheKey, graph: &CompileGraph) {
+        if let Err(err) = self.try_store(key, graph) {
+            warn!("Could not write graph cache entry: {}", err);
+        }
+    }
+
+    fn try_store(&self, key: &CacheKey, graph: &CompileGraph) -> Result<(), CacheError> {
+        static TEMP_ID: AtomicUsize = AtomicUsize::new(0);
+
+        fs::create_dir_all(&self.dir)?;
+        // Entries are written to a temporary file first so that other compiles sharing the cache
+        // never read a partially written entry
+        let temp_path = self.dir.join(format!(
+            "{:x}.{}.{}.tmp",
+            key.digest(),
+            std::process::id(),
+            TEMP_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let result = (|| -> Result<(), CacheError> {
+            let mut writer = BufWriter::new(File::create(&temp_path)?);
+            bincode::serialize_into(&mut writer, key)?;
+            bincode::serialize_into(&mut writer, graph)?;
+            writer.flush()?;
+            fs::rename(&temp_path, self.entry_path(key))?;
+            Ok(())
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+}
+
+/// The blocks and block entities of a plot. Chunk sections are only stored as their
+/// [content hash](ChunkSection::content_hash).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(super) struct PlotContents {
+    sections: Vec<[u8; 16]>,
+    /// Block entities don't implement `PartialEq`, but their encoding is deterministic
+    block_entities: Vec<(BlockPos, Vec<u8>)>,
+}
+
+impl PlotContents {
+    /// Chunk sections are hashed in parallel
+    pub(super) fn of(plot: &PlotWorld) -> PlotContents {
+        let sections = plot
+            .chunks
+            .par_iter()
+            .flat_map_iter(|chunk| chunk.sections.iter().map(ChunkSection::content_hash))
+            .collect();
+
+        let mut block_entities = Vec::new();
+        for chunk in &plot.chunks {
+            let mut entities: Vec<_> = chunk.block_entities.iter().collect();
+            entities.sort_unstable_by_key(|(pos, _)| (pos.x, pos.y, pos.z));
+            block_entities.extend(
+                entities
+                    .into_iter()
+                    .map(|(&pos, block_entity)| (pos, bincode::serialize(block_entity).unwrap())),
+            );
+        }
+        PlotContents {
+            sections,
+            block_entities,
+        }
+    }
+}
+
+/// Everything the passes depend on: the contents of the plot, pending ticks, observable positions
+/// and the compiler options
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub(super) struct CacheKey {
+    version: u32,
+    crate_version: String,
+    options: CompilerOptions,
+    plot: (i32, i32),
+    contents: PlotContents,
+    ticks: Vec<TickEntry>,
+    observable: Vec<BlockPos>,
+}
+
+impl CacheKey {
+    /// A hash of the key which is stable between builds, used to name the cache entry
+    fn digest(&self) -> md5::Digest {
+        md5::compute(bincode::serialize(self).unwrap())
+    }
+}
+
+/// Builds the cache key of a compile. `contents` must be the contents of `input.plot`.
+pub(super) fn cache_key(
+    input: &CompilerInput<'_>,
+    options: &CompilerOptions,
+    contents: &PlotContents,
+) -> CacheKey {
+    CacheKey {
+        version: CACHE_VERSION,
+        crate_version: env!("CARGO_PKG_VERSION").to_owned(),
+        options: options.clone(),
+        plot: (input.plot.x, input.plot.z),
+        contents: contents.clone(),
+        ticks: input.ticks.to_vec(),
+        observable: input.observable.to_vec(),
+    }
+}
+
+#[test]
+fn reuse_cached_graph() {
+    use crate::blocks::{Block, Lever, LeverFace};
+    use crate::plot::PLOT_WIDTH;
+    use crate::redpiler::Compiler;
+    use crate::world::storage::Chunk;
+    use crate::world::World;
+    use mchprs_blocks::BlockPos;
+
+    let lever_pos = BlockPos::new(1, 1, 1);
+    let lamp_pos = BlockPos::new(2, 1, 1);
+    let chunks = (0..PLOT_WIDTH * PLOT_WIDTH)
+        .map(|i| Chunk::empty(i / PLOT_WIDTH, i % PLOT_WIDTH))
+        .collect();
+    let mut plot = PlotWorld {
+        x: 0,
+        z: 0,
+        chunks,
+        to_be_ticked: Vec::new(),
+    };
+    let lever = Lever::new(LeverFace::Floor, Default::default(), false);
+    plot.set_block(lever_pos, Block::Lever { lever });
+    plot.set_block(lamp_pos, Block::RedstoneLamp { lit: false });
+
+    let dir = std::env::temp_dir().join(format!("redpiler_cache_test_{}", std::process::id()));
+    let mut compiler = Compiler::default();
+    compiler.set_graph_cache(Some(GraphCache::next_to_save(dir.join("p0,0"))));
+
+    let first = compiler.compile(&mut plot, Default::default(), Vec::new());
+    let second = compiler.compile(&mut plot, Default::default(), Vec::new());
+    assert!(!first.from_cache);
+    assert!(second.from_cache);
+    assert_eq!(first.node_count(), second.node_count());
+    assert_eq!(first.backend, second.backend);
+    let handle = compiler.compile_in_background(&plot, Default::default(), Vec::new());
+    let background = compiler.install(&mut plot, handle).unwrap();
+    assert!(background.from_cache);
+
+    // The cached graph must still work
+    compiler.on_use_block(&mut plot, lever_pos).unwrap();
+    compiler.tick(&mut plot).unwrap();
+    assert!(compiler.inspect(lamp_pos).unwrap().powered);
+    compiler.reset(&mut plot);
+
+    // Changing the plot or the options must not use the old graph
+    plot.set_block(BlockPos::new(3, 1, 1), Block::RedstoneLamp { lit: false });
+    let changed = compiler.compile(&mut plot, Default::default(), Vec::new());
+    assert!(!changed.from_cache);
+    assert_eq!(changed.node_count(), 3);
+    let optimized = compiler.compile(&mut plot, CompilerOptions::parse("-O").unwrap(), Vec::new());
+    assert!(!optimized.from_cache);
+
+    // An entry stored for another key under the same name is not used
+    let input = CompilerInput {
+        plot: &plot,
+        ticks: &[],
+        observable: &[],
+        progress: &Default::default(),
+    };
+    let contents = PlotContents::of(&plot);
+    let key = cache_key(&input, &Default::default(), &contents);
+    let other = CacheKey {
+        plot: (1, 0),
+        ..cache_key(&input, &Default::default(), &contents)
+    };
+    let cache = GraphCache::new(dir.join("redpiler_cache"));
+    let mut writer = BufWriter::new(File::create(cache.entry_path(&key)).unwrap());
+    bincode::serialize_into(&mut writer, &other).unwrap();
+    bincode::serialize_into(&mut writer, &CompileGraph::new()).unwrap();
+    drop(writer);
+    assert!(cache.load(&key).is_none());
+    let uncached = compiler.compile(&mut plot, Default::default(), Vec::new());
+    assert!(!uncached.from_cache);
+    assert_eq!(uncached.node_count(), 3);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,14 +1,17 @@
 use crate::blocks::ComparatorMode;
 use mchprs_blocks::BlockPos;
 use petgraph::stable_graph::{NodeIndex, StableGraph};
+use serde::{Deserialize, Serialize};
 
 pub type NodeIdx = NodeIndex;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum NodeType {
     Repeater(u8),
     Torch,
     Comparator(ComparatorMode),
+    /// A comparator without side inputs which can only ever output 0 or 15
+    BinaryComparator,
     Lamp,
     Button,
     Lever,
@@ -16,6 +19,41 @@ pub enum NodeType {
     Trapdoor,
     Wire,
     Constant,
+    /// A chain of non-locking repeaters and torches folded into one node. Every stage after the
+    /// first only delays the output of the first stage. The individual components are stored in
+    /// [`CompileNode::stages`].
+    DelayLine {
+        /// The summed delay of all stages
+        delay: u32,
+        /// True if the chain contains an odd number of torches
+        inverted: bool,
+    },
+    /// A torch with multiple inputs, which is a NOR gate
+    Not {
+        delay: u8,
+    },
+    /// A torch OR gate: a torch with multiple inputs powers a torch which inverts it. Both torches
+    /// are stored in [`CompileNode::stages`].
+    Or {
+        delay: u8,
+    },
+    /// A torch AND gate: every input goes into its own torch and the torches power an output
+    /// torch. The torches are stored in [`CompileNode::stages`].
+    And {
+        delay: u8,
+    },
+    /// A comparator XOR gate: two subtract mode comparators which subtract the inputs from each
+    /// other. The comparators are stored in [`CompileNode::stages`] in the order they tick in, the
+    /// node keeps the block of one of them.
+    Xor {
+        delay: u8,
+    },
+    /// A repeater-locked latch: a repeater which is locked by other repeaters. The locking
+    /// repeaters and the locked repeater are stored in [`CompileNode::stages`], the inputs of the
+    /// locking repeaters are side inputs of the node.
+    Latch {
+        delay: u8,
+    },
 }
 
 impl NodeType {
@@ -24,7 +62,7 @@ impl NodeType {
     }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct NodeState {
     pub powered: bool,
     pub repeater_locked: bool,
@@ -64,7 +102,42 @@ impl NodeState {
     }
 }
 
-#[derive(Debug)]
+/// Where a [`Stage`] gets its input from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StageSource {
+    /// Every default input of the node, using the weight of the link in the graph
+    Node,
+    /// A single input of the node. The node must have a link from this source so that it gets
+    /// updated, passes that remove nodes must keep these up to date.
+    Input(NodeIdx),
+    /// Another stage of the same node
+    Stage(usize),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct StageInput {
+    pub source: StageSource,
+    pub ty: LinkType,
+    /// Ignored for `StageSource::Node`
+    pub ss: u8,
+}
+
+/// A component which is simulated inside of a node that was built from several components, such
+/// as a [`NodeType::DelayLine`] or a [`NodeType::And`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stage {
+    /// `Repeater`, `Torch` or `Comparator`
+    pub ty: NodeType,
+    pub facing_diode: bool,
+    pub state: NodeState,
+    pub inputs: Vec<StageInput>,
+    /// The output of the node is the strongest output of all output stages
+    pub output: bool,
+    /// `None` if the stage uses the block of the node itself
+    pub block: Option<(BlockPos, u32)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompileNode {
     pub ty: NodeType,
     pub block: Option<(BlockPos, u32)>,
@@ -72,15 +145,17 @@ pub struct CompileNode {
 
     pub facing_diode: bool,
     pub comparator_far_input: Option<u8>,
+    /// The components of nodes built from several components, empty for everything else
+    pub stages: Vec<Stage>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum LinkType {
     Default,
     Side,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompileLink {
     pub ty: LinkType,
     pub ss: u8,
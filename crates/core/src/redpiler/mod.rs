@@ -1,16 +1,33 @@
 mod backend;
+mod background;
+mod breakpoints;
+mod cache;
 mod compile_graph;
 // mod debug_graph;
 mod passes;
+mod report;
 
 use crate::blocks::Block;
 use crate::plot::PlotWorld;
 use crate::world::World;
+pub use backend::FinalGraphStats;
 use backend::JITBackend;
+pub use backend::{BackendError, InspectedLink, NodeInspection, NodeSnapshot, TickRunStats};
+use background::CompiledBackend;
+pub use background::{CompileHandle, CompileProgress};
+pub use breakpoints::{BreakCondition, BreakpointHit};
+pub use cache::GraphCache;
+use cache::PlotContents;
+pub use compile_graph::{LinkType, NodeType};
 use mchprs_blocks::BlockPos;
 use mchprs_world::TickEntry;
 use passes::DEFAULT_PASS_MANAGER;
+pub use report::{CompileReport, PassReport};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::thread;
 use std::time::Instant;
+use thiserror::Error;
 use tracing::{debug, error, trace, warn};
 
 fn bool_to_ss(b: bool) -> u8 {
@@ -35,27 +52,130 @@ fn block_powered_mut(block: &mut Block) -> Option<&mut bool> {
     })
 }
 
-#[derive(Default)]
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum OptionsError {
+    #[error("unrecognized option: {0}")]
+    UnknownFlag(String),
+
+    #[error("option {flag} was given conflicting values")]
+    Conflict { flag: &'static str },
+
+    #[error("invalid value `{value}` for option {flag}, expected `true` or `false`")]
+    BadValue { flag: &'static str, value: String },
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum CompilerError {
+    #[error("redpiler is not active")]
+    Inactive,
+
+    #[error("redpiler is active but is missing a backend")]
+    MissingBackend,
+
+    #[error("the compile was cancelled")]
+    Cancelled,
+
+    #[error("the plot changed while it was being compiled")]
+    PlotChanged,
+
+    #[error(transparent)]
+    Backend(#[from] BackendError),
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct CompilerOptions {
     pub optimize: bool,
     pub export: bool,
     pub io_only: bool,
+    pub keep_dead: bool,
+}
+
+struct OptionInfo {
+    long: &'static str,
+    short: &'static str,
+    description: &'static str,
+    field: fn(&mut CompilerOptions) -> &mut bool,
 }
 
+const OPTIONS: &[OptionInfo] = &[
+    OptionInfo {
+        long: "--optimize",
+        short: "-O",
+        description: "Run optimization passes. Redstone wires are not kept in the graph.",
+        field: |co| &mut co.optimize,
+    },
+    OptionInfo {
+        long: "--export",
+        short: "-E",
+        description: "Export the compiled graph.",
+        field: |co| &mut co.export,
+    },
+    OptionInfo {
+        long: "--io-only",
+        short: "-I",
+        description: "Only update input and output blocks in the world.",
+        field: |co| &mut co.io_only,
+    },
+    OptionInfo {
+        long: "--keep-dead",
+        short: "-K",
+        description:
+            "Keep components that cannot affect any output so the whole world stays up to date.",
+        field: |co| &mut co.keep_dead,
+    },
+];
+
 impl CompilerOptions {
-    pub fn parse(str: &str) -> CompilerOptions {
+    /// Parses a whitespace separated list of options. Each option can be given in its long
+    /// (`--optimize`) or short (`-O`) form. The long form also accepts an explicit value such as
+    /// `--optimize=false`.
+    pub fn parse(str: &str) -> Result<CompilerOptions, OptionsError> {
         let mut co: CompilerOptions = Default::default();
-        let options = str.split_whitespace();
-        for option in options {
-            match option {
-                "--optimize" | "-O" => co.optimize = true,
-                "--export" | "-E" => co.export = true,
-                "--io-only" | "-I" => co.io_only = true,
-                // FIXME: use actual error handling
-                _ => warn!("Unrecognized option: {}", option),
+        // Tracks the options that have been explicitly given so contradictions can be detected
+        let mut given = [None; OPTIONS.len()];
+        for option in str.split_whitespace() {
+            let (name, value) = match option.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name, Some(value)),
+                _ => (option, None),
+            };
+            let Some(idx) = OPTIONS
+                .iter()
+                .position(|info| info.long == name || (value.is_none() && info.short == name))
+            else {
+                return Err(OptionsError::UnknownFlag(option.to_string()));
+            };
+            let info = &OPTIONS[idx];
+
+            let value = match value {
+                None | Some("true") => true,
+                Some("false") => false,
+                Some(value) => {
+                    return Err(OptionsError::BadValue {
+                        flag: info.long,
+                        value: value.to_string(),
+                    })
+                }
+            };
+
+            match given[idx] {
+                Some(prev) if prev != value => {
+                    return Err(OptionsError::Conflict { flag: info.long });
+                }
+                _ => given[idx] = Some(value),
             }
+            *(info.field)(&mut co) = value;
+        }
+        Ok(co)
+    }
+
+    /// Returns a usage string listing every option and its description.
+    pub fn help() -> String {
+        let mut help = String::from("Options:\n");
+        for info in OPTIONS {
+            let flags = format!("{}, {}", info.short, info.long);
+            help.push_str(&format!("  {:<16} {}\n", flags, info.description));
         }
-        co
+        help
     }
 }
 
@@ -64,6 +184,13 @@ pub struct Compiler {
     is_active: bool,
     jit: Option<Box<dyn JITBackend>>,
     options: CompilerOptions,
+    /// Number of ticks since the last compile
+    ticks: u64,
+    /// Registered breakpoints along with the last state of their node
+    breakpoints: Vec<(BreakCondition, NodeSnapshot)>,
+    /// Positions which are kept by optimizations even if they don't affect any output
+    observable: Vec<BlockPos>,
+    cache: Option<GraphCache>,
 }
 
 impl Compiler {
@@ -84,19 +211,28 @@ impl Compiler {
         self.jit = Some(jit);
     }
 
+    /// Sets the cache optimized graphs are loaded from and stored in. Passing `None` disables
+    /// caching, which is the default.
+    pub fn set_graph_cache(&mut self, cache: Option<GraphCache>) {
+        self.cache = cache;
+    }
+
     pub fn compile(
         &mut self,
         plot: &mut PlotWorld,
         options: CompilerOptions,
         ticks: Vec<TickEntry>,
-    ) {
+    ) -> CompileReport {
         debug!("Starting compile");
-        let start = Instant::now();
 
-        self.is_active = true;
-
-        let input = CompilerInput { plot };
-        let graph = DEFAULT_PASS_MANAGER.run_passes(&options, input);
+        let observable = self.observable_positions();
+        let progress = CompileProgress::default();
+        let input = CompilerInput {
+            plot,
+            ticks: &ticks,
+            observable: &observable,
+            progress: &progress,
+        };
 
         // TODO: Remove this once there is proper backend switching
         if self.jit.is_none() {
@@ -105,17 +241,121 @@ impl Compiler {
             self.use_jit(jit);
         }
 
-        if let Some(jit) = &mut self.jit {
-            trace!("Compiling backend");
-            let start = Instant::now();
-            jit.compile(graph, ticks);
-            trace!("Backend compiled in {:?}", start.elapsed());
+        let report = if let Some(jit) = &mut self.jit {
+            // The contents are only needed for the cache key
+            let contents = self.cache.as_ref().map(|_| PlotContents::of(input.plot));
+            let cache = self.cache.as_ref().zip(contents.as_ref());
+            // Nothing can cancel a synchronous compile
+            run_compile(jit.as_mut(), &options, input, cache).unwrap_or_default()
         } else {
             error!("Cannot compile without JIT variant selected");
+            Default::default()
+        };
+
+        self.activate(options);
+        debug!("Compile completed in {:?}", report.total_time);
+        report
+    }
+
+    /// Starts compiling a snapshot of the plot on a new thread, so the caller can keep running
+    /// while the passes walk the plot. Once the returned handle has finished, [`Compiler::install`]
+    /// switches to the compiled backend.
+    ///
+    /// The compile uses a new backend of the kind selected with [`Compiler::use_jit`]. Changes
+    /// made to the plot after this is called are not seen by the compile, so it can only be
+    /// installed if the plot did not change in the meantime.
+    pub fn compile_in_background(
+        &self,
+        plot: &PlotWorld,
+        options: CompilerOptions,
+        ticks: Vec<TickEntry>,
+    ) -> CompileHandle {
+        debug!("Starting background compile");
+
+        let mut jit = match &self.jit {
+            Some(jit) => jit.new_backend(),
+            None => Box::<backend::direct::DirectBackend>::default(),
+        };
+        let plot = plot.clone();
+        let observable = self.observable_positions();
+        let cache = self.cache.clone();
+        let progress = Arc::new(CompileProgress::default());
+        let thread_progress = Arc::clone(&progress);
+        let thread = thread::spawn(move || {
+            let contents = PlotContents::of(&plot);
+            let input = CompilerInput {
+                plot: &plot,
+                ticks: &ticks,
+                observable: &observable,
+                progress: &thread_progress,
+            };
+            let cache = cache.as_ref().map(|cache| (cache, &contents));
+            let report = run_compile(jit.as_mut(), &options, input, cache)?;
+            Some(CompiledBackend {
+                jit,
+                options,
+                report,
+                contents,
+                ticks,
+            })
+        });
+        CompileHandle::new(progress, thread)
+    }
+
+    /// Waits for a background compile to finish and switches to the backend it compiled.
+    ///
+    /// Returns [`CompilerError::PlotChanged`] and discards the compile if the plot no longer
+    /// matches the snapshot the compile was started with. The active backend, if any, keeps
+    /// running in that case. Otherwise the active backend is flushed and reset first, and if the
+    /// plot or the ticks it hands back no longer match the compile, the plot is compiled again
+    /// with those ticks.
+    pub fn install(
+        &mut self,
+        plot: &mut PlotWorld,
+        handle: CompileHandle,
+    ) -> Result<CompileReport, CompilerError> {
+        let compiled = handle.join().ok_or(CompilerError::Cancelled)?;
+        if PlotContents::of(plot) != compiled.contents {
+            debug!("Discarding background compile of a plot that changed");
+            return Err(CompilerError::PlotChanged);
         }
+        if self.is_active {
+            let pending = plot.to_be_ticked.len();
+            self.flush(plot)?;
+            self.reset(plot);
+            let ticks: Vec<TickEntry> = plot.to_be_ticked.drain(pending..).collect();
+            if ticks != compiled.ticks || PlotContents::of(plot) != compiled.contents {
+                debug!("Recompiling background compile after reset");
+                return Ok(self.compile(plot, compiled.options, ticks));
+            }
+        }
+        self.jit = Some(compiled.jit);
+        self.activate(compiled.options);
+        debug!(
+            "Background compile completed in {:?}",
+            compiled.report.total_time
+        );
+        Ok(compiled.report)
+    }
 
+    fn activate(&mut self, options: CompilerOptions) {
+        self.is_active = true;
         self.options = options;
-        debug!("Compile completed in {:?}", start.elapsed());
+        self.ticks = 0;
+        self.refresh_breakpoints();
+    }
+
+    /// Positions that optimizations must keep, including the positions of all breakpoints
+    fn observable_positions(&self) -> Vec<BlockPos> {
+        self.observable
+            .iter()
+            .copied()
+            .chain(
+                self.breakpoints
+                    .iter()
+                    .map(|(condition, _)| condition.pos()),
+            )
+            .collect()
     }
 
     pub fn reset(&mut self, plot: &mut PlotWorld) {
@@ -145,44 +385,414 @@ impl Compiler {
         self.options = Default::default();
     }
 
-    fn backend(&mut self) -> &mut Box<dyn JITBackend> {
-        assert!(
-            self.is_active,
-            "tried to get redpiler backend when inactive"
-        );
-        if let Some(jit) = &mut self.jit {
-            jit
-        } else {
-            panic!("redpiler is active but is missing jit backend");
+    fn backend(&mut self) -> Result<&mut Box<dyn JITBackend>, CompilerError> {
+        if !self.is_active {
+            return Err(CompilerError::Inactive);
         }
+        self.jit.as_mut().ok_or(CompilerError::MissingBackend)
     }
 
-    pub fn tick(&mut self, plot: &mut PlotWorld) {
-        self.backend().tick(plot);
+    /// Runs a single tick. Returns the breakpoints that fired during this tick, if any.
+    pub fn tick(&mut self, plot: &mut PlotWorld) -> Result<Option<BreakpointHit>, CompilerError> {
+        self.backend()?.tick(plot);
+        self.ticks += 1;
+        self.check_breakpoints()
     }
 
-    pub fn on_use_block(&mut self, plot: &mut PlotWorld, pos: BlockPos) {
-        self.backend().on_use_block(plot, pos);
+    /// Runs `ticks` ticks as fast as possible by skipping over ticks in which nothing happens.
+    /// Breakpoints are not checked, use [`Compiler::run_until_break`] to stop on breakpoints.
+    pub fn run_ticks(
+        &mut self,
+        plot: &mut PlotWorld,
+        ticks: u64,
+    ) -> Result<TickRunStats, CompilerError> {
+        let stats = self.backend()?.run_ticks(plot, ticks);
+        self.ticks += ticks;
+        // Don't report changes that happened during the run on the next tick
+        self.refresh_breakpoints();
+        Ok(stats)
     }
 
-    pub fn set_pressure_plate(&mut self, plot: &mut PlotWorld, pos: BlockPos, powered: bool) {
-        self.backend().set_pressure_plate(plot, pos, powered);
+    /// Runs up to `max_ticks` ticks, stopping early when a breakpoint fires.
+    /// Returns `None` if no breakpoint fired.
+    pub fn run_until_break(
+        &mut self,
+        plot: &mut PlotWorld,
+        max_ticks: u64,
+    ) -> Result<Option<BreakpointHit>, CompilerError> {
+        for _ in 0..max_ticks {
+            if let Some(hit) = self.tick(plot)? {
+                return Ok(Some(hit));
+            }
+        }
+        Ok(None)
     }
 
-    pub fn flush(&mut self, plot: &mut PlotWorld) {
-        let io_only = self.options.io_only;
-        self.backend().flush(plot, io_only);
+    /// Returns the number of ticks that have been run since the last compile.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
     }
 
-    pub fn inspect(&mut self, pos: BlockPos) {
-        if let Some(backend) = &mut self.jit {
-            backend.inspect(pos);
-        } else {
-            debug!("cannot inspect when backend is not running");
+    pub fn add_breakpoint(&mut self, condition: BreakCondition) -> Result<(), CompilerError> {
+        let state = self.backend()?.snapshot(condition.pos())?;
+        self.breakpoints.push((condition, state));
+        Ok(())
+    }
+
+    /// Removes a breakpoint. Returns true if the breakpoint was registered.
+    pub fn remove_breakpoint(&mut self, condition: BreakCondition) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|(c, _)| *c != condition);
+        len != self.breakpoints.len()
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Marks a position as observable so it isn't optimized away even if it doesn't affect any
+    /// output. Positions of breakpoints are always observable. Requires recompilation to take
+    /// effect.
+    pub fn add_observable(&mut self, pos: BlockPos) {
+        if !self.observable.contains(&pos) {
+            self.observable.push(pos);
+        }
+    }
+
+    /// Returns true if the position was marked as observable.
+    pub fn remove_observable(&mut self, pos: BlockPos) -> bool {
+        let len = self.observable.len();
+        self.observable.retain(|&p| p != pos);
+        len != self.observable.len()
+    }
+
+    fn check_breakpoints(&mut self) -> Result<Option<BreakpointHit>, CompilerError> {
+        if self.breakpoints.is_empty() {
+            return Ok(None);
+        }
+        let backend = self.jit.as_ref().ok_or(CompilerError::MissingBackend)?;
+        let mut conditions = Vec::new();
+        for (condition, last) in &mut self.breakpoints {
+            let state = backend.snapshot(condition.pos())?;
+            if condition.triggered(*last, state) {
+                conditions.push(*condition);
+            }
+            *last = state;
         }
+        if conditions.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(BreakpointHit {
+            tick: self.ticks,
+            conditions,
+        }))
+    }
+
+    /// Updates the last known state of every breakpoint after a compile. Breakpoints on
+    /// positions that are no longer nodes are removed.
+    fn refresh_breakpoints(&mut self) {
+        let Some(backend) = &self.jit else {
+            return;
+        };
+        self.breakpoints.retain_mut(
+            |(condition, last)| match backend.snapshot(condition.pos()) {
+                Ok(state) => {
+                    *last = state;
+                    true
+                }
+                Err(err) => {
+                    warn!("Removing breakpoint {:?}: {}", condition, err);
+                    false
+                }
+            },
+        );
+    }
+
+    pub fn on_use_block(
+        &mut self,
+        plot: &mut PlotWorld,
+        pos: BlockPos,
+    ) -> Result<(), CompilerError> {
+        Ok(self.backend()?.on_use_block(plot, pos)?)
     }
+
+    pub fn set_pressure_plate(
+        &mut self,
+        plot: &mut PlotWorld,
+        pos: BlockPos,
+        powered: bool,
+    ) -> Result<(), CompilerError> {
+        Ok(self.backend()?.set_pressure_plate(plot, pos, powered)?)
+    }
+
+    pub fn flush(&mut self, plot: &mut PlotWorld) -> Result<(), CompilerError> {
+        let io_only = self.options.io_only;
+        self.backend()?.flush(plot, io_only);
+        Ok(())
+    }
+
+    /// Enables or disables per-tick tracing in the backend. Tick stats are emitted as `debug`
+    /// events inside a `redpiler_tick` span.
+    pub fn set_tick_tracing(&mut self, enabled: bool) -> Result<(), CompilerError> {
+        self.backend()?.set_tracing(enabled);
+        Ok(())
+    }
+
+    /// Logs the state transitions of the node at `pos` as `info` events.
+    pub fn watch(&mut self, pos: BlockPos) -> Result<(), CompilerError> {
+        Ok(self.backend()?.watch(pos)?)
+    }
+
+    pub fn unwatch(&mut self, pos: BlockPos) -> Result<(), CompilerError> {
+        self.backend()?.unwatch(pos);
+        Ok(())
+    }
+
+    /// Returns the state of the node at `pos` for debugging.
+    pub fn inspect(&mut self, pos: BlockPos) -> Result<NodeInspection, CompilerError> {
+        Ok(self.backend()?.inspect(pos)?)
+    }
+}
+
+/// Runs the passes and compiles `jit` with the resulting graph. If a cache is given along with the
+/// contents of the plot, the graph is loaded from it when possible instead of running the passes.
+/// Returns `None` if the compile was cancelled before the backend was compiled.
+fn run_compile(
+    jit: &mut dyn JITBackend,
+    options: &CompilerOptions,
+    input: CompilerInput<'_>,
+    cache: Option<(&GraphCache, &PlotContents)>,
+) -> Option<CompileReport> {
+    let start = Instant::now();
+    let ticks = input.ticks.to_vec();
+    let progress = input.progress;
+    let cache = cache.map(|(cache, contents)| (cache, cache::cache_key(&input, options, contents)));
+
+    let cached = cache.as_ref().and_then(|(cache, key)| cache.load(key));
+    let from_cache = cached.is_some();
+    let (graph, passes) = match cached {
+        Some(graph) => {
+            debug!("Loaded graph from cache");
+            let report = PassReport {
+                name: "GraphCache",
+                time: start.elapsed(),
+                nodes_before: 0,
+                nodes_after: graph.node_count(),
+                edges_before: 0,
+                edges_after: graph.edge_count(),
+            };
+            (graph, vec![report])
+        }
+        None => {
+            let (graph, passes) = DEFAULT_PASS_MANAGER.run_passes(options, input);
+            if progress.is_cancelled() {
+                debug!("Compile cancelled");
+                return None;
+            }
+            if let Some((cache, key)) = &cache {
+                cache.store(key, &graph);
+            }
+            (graph, passes)
+        }
+    };
+
+    trace!("Compiling backend");
+    let backend_start = Instant::now();
+    let backend = jit.compile(graph, ticks);
+    let backend_time = backend_start.elapsed();
+    trace!("Backend compiled in {:?}", backend_time);
+
+    Some(CompileReport {
+        passes,
+        from_cache,
+        backend: Some(backend),
+        backend_time,
+        total_time: start.elapsed(),
+    })
 }
 
 pub struct CompilerInput<'w> {
     pub plot: &'w PlotWorld,
+    /// Ticks pending in the world that will be scheduled in the backend
+    pub ticks: &'w [TickEntry],
+    /// Positions that must be kept even if they don't affect any output
+    pub observable: &'w [BlockPos],
+    /// Progress reported back to the caller, which is also used to cancel the compile
+    pub progress: &'w CompileProgress,
+}
+
+#[test]
+fn parse_compiler_options() {
+    let options = CompilerOptions::parse("-O --io-only").unwrap();
+    assert!(options.optimize && options.io_only && !options.export);
+
+    let options = CompilerOptions::parse("--optimize=true -E --export=true").unwrap();
+    assert!(options.optimize && options.export);
+
+    assert_eq!(
+        CompilerOptions::parse("-O --optimise"),
+        Err(OptionsError::UnknownFlag("--optimise".to_string()))
+    );
+    assert_eq!(
+        CompilerOptions::parse("-O --optimize=false"),
+        Err(OptionsError::Conflict { flag: "--optimize" })
+    );
+    assert_eq!(
+        CompilerOptions::parse("--export=yes"),
+        Err(OptionsError::BadValue {
+            flag: "--export",
+            value: "yes".to_string()
+        })
+    );
+    assert!(CompilerOptions::parse("-O=true").is_err());
+}
+
+#[test]
+fn inactive_compiler_errors() {
+    let mut plot = PlotWorld {
+        x: 0,
+        z: 0,
+        chunks: Vec::new(),
+        to_be_ticked: Vec::new(),
+    };
+    let mut compiler = Compiler::default();
+    assert_eq!(compiler.tick(&mut plot), Err(CompilerError::Inactive));
+    assert_eq!(
+        compiler.on_use_block(&mut plot, BlockPos::zero()),
+        Err(CompilerError::Inactive)
+    );
+}
+
+#[test]
+fn lamp_breakpoint() {
+    use crate::blocks::{Lever, LeverFace};
+    use crate::plot::PLOT_WIDTH;
+    use crate::world::storage::Chunk;
+
+    let lever_pos = BlockPos::new(1, 1, 1);
+    let lamp_pos = BlockPos::new(2, 1, 1);
+    let chunks = (0..PLOT_WIDTH * PLOT_WIDTH)
+        .map(|i| Chunk::empty(i / PLOT_WIDTH, i % PLOT_WIDTH))
+        .collect();
+    let mut plot = PlotWorld {
+        x: 0,
+        z: 0,
+        chunks,
+        to_be_ticked: Vec::new(),
+    };
+    let lever = Lever::new(LeverFace::Floor, Default::default(), false);
+    plot.set_block(lever_pos, Block::Lever { lever });
+    plot.set_block(lamp_pos, Block::RedstoneLamp { lit: false });
+
+    let mut compiler = Compiler::default();
+    compiler.compile(&mut plot, Default::default(), Vec::new());
+    compiler
+        .add_breakpoint(BreakCondition::PoweredOff(lamp_pos))
+        .unwrap();
+    assert_eq!(
+        compiler.add_breakpoint(BreakCondition::Changed(BlockPos::zero())),
+        Err(CompilerError::Backend(BackendError::NoNode(
+            BlockPos::zero()
+        )))
+    );
+
+    compiler.on_use_block(&mut plot, lever_pos).unwrap();
+    assert_eq!(compiler.run_until_break(&mut plot, 10).unwrap(), None);
+    compiler.on_use_block(&mut plot, lever_pos).unwrap();
+    assert_eq!(
+        compiler.run_until_break(&mut plot, 10).unwrap(),
+        Some(BreakpointHit {
+            tick: 13,
+            conditions: vec![BreakCondition::PoweredOff(lamp_pos)],
+        })
+    );
+}
+
+#[test]
+fn background_compile() {
+    use crate::blocks::{Lever, LeverFace};
+    use crate::plot::PLOT_WIDTH;
+    use crate::world::storage::Chunk;
+
+    let lever_pos = BlockPos::new(1, 1, 1);
+    let lamp_pos = BlockPos::new(2, 1, 1);
+    let chunks = (0..PLOT_WIDTH * PLOT_WIDTH)
+        .map(|i| Chunk::empty(i / PLOT_WIDTH, i % PLOT_WIDTH))
+        .collect();
+    let mut plot = PlotWorld {
+        x: 0,
+        z: 0,
+        chunks,
+        to_be_ticked: Vec::new(),
+    };
+    let lever = Lever::new(LeverFace::Floor, Default::default(), false);
+    plot.set_block(lever_pos, Block::Lever { lever });
+    plot.set_block(lamp_pos, Block::RedstoneLamp { lit: false });
+
+    let mut compiler = Compiler::default();
+    let handle = compiler.compile_in_background(&plot, Default::default(), Vec::new());
+    while !handle.is_finished() {
+        thread::yield_now();
+    }
+    assert_eq!(handle.progress().scanned(), 1.0);
+    assert!(handle.progress().current_pass().is_some());
+    let report = compiler.install(&mut plot, handle).unwrap();
+    assert_eq!(report.node_count(), 2);
+    assert!(compiler.is_active());
+
+    compiler.on_use_block(&mut plot, lever_pos).unwrap();
+    compiler.tick(&mut plot).unwrap();
+    compiler.flush(&mut plot).unwrap();
+    assert!(compiler.inspect(lamp_pos).unwrap().powered);
+
+    let handle = compiler.compile_in_background(&plot, Default::default(), Vec::new());
+    handle.cancel();
+    assert_eq!(
+        compiler.install(&mut plot, handle),
+        Err(CompilerError::Cancelled)
+    );
+
+    // The active backend is reset before the new one is installed
+    let handle = compiler.compile_in_background(&plot, Default::default(), Vec::new());
+    compiler.install(&mut plot, handle).unwrap();
+    assert!(compiler.is_active());
+    assert!(compiler.inspect(lever_pos).unwrap().powered);
+
+    // A compile of a plot that changed since is discarded and the active backend keeps running
+    let handle = compiler.compile_in_background(&plot, Default::default(), Vec::new());
+    compiler.on_use_block(&mut plot, lever_pos).unwrap();
+    compiler.flush(&mut plot).unwrap();
+    assert_eq!(
+        compiler.install(&mut plot, handle),
+        Err(CompilerError::PlotChanged)
+    );
+    assert!(compiler.is_active());
+    assert!(!compiler.inspect(lever_pos).unwrap().powered);
+
+    // The lamp turning off is pending in the active backend but not in the compile, so the
+    // plot is compiled again with the ticks handed back by the reset
+    let handle = compiler.compile_in_background(&plot, Default::default(), Vec::new());
+    compiler.install(&mut plot, handle).unwrap();
+    assert!(compiler.is_active());
+    assert!(plot.to_be_ticked.is_empty());
+    assert!(compiler.inspect(lamp_pos).unwrap().powered);
+    compiler.run_ticks(&mut plot, 2).unwrap();
+    assert!(compiler.inspect(lamp_pos).unwrap().powered);
+    compiler.run_ticks(&mut plot, 1).unwrap();
+    assert!(!compiler.inspect(lamp_pos).unwrap().powered);
+
+    // Changes made while nothing is running were not seen by the compile either
+    compiler.flush(&mut plot).unwrap();
+    compiler.reset(&mut plot);
+    let handle = compiler.compile_in_background(&plot, Default::default(), Vec::new());
+    plot.set_block(lamp_pos, Block::RedstoneLamp { lit: true });
+    assert_eq!(
+        compiler.install(&mut plot, handle),
+        Err(CompilerError::PlotChanged)
+    );
+    assert!(!compiler.is_active());
+
+    let handle = compiler.compile_in_background(&plot, Default::default(), Vec::new());
+    compiler.install(&mut plot, handle).unwrap();
+    assert!(compiler.inspect(lamp_pos).unwrap().powered);
 }
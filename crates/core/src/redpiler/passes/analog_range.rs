@@ -0,0 +1,266 @@
+//! # [`AnalogRange`]
+//!
+//! Computes the set of signal strengths every node can possibly output, starting from the current
+//! state of each node and propagating through the graph until nothing changes. Link weights are
+//! taken into account, so a comparator behind a long wire can only output weak signals.
+//!
+//! The sets are then used to:
+//! - Remove links that can never carry any power.
+//! - Replace nodes that can only ever output a single strength with a [`NodeType::Constant`].
+//! - Replace comparators that can only output 0 or 15 with a [`NodeType::BinaryComparator`].
+
+use super::Pass;
+use crate::blocks::ComparatorMode;
+use crate::redpiler::compile_graph::{CompileGraph, LinkType, NodeIdx, NodeType};
+use crate::redpiler::{CompilerInput, CompilerOptions};
+use mchprs_blocks::BlockPos;
+use petgraph::visit::{EdgeRef, NodeIndexable};
+use petgraph::Direction;
+use std::collections::HashSet;
+use tracing::trace;
+
+/// A set of signal strengths. Bit `n` is set if strength `n` is possible.
+type Strengths = u16;
+
+const OFF: Strengths = 1;
+const ON: Strengths = 1 << 15;
+const ANY: Strengths = Strengths::MAX;
+
+pub struct AnalogRange;
+
+impl Pass for AnalogRange {
+    fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, input: &CompilerInput<'_>) {
+        let ranges = analyze(graph, input);
+
+        let mut removed_links = 0;
+        let mut edges = graph.edge_indices().collect::<Vec<_>>();
+        edges.retain(|&edge| {
+            let (source, _) = graph.edge_endpoints(edge).unwrap();
+            attenuate(ranges[source.index()], graph[edge].ss) == OFF
+        });
+        for edge in edges {
+            graph.remove_edge(edge);
+            removed_links += 1;
+        }
+
+        let mut constants = 0;
+        let mut binary_comparators = 0;
+        for idx in graph.node_indices().collect::<Vec<_>>() {
+            let range = ranges[idx.index()];
+            let node = &graph[idx];
+            match node.ty {
+                NodeType::Repeater(_)
+                | NodeType::Torch
+                | NodeType::Comparator(_)
+                | NodeType::BinaryComparator
+                | NodeType::Wire
+                    if range.count_ones() == 1 =>
+                {
+                    let node = &mut graph[idx];
+                    node.ty = NodeType::Constant;
+                    node.state.output_strength = range.trailing_zeros() as u8;
+
+                    let mut incoming = graph.neighbors_directed(idx, Direction::Incoming).detach();
+                    while let Some(edge) = incoming.next_edge(graph) {
+                        graph.remove_edge(edge);
+                    }
+                    constants += 1;
+                }
+                NodeType::Comparator(_)
+                    if range & !(OFF | ON) == 0
+                        && node.comparator_far_input.is_none()
+                        && graph
+                            .edges_directed(idx, Direction::Incoming)
+                            .all(|edge| edge.weight().ty == LinkType::Default) =>
+                {
+                    // Without side inputs or a far input override, a comparator with a binary
+                    // output can only ever have received 0 or 15 from its inputs.
+                    graph[idx].ty = NodeType::BinaryComparator;
+                    binary_comparators += 1;
+                }
+                _ => {}
+            }
+        }
+
+        trace!(
+            "Removed {} links, folded {} constants and found {} binary comparators",
+            removed_links,
+            constants,
+            binary_comparators
+        );
+    }
+}
+
+fn analyze(graph: &CompileGraph, input: &CompilerInput<'_>) -> Vec<Strengths> {
+    let pending: HashSet<BlockPos> = input.ticks.iter().map(|entry| entry.pos).collect();
+
+    let mut ranges = vec![0; graph.node_bound()];
+    for idx in graph.node_indices() {
+        let node = &graph[idx];
+        ranges[idx.index()] = match node.block {
+            // The outcome of a pending tick isn't known, repeaters in particular can turn on
+            // without any input.
+            Some((pos, _)) if pending.contains(&pos) => ANY,
+            _ => 1 << node.state.output_strength,
+        };
+    }
+
+    loop {
+        let mut changed = false;
+        for idx in graph.node_indices() {
+            let new = ranges[idx.index()] | transfer(graph, idx, &ranges);
+            if new != ranges[idx.index()] {
+                ranges[idx.index()] = new;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    ranges
+}
+
+/// Returns the strengths a node could output given the possible outputs of its inputs
+fn transfer(graph: &CompileGraph, idx: NodeIdx, ranges: &[Strengths]) -> Strengths {
+    let node = &graph[idx];
+    let default = input_range(graph, idx, ranges, LinkType::Default);
+    let binary = |powered_when_on: bool| {
+        let mut range = 0;
+        if default & OFF != 0 {
+            range |= if powered_when_on { OFF } else { ON };
+        }
+        if default & !OFF != 0 {
+            range |= if powered_when_on { ON } else { OFF };
+        }
+        range
+    };
+
+    match node.ty {
+        NodeType::Constant => 1 << node.state.output_strength,
+        NodeType::Repeater(_)
+        | NodeType::BinaryComparator
+        | NodeType::Lamp
+        | NodeType::Trapdoor => binary(true),
+        NodeType::Torch | NodeType::Not { .. } => binary(false),
+        NodeType::Wire => default,
+        NodeType::Comparator(mode) => {
+            let side = input_range(graph, idx, ranges, LinkType::Side);
+            let mut range = 0;
+            for input in strengths(default) {
+                let input = match node.comparator_far_input {
+                    Some(far_override) if input < 15 => far_override,
+                    _ => input,
+                };
+                for side in strengths(side) {
+                    let output = match mode {
+                        ComparatorMode::Compare if input >= side => input,
+                        ComparatorMode::Compare => 0,
+                        ComparatorMode::Subtract => input.saturating_sub(side),
+                    };
+                    range |= 1 << output;
+                }
+            }
+            range
+        }
+        NodeType::Button
+        | NodeType::Lever
+        | NodeType::PressurePlate
+        | NodeType::DelayLine { .. }
+        | NodeType::Or { .. }
+        | NodeType::And { .. }
+        | NodeType::Latch { .. } => OFF | ON,
+        NodeType::Xor { .. } => ANY,
+    }
+}
+
+/// Returns the strengths the strongest input of the given link type could have
+fn input_range(
+    graph: &CompileGraph,
+    idx: NodeIdx,
+    ranges: &[Strengths],
+    ty: LinkType,
+) -> Strengths {
+    let mut range = 0;
+    // The strongest input is always at least as strong as the weakest value of every link
+    let mut lower_bound = 0;
+    for edge in graph.edges_directed(idx, Direction::Incoming) {
+        if edge.weight().ty != ty {
+            continue;
+        }
+        let link = attenuate(ranges[edge.source().index()], edge.weight().ss);
+        range |= link;
+        lower_bound = lower_bound.max(link.trailing_zeros());
+    }
+    if range == 0 {
+        return OFF;
+    }
+    range & (ANY << lower_bound)
+}
+
+fn attenuate(range: Strengths, weight: u8) -> Strengths {
+    strengths(range).fold(0, |acc, ss| acc | 1 << ss.saturating_sub(weight))
+}
+
+fn strengths(range: Strengths) -> impl Iterator<Item = u8> {
+    (0..16).filter(move |ss| range & (1 << ss) != 0)
+}
+
+#[test]
+fn analog_range_folding() {
+    use crate::plot::PlotWorld;
+    use crate::redpiler::compile_graph::{CompileLink, CompileNode, NodeState};
+
+    let mut graph = CompileGraph::new();
+    let mut add = |ty: NodeType| {
+        graph.add_node(CompileNode {
+            ty,
+            block: None,
+            state: NodeState::simple(false),
+            facing_diode: false,
+            comparator_far_input: None,
+            stages: Vec::new(),
+        })
+    };
+    let lever = add(NodeType::Lever);
+    let weak = add(NodeType::Comparator(ComparatorMode::Compare));
+    let weaker = add(NodeType::Comparator(ComparatorMode::Subtract));
+    let binary = add(NodeType::Comparator(ComparatorMode::Compare));
+    let lamp = add(NodeType::Lamp);
+    let dead_lamp = add(NodeType::Lamp);
+    // The first comparator can output at most 12, the second at most 7
+    graph.add_edge(lever, weak, CompileLink::default(3));
+    graph.add_edge(weak, weaker, CompileLink::default(5));
+    graph.add_edge(weaker, dead_lamp, CompileLink::default(7));
+    graph.add_edge(weaker, lamp, CompileLink::default(6));
+    graph.add_edge(lever, binary, CompileLink::default(0));
+    graph.add_edge(binary, lamp, CompileLink::default(0));
+
+    let plot = PlotWorld {
+        x: 0,
+        z: 0,
+        chunks: Vec::new(),
+        to_be_ticked: Vec::new(),
+    };
+    let input = CompilerInput {
+        plot: &plot,
+        ticks: &[],
+        observable: &[],
+        progress: &Default::default(),
+    };
+    AnalogRange.run_pass(&mut graph, &Default::default(), &input);
+
+    assert_eq!(graph.find_edge(weaker, dead_lamp), None);
+    assert!(graph.find_edge(weaker, lamp).is_some());
+    assert_eq!(
+        graph[weak].ty,
+        NodeType::Comparator(ComparatorMode::Compare)
+    );
+    assert_eq!(graph[binary].ty, NodeType::BinaryComparator);
+    // Nothing can power the dead lamp
+    assert_eq!(graph[dead_lamp].ty, NodeType::Lamp);
+    assert_eq!(
+        graph.edges_directed(dead_lamp, Direction::Incoming).count(),
+        0
+    );
+}
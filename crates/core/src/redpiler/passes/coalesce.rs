@@ -1,13 +1,19 @@
 use super::Pass;
+use crate::blocks::ComparatorMode;
 use crate::redpiler::compile_graph::{CompileGraph, LinkType, NodeIdx, NodeType};
 use crate::redpiler::{CompilerInput, CompilerOptions};
+use mchprs_blocks::BlockPos;
 use petgraph::visit::{EdgeRef, NodeIndexable};
 use petgraph::Direction;
+use std::collections::{HashMap, HashSet};
 
 pub struct Coalesce;
 
 impl Pass for Coalesce {
-    fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, _: &CompilerInput<'_>) {
+    fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, input: &CompilerInput<'_>) {
+        // Merging comparators can make the comparators they output into identical as well
+        while coalesce_comparators(graph, input) {}
+
         for i in 0..graph.node_bound() {
             let idx = NodeIdx::new(i);
             if !graph.contains_node(idx) {
@@ -16,8 +22,12 @@ impl Pass for Coalesce {
 
             let node = &graph[idx];
             // Comparators depend on the link weight as well as the type,
-            // we could implement that later if it's beneficial enough.
-            if matches!(node.ty, NodeType::Comparator(_)) || node.ty.is_output() {
+            // so they are handled separately by `coalesce_comparators`.
+            // Nodes built from several components also depend on their stages.
+            if matches!(node.ty, NodeType::Comparator(_))
+                || node.ty.is_output()
+                || !node.stages.is_empty()
+            {
                 continue;
             }
 
@@ -74,3 +84,133 @@ fn coalesce(graph: &mut CompileGraph, node: NodeIdx, into: NodeIdx) {
     }
     graph.remove_node(node);
 }
+
+/// Everything the behaviour of a comparator depends on
+#[derive(PartialEq, Eq, Hash)]
+struct ComparatorKey {
+    mode: ComparatorMode,
+    facing_diode: bool,
+    far_input: Option<u8>,
+    output_strength: u8,
+    /// Sorted list of source, link type and weight
+    inputs: Vec<(NodeIdx, bool, u8)>,
+}
+
+/// Merges comparators which have exactly the same inputs, mode and state.
+/// Returns true if anything was merged.
+fn coalesce_comparators(graph: &mut CompileGraph, input: &CompilerInput<'_>) -> bool {
+    // Nodes with pending ticks or observable nodes must stay separate
+    let keep: HashSet<BlockPos> = input
+        .ticks
+        .iter()
+        .map(|entry| entry.pos)
+        .chain(input.observable.iter().copied())
+        .collect();
+    let mut seen: HashMap<ComparatorKey, NodeIdx> = HashMap::new();
+    let mut merged = false;
+
+    for i in 0..graph.node_bound() {
+        let idx = NodeIdx::new(i);
+        if !graph.contains_node(idx) {
+            continue;
+        }
+
+        let node = &graph[idx];
+        let NodeType::Comparator(mode) = node.ty else {
+            continue;
+        };
+        // The tick would be lost if this node was merged away, and observable nodes must still
+        // be there to be inspected
+        if matches!(node.block, Some((pos, _)) if keep.contains(&pos)) {
+            continue;
+        }
+
+        let mut inputs: Vec<_> = graph
+            .edges_directed(idx, Direction::Incoming)
+            .map(|edge| {
+                let link = edge.weight();
+                (edge.source(), link.ty == LinkType::Side, link.ss)
+            })
+            .collect();
+        // Comparators without inputs are handled by constant folding
+        if inputs.is_empty() {
+            continue;
+        }
+        inputs.sort_unstable();
+        inputs.dedup();
+
+        let key = ComparatorKey {
+            mode,
+            facing_diode: node.facing_diode,
+            far_input: node.comparator_far_input,
+            output_strength: node.state.output_strength,
+            inputs,
+        };
+        match seen.get(&key) {
+            Some(&into) => {
+                coalesce(graph, idx, into);
+                merged = true;
+            }
+            None => {
+                seen.insert(key, idx);
+            }
+        }
+    }
+    merged
+}
+
+#[test]
+fn coalesce_identical_comparators() {
+    use crate::plot::PlotWorld;
+    use crate::redpiler::compile_graph::{CompileLink, CompileNode, NodeState};
+
+    let mut graph = CompileGraph::new();
+    let mut add = |ty: NodeType| {
+        graph.add_node(CompileNode {
+            ty,
+            block: None,
+            state: NodeState::simple(false),
+            facing_diode: false,
+            comparator_far_input: None,
+            stages: Vec::new(),
+        })
+    };
+    let lever = add(NodeType::Lever);
+    let side = add(NodeType::Lever);
+    let comparators = [(); 4].map(|_| add(NodeType::Comparator(ComparatorMode::Subtract)));
+    let lamps = [(); 4].map(|_| add(NodeType::Lamp));
+    for (i, (&comparator, &lamp)) in comparators.iter().zip(&lamps).enumerate() {
+        // The last comparator has a different link weight
+        graph.add_edge(lever, comparator, CompileLink::default(i as u8 / 3));
+        graph.add_edge(side, comparator, CompileLink::side(1));
+        graph.add_edge(comparator, lamp, CompileLink::default(0));
+    }
+    // Observable comparators are never merged
+    let observed = BlockPos::new(2, 0, 0);
+    graph[comparators[2]].block = Some((observed, 0));
+
+    let plot = PlotWorld {
+        x: 0,
+        z: 0,
+        chunks: Vec::new(),
+        to_be_ticked: Vec::new(),
+    };
+    let input = CompilerInput {
+        plot: &plot,
+        ticks: &[],
+        observable: &[observed],
+        progress: &Default::default(),
+    };
+    Coalesce.run_pass(&mut graph, &Default::default(), &input);
+
+    assert!(graph.contains_node(comparators[0]));
+    assert!(!graph.contains_node(comparators[1]));
+    assert!(graph.contains_node(comparators[2]));
+    assert!(graph.contains_node(comparators[3]));
+    assert_eq!(
+        graph
+            .neighbors_directed(comparators[0], Direction::Outgoing)
+            .count(),
+        2
+    );
+}
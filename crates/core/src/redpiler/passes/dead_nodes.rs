@@ -0,0 +1,174 @@
+//! # [`DeadNodes`]
+//!
+//! Removes every node that can never affect an output. A node is kept if it can reach a lamp or
+//! trapdoor, or a position marked as observable by the user, by following its outgoing edges.
+//! Inputs are always kept so they can still be interacted with, and nodes with pending ticks are
+//! kept so the backend can still schedule them.
+//!
+//! The blocks of removed nodes are no longer updated in the world, so this pass can be disabled
+//! with `--keep-dead` when the whole world needs to stay accurate.
+
+use super::Pass;
+use crate::redpiler::compile_graph::{CompileGraph, NodeIdx, NodeType};
+use crate::redpiler::{CompilerInput, CompilerOptions};
+use mchprs_blocks::BlockPos;
+use petgraph::visit::NodeIndexable;
+use petgraph::Direction;
+use std::collections::HashSet;
+
+pub struct DeadNodes;
+
+impl Pass for DeadNodes {
+    fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, input: &CompilerInput<'_>) {
+        // Nodes with pending ticks are live as well
+        let live: HashSet<BlockPos> = input
+            .ticks
+            .iter()
+            .map(|entry| entry.pos)
+            .chain(input.observable.iter().copied())
+            .collect();
+
+        let mut alive = vec![false; graph.node_bound()];
+        let mut stack: Vec<NodeIdx> = graph
+            .node_indices()
+            .filter(|&idx| {
+                let node = &graph[idx];
+                node.ty.is_output()
+                    || matches!(
+                        node.ty,
+                        NodeType::Button | NodeType::Lever | NodeType::PressurePlate
+                    )
+                    || matches!(node.block, Some((pos, _)) if live.contains(&pos))
+            })
+            .collect();
+        for &idx in &stack {
+            alive[idx.index()] = true;
+        }
+
+        while let Some(idx) = stack.pop() {
+            for source in graph.neighbors_directed(idx, Direction::Incoming) {
+                if !alive[source.index()] {
+                    alive[source.index()] = true;
+                    stack.push(source);
+                }
+            }
+        }
+
+        graph.retain_nodes(|_, idx| alive[idx.index()]);
+    }
+
+    fn should_run(&self, options: &CompilerOptions) -> bool {
+        options.optimize && !options.keep_dead
+    }
+}
+
+#[test]
+fn remove_nodes_without_outputs() {
+    use crate::plot::PlotWorld;
+    use crate::redpiler::compile_graph::{CompileLink, CompileNode, NodeState};
+
+    let mut graph = CompileGraph::new();
+    let mut add = |ty: NodeType, x: i32| {
+        graph.add_node(CompileNode {
+            ty,
+            block: Some((BlockPos::new(x, 0, 0), 0)),
+            state: NodeState::simple(false),
+            facing_diode: false,
+            comparator_far_input: None,
+            stages: Vec::new(),
+        })
+    };
+    let lever = add(NodeType::Lever, 0);
+    let torch = add(NodeType::Torch, 1);
+    let repeater = add(NodeType::Repeater(1), 2);
+    let lamp = add(NodeType::Lamp, 3);
+    let dead_torch = add(NodeType::Torch, 4);
+    let dead_repeater = add(NodeType::Repeater(1), 5);
+    let observed = add(NodeType::Repeater(1), 6);
+    graph.add_edge(lever, torch, CompileLink::default(0));
+    graph.add_edge(torch, repeater, CompileLink::default(0));
+    graph.add_edge(repeater, lamp, CompileLink::default(0));
+    graph.add_edge(torch, dead_torch, CompileLink::default(0));
+    graph.add_edge(dead_torch, dead_repeater, CompileLink::default(0));
+    graph.add_edge(dead_torch, observed, CompileLink::default(0));
+    graph.add_edge(dead_repeater, dead_torch, CompileLink::default(0));
+
+    let plot = PlotWorld {
+        x: 0,
+        z: 0,
+        chunks: Vec::new(),
+        to_be_ticked: Vec::new(),
+    };
+    let mut input = CompilerInput {
+        plot: &plot,
+        ticks: &[],
+        observable: &[],
+        progress: &Default::default(),
+    };
+    let mut pruned = graph.clone();
+    DeadNodes.run_pass(&mut pruned, &Default::default(), &input);
+    assert_eq!(pruned.node_count(), 4);
+    assert!(!pruned.contains_node(dead_torch));
+    assert!(!pruned.contains_node(dead_repeater));
+    assert!(!pruned.contains_node(observed));
+
+    let observable = [BlockPos::new(6, 0, 0)];
+    input.observable = &observable;
+    DeadNodes.run_pass(&mut graph, &Default::default(), &input);
+    // The repeater is kept as well because it feeds back into the torch
+    assert_eq!(graph.node_count(), 7);
+    assert!(graph.contains_node(dead_torch));
+    assert!(graph.contains_node(dead_repeater));
+}
+
+#[test]
+fn keep_nodes_with_pending_ticks() {
+    use crate::plot::PlotWorld;
+    use crate::redpiler::compile_graph::{CompileLink, CompileNode, NodeState};
+    use mchprs_world::{TickEntry, TickPriority};
+
+    let mut graph = CompileGraph::new();
+    let mut add = |ty: NodeType, x: i32| {
+        graph.add_node(CompileNode {
+            ty,
+            block: Some((BlockPos::new(x, 0, 0), 0)),
+            state: NodeState::simple(false),
+            facing_diode: false,
+            comparator_far_input: None,
+            stages: Vec::new(),
+        })
+    };
+    let lever = add(NodeType::Lever, 0);
+    let lamp = add(NodeType::Lamp, 1);
+    let torch = add(NodeType::Torch, 2);
+    let repeater = add(NodeType::Repeater(2), 3);
+    let dead_repeater = add(NodeType::Repeater(1), 4);
+    graph.add_edge(lever, lamp, CompileLink::default(0));
+    graph.add_edge(lever, torch, CompileLink::default(0));
+    graph.add_edge(torch, repeater, CompileLink::default(0));
+    graph.add_edge(lever, dead_repeater, CompileLink::default(0));
+
+    let plot = PlotWorld {
+        x: 0,
+        z: 0,
+        chunks: Vec::new(),
+        to_be_ticked: Vec::new(),
+    };
+    // The repeater has no path to an output, but is about to turn on
+    let ticks = [TickEntry {
+        ticks_left: 2,
+        tick_priority: TickPriority::High,
+        pos: BlockPos::new(3, 0, 0),
+    }];
+    let input = CompilerInput {
+        plot: &plot,
+        ticks: &ticks,
+        observable: &[],
+        progress: &Default::default(),
+    };
+    DeadNodes.run_pass(&mut graph, &Default::default(), &input);
+    assert_eq!(graph.node_count(), 4);
+    assert!(graph.contains_node(repeater));
+    assert!(graph.contains_node(torch));
+    assert!(!graph.contains_node(dead_repeater));
+}
@@ -0,0 +1,333 @@
+//! # [`DelayLineFold`]
+//!
+//! This pass folds chains of non-locking repeaters and torches into a single
+//! [`NodeType::DelayLine`] node. Every stage except the last must have a single output which goes
+//! into the next stage, and every stage except the first must have a single input.
+//!
+//! Only the first stage of a delay line shortens and extends pulses, which leaves every pulse and
+//! gap at least as long as its delay. A later stage with a shorter delay passes such a signal on
+//! unchanged, so the backend only simulates the first stage and sends its changes down the line
+//! after the summed delay of the other stages. A stage with a longer delay, or an equal delay
+//! where it would tick after its input changes, starts a new delay line instead.
+//!
+//! Nodes with pending ticks are never folded, as the backend would have no way of scheduling
+//! them. Observable nodes are kept as well so they can still be inspected.
+
+use super::Pass;
+use crate::redpiler::compile_graph::{
+    CompileGraph, CompileNode, LinkType, NodeIdx, NodeType, Stage, StageInput, StageSource,
+};
+use crate::redpiler::{CompilerInput, CompilerOptions};
+use mchprs_blocks::BlockPos;
+use mchprs_world::TickPriority;
+use petgraph::visit::{EdgeRef, NodeIndexable};
+use petgraph::Direction;
+use std::collections::HashSet;
+
+pub struct DelayLineFold;
+
+impl Pass for DelayLineFold {
+    fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, input: &CompilerInput<'_>) {
+        // Nodes with pending ticks or observable nodes must stay separate
+        let keep: HashSet<BlockPos> = input
+            .ticks
+            .iter()
+            .map(|entry| entry.pos)
+            .chain(input.observable.iter().copied())
+            .collect();
+
+        for i in 0..graph.node_bound() {
+            let idx = NodeIdx::new(i);
+            if !graph.contains_node(idx) {
+                continue;
+            }
+
+            // Only start walking from the head of a chain
+            if !is_stage(graph, idx, &keep) || previous_stage(graph, idx, &keep).is_some() {
+                continue;
+            }
+
+            let mut chain = vec![idx];
+            while let Some(next) = next_stage(graph, chain[chain.len() - 1], &keep) {
+                chain.push(next);
+            }
+
+            let mut start = 0;
+            while start < chain.len() {
+                let width = stage_delay(&graph[chain[start]]);
+                let mut end = start + 1;
+                while end < chain.len()
+                    && only_delays(&graph[chain[end - 1]], &graph[chain[end]], width)
+                {
+                    end += 1;
+                }
+                if end - start > 1 {
+                    fold(graph, &chain[start..end]);
+                }
+                start = end;
+            }
+        }
+    }
+}
+
+fn is_stage(graph: &CompileGraph, idx: NodeIdx, keep: &HashSet<BlockPos>) -> bool {
+    let node = &graph[idx];
+    let non_locking = match node.ty {
+        NodeType::Repeater(_) => graph
+            .edges_directed(idx, Direction::Incoming)
+            .all(|edge| edge.weight().ty == LinkType::Default),
+        NodeType::Torch => true,
+        _ => false,
+    };
+    non_locking && matches!(node.block, Some((pos, _)) if !keep.contains(&pos))
+}
+
+fn next_stage(graph: &CompileGraph, idx: NodeIdx, keep: &HashSet<BlockPos>) -> Option<NodeIdx> {
+    let mut outgoing = graph.edges_directed(idx, Direction::Outgoing);
+    let edge = outgoing.next()?;
+    if outgoing.next().is_some() || edge.weight().ty != LinkType::Default {
+        return None;
+    }
+
+    let next = edge.target();
+    let single_input = graph.edges_directed(next, Direction::Incoming).count() == 1;
+    (single_input && is_stage(graph, next, keep)).then_some(next)
+}
+
+fn previous_stage(graph: &CompileGraph, idx: NodeIdx, keep: &HashSet<BlockPos>) -> Option<NodeIdx> {
+    let mut incoming = graph.neighbors_directed(idx, Direction::Incoming);
+    let source = incoming.next()?;
+    if incoming.next().is_some() || !is_stage(graph, source, keep) {
+        return None;
+    }
+    (next_stage(graph, source, keep) == Some(idx)).then_some(source)
+}
+
+fn stage_delay(node: &CompileNode) -> u32 {
+    match node.ty {
+        NodeType::Repeater(delay) => delay as u32,
+        _ => 1,
+    }
+}
+
+/// The priority of the tick in which the stage changes to `powered`
+fn tick_priority(node: &CompileNode, powered: bool) -> TickPriority {
+    match node.ty {
+        NodeType::Repeater(_) if node.facing_diode => TickPriority::Highest,
+        NodeType::Repeater(_) if powered => TickPriority::High,
+        NodeType::Repeater(_) => TickPriority::Higher,
+        _ => TickPriority::Normal,
+    }
+}
+
+/// Returns true if `stage` only delays the output of `previous`, whose pulses and gaps are at
+/// least `width` ticks long.
+fn only_delays(previous: &CompileNode, stage: &CompileNode, width: u32) -> bool {
+    let delay = stage_delay(stage);
+    if delay != width {
+        return delay < width;
+    }
+    // When the input changes again in the tick the stage changes in, the stage has to tick first.
+    // Ticks of the same priority run in the order they were scheduled, which puts the stage first.
+    match stage.ty {
+        // Repeaters turn on regardless of their input, only gaps can be swallowed
+        NodeType::Repeater(_) => tick_priority(stage, false) <= tick_priority(previous, true),
+        _ => [false, true]
+            .into_iter()
+            .all(|powered| tick_priority(stage, powered) <= tick_priority(previous, powered)),
+    }
+}
+
+fn fold(graph: &mut CompileGraph, chain: &[NodeIdx]) {
+    let (&last, rest) = chain.split_last().unwrap();
+    let stages: Vec<Stage> = chain
+        .iter()
+        .enumerate()
+        .map(|(i, &idx)| {
+            let node = &graph[idx];
+            let source = match i {
+                0 => StageSource::Node,
+                _ => StageSource::Stage(i - 1),
+            };
+            Stage {
+                ty: node.ty,
+                facing_diode: node.facing_diode,
+                state: node.state.clone(),
+                inputs: vec![StageInput {
+                    source,
+                    ty: LinkType::Default,
+                    ss: 0,
+                }],
+                output: idx == last,
+                block: if idx == last { None } else { node.block },
+            }
+        })
+        .collect();
+    let delay = chain.iter().map(|&idx| stage_delay(&graph[idx])).sum();
+    let inverted = stages.iter().filter(|s| s.ty == NodeType::Torch).count() % 2 == 1;
+
+    // The inputs of the first stage become the inputs of the delay line
+    let mut incoming = graph
+        .neighbors_directed(chain[0], Direction::Incoming)
+        .detach();
+    while let Some((edge_idx, source)) = incoming.next(graph) {
+        let weight = graph.remove_edge(edge_idx).unwrap();
+        graph.add_edge(source, last, weight);
+    }
+    for &idx in rest {
+        graph.remove_node(idx);
+    }
+
+    let node = &mut graph[last];
+    node.ty = NodeType::DelayLine { delay, inverted };
+    node.stages = stages;
+}
+
+#[test]
+fn folded_chain_matches_unfolded() {
+    use crate::blocks::{Block, Lever, RedstoneRepeater};
+    use crate::plot::{PlotWorld, PLOT_WIDTH};
+    use crate::redpiler::backend::direct::DirectBackend;
+    use crate::redpiler::backend::JITBackend;
+    use crate::redpiler::compile_graph::{CompileLink, NodeState};
+    use crate::world::storage::Chunk;
+    use crate::world::World;
+
+    let lever_pos = BlockPos::new(0, 0, 0);
+    let lamp_pos = BlockPos::new(10, 0, 0);
+    let build = || {
+        let mut graph = CompileGraph::new();
+        let mut add = |x: i32, ty: NodeType, block: Block, powered: bool| {
+            graph.add_node(CompileNode {
+                ty,
+                block: Some((BlockPos::new(x, 0, 0), block.get_id())),
+                state: NodeState::simple(powered),
+                // The repeaters in front of another repeater
+                facing_diode: matches!(x, 5 | 6),
+                comparator_far_input: None,
+                stages: Vec::new(),
+            })
+        };
+        let repeater = |delay: u8, powered: bool| Block::RedstoneRepeater {
+            repeater: RedstoneRepeater {
+                delay,
+                powered,
+                ..Default::default()
+            },
+        };
+        let torch = |lit: bool| Block::RedstoneTorch { lit };
+        let lever = Block::Lever {
+            lever: Lever::default(),
+        };
+        let chain = [
+            add(0, NodeType::Lever, lever, false),
+            add(1, NodeType::Repeater(2), repeater(2, false), false),
+            add(2, NodeType::Torch, torch(true), true),
+            add(3, NodeType::Repeater(1), repeater(1, true), true),
+            add(4, NodeType::Torch, torch(false), false),
+            // Ticks before the torch changes again, so this still only delays
+            add(5, NodeType::Repeater(2), repeater(2, false), false),
+            // Extends pulses, so this starts a new delay line
+            add(6, NodeType::Repeater(3), repeater(3, false), false),
+            add(7, NodeType::Repeater(1), repeater(1, false), false),
+            add(8, NodeType::Torch, torch(true), true),
+            add(9, NodeType::Torch, torch(false), false),
+            add(
+                10,
+                NodeType::Lamp,
+                Block::RedstoneLamp { lit: false },
+                false,
+            ),
+        ];
+        for pair in chain.windows(2) {
+            graph.add_edge(pair[0], pair[1], CompileLink::default(0));
+        }
+        graph
+    };
+
+    let new_plot = || PlotWorld {
+        x: 0,
+        z: 0,
+        chunks: (0..PLOT_WIDTH * PLOT_WIDTH)
+            .map(|i| Chunk::empty(i / PLOT_WIDTH, i % PLOT_WIDTH))
+            .collect(),
+        to_be_ticked: Vec::new(),
+    };
+    let mut unfolded_plot = new_plot();
+    let mut folded_plot = new_plot();
+    let mut folded_graph = build();
+    let input = CompilerInput {
+        plot: &folded_plot,
+        ticks: &[],
+        observable: &[],
+        progress: &Default::default(),
+    };
+    DelayLineFold.run_pass(&mut folded_graph, &Default::default(), &input);
+    // Lever, two delay lines and lamp
+    assert_eq!(folded_graph.node_count(), 4);
+
+    let mut unfolded = DirectBackend::default();
+    unfolded.compile(build(), Vec::new());
+    let mut folded = DirectBackend::default();
+    folded.compile(folded_graph, Vec::new());
+    assert_eq!(
+        folded.inspect(BlockPos::new(5, 0, 0)).unwrap().ty,
+        NodeType::DelayLine {
+            delay: 7,
+            inverted: false
+        }
+    );
+    assert_eq!(
+        folded.inspect(BlockPos::new(9, 0, 0)).unwrap().ty,
+        NodeType::DelayLine {
+            delay: 6,
+            inverted: false
+        }
+    );
+
+    // Short pulses get shortened and extended by the first stage of each delay line
+    let toggles = [
+        0, 1, 2, 5, 6, 7, 9, 10, 20, 21, 23, 40, 41, 42, 43, 44, 60, 62, 64, 70,
+    ];
+    let mut lit_ticks = 0;
+    // Stops with pulses still travelling down the lines
+    for tick in 0..73 {
+        for (backend, plot) in [
+            (&mut unfolded, &mut unfolded_plot),
+            (&mut folded, &mut folded_plot),
+        ] {
+            if toggles.contains(&tick) {
+                backend.on_use_block(plot, lever_pos).unwrap();
+            }
+            backend.tick(plot);
+            backend.flush(plot, false);
+        }
+        for x in 1..=10 {
+            let pos = BlockPos::new(x, 0, 0);
+            assert_eq!(
+                unfolded_plot.get_block(pos),
+                folded_plot.get_block(pos),
+                "block at {} differs on tick {}",
+                pos,
+                tick
+            );
+        }
+        lit_ticks += folded.snapshot(lamp_pos).unwrap().powered as u32;
+    }
+    assert!(lit_ticks > 0);
+
+    // The pending ticks of the stages are handed back to the world
+    unfolded.reset(&mut unfolded_plot, false);
+    folded.reset(&mut folded_plot, false);
+    let pending = |plot: &PlotWorld| {
+        let mut ticks: Vec<_> = plot
+            .to_be_ticked
+            .iter()
+            .map(|entry| (entry.pos.x, entry.ticks_left, entry.tick_priority))
+            .collect();
+        ticks.sort();
+        ticks
+    };
+    assert!(!pending(&folded_plot).is_empty());
+    assert_eq!(pending(&unfolded_plot), pending(&folded_plot));
+}
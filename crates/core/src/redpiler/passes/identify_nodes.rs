@@ -5,16 +5,21 @@
 //!
 //! If `optimize` is set in [`CompilerOptions`], redstone wires will not be added to the graph.
 //!
+//! Chunk sections are scanned in parallel. Sections whose palette contains no redstone components
+//! are skipped entirely, so the time this pass takes depends on the size of the circuit rather
+//! than the size of the plot.
+//!
 //! There are no requirements for this pass.
 
 use super::Pass;
 use crate::blocks::Block;
-use crate::plot::PlotWorld;
+use crate::plot::{PlotWorld, PLOT_SCALE, PLOT_WIDTH};
 use crate::redpiler::compile_graph::{CompileGraph, CompileNode, NodeState, NodeType};
 use crate::redpiler::{CompilerInput, CompilerOptions};
 use crate::world::World;
 use mchprs_blocks::block_entities::BlockEntity;
 use mchprs_blocks::BlockPos;
+use rayon::prelude::*;
 
 pub struct IdentifyNodes;
 
@@ -29,16 +34,55 @@ impl Pass for IdentifyNodes {
         let plot = input.plot;
 
         let (first_pos, second_pos) = plot.get_corners();
-
         let start_pos = first_pos.min(second_pos);
-        let end_pos = first_pos.max(second_pos);
-        for y in start_pos.y..=end_pos.y {
-            for z in start_pos.z..=end_pos.z {
-                for x in start_pos.x..=end_pos.x {
-                    let pos = BlockPos::new(x, y, z);
-                    for_pos(ignore_wires, plot, graph, pos);
+
+        input
+            .progress
+            .start_scan(plot.chunks.len() * SECTIONS_PER_CHUNK * SECTION_VOLUME);
+        let sections = (0..plot.chunks.len())
+            .flat_map(|chunk| (0..SECTIONS_PER_CHUNK).map(move |section| (chunk, section)))
+            .collect::<Vec<_>>();
+        let mut nodes: Vec<CompileNode> = sections
+            .into_par_iter()
+            .flat_map_iter(|(chunk_idx, section_y)| {
+                if input.progress.is_cancelled() {
+                    return Vec::new();
                 }
-            }
+                let chunk = &plot.chunks[chunk_idx];
+                let section = &chunk.sections[section_y];
+                // Chunks are indexed by their position relative to the plot
+                let origin = BlockPos::new(
+                    start_pos.x + (chunk_idx >> PLOT_SCALE) as i32 * 16,
+                    start_pos.y + section_y as i32 * 16,
+                    start_pos.z + (chunk_idx & (PLOT_WIDTH as usize - 1)) as i32 * 16,
+                );
+
+                let mut nodes = Vec::new();
+                if section.may_contain(|id| is_node_block(Block::from_id(id), ignore_wires)) {
+                    for y in 0..16 {
+                        for z in 0..16 {
+                            for x in 0..16 {
+                                let pos = BlockPos::new(origin.x + x, origin.y + y, origin.z + z);
+                                nodes.extend(for_pos(ignore_wires, plot, pos));
+                            }
+                        }
+                    }
+                }
+                input.progress.add_scanned(SECTION_VOLUME);
+                nodes
+            })
+            .collect();
+        if input.progress.is_cancelled() {
+            return;
+        }
+
+        // Keep the order of a plain scan through the plot so compiles stay deterministic
+        nodes.sort_unstable_by_key(|node| {
+            let (pos, _) = node.block.unwrap();
+            (pos.y, pos.z, pos.x)
+        });
+        for node in nodes {
+            graph.add_node(node);
         }
     }
 
@@ -48,13 +92,14 @@ impl Pass for IdentifyNodes {
     }
 }
 
-fn for_pos(ignore_wires: bool, plot: &PlotWorld, graph: &mut CompileGraph, pos: BlockPos) {
+const SECTIONS_PER_CHUNK: usize = 16;
+const SECTION_VOLUME: usize = 16 * 16 * 16;
+
+fn for_pos(ignore_wires: bool, plot: &PlotWorld, pos: BlockPos) -> Option<CompileNode> {
     let id = plot.get_block_raw(pos);
     let block = Block::from_id(id);
 
-    let Some((ty, state)) = identify_block(block, pos, plot) else {
-        return;
-    };
+    let (ty, state) = identify_block(block, pos, plot)?;
 
     let facing_diode = if let Block::RedstoneRepeater { repeater } = block {
         plot.get_block(pos.offset(repeater.facing.opposite().block_face()))
@@ -67,17 +112,37 @@ fn for_pos(ignore_wires: bool, plot: &PlotWorld, graph: &mut CompileGraph, pos:
     };
 
     if ignore_wires && ty == NodeType::Wire {
-        return;
+        return None;
     }
 
-    graph.add_node(CompileNode {
+    Some(CompileNode {
         ty,
         block: Some((pos, id)),
         state,
 
         facing_diode,
         comparator_far_input: None,
-    });
+        stages: Vec::new(),
+    })
+}
+
+/// Returns true if `identify_block` would create a node for this block. Used to skip whole chunk
+/// sections using their palette.
+fn is_node_block(block: Block, ignore_wires: bool) -> bool {
+    match block {
+        Block::RedstoneWire { .. } => !ignore_wires,
+        Block::RedstoneRepeater { .. }
+        | Block::RedstoneComparator { .. }
+        | Block::RedstoneTorch { .. }
+        | Block::RedstoneWallTorch { .. }
+        | Block::StoneButton { .. }
+        | Block::RedstoneLamp { .. }
+        | Block::Lever { .. }
+        | Block::StonePressurePlate { .. }
+        | Block::IronTrapdoor { .. }
+        | Block::RedstoneBlock {} => true,
+        block => block.has_comparator_override(),
+    }
 }
 
 fn identify_block(block: Block, pos: BlockPos, world: &PlotWorld) -> Option<(NodeType, NodeState)> {
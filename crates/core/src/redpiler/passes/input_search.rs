@@ -1,6 +1,6 @@
 //! # [`InputSearch`]
 //!
-//! This pass populates the graph with edges.
+//! This pass populates the graph with edges. The inputs of every node are searched in parallel.
 //! This pass is *mandatory*. Without it, there would be no links between nodes.
 
 use super::Pass;
@@ -10,15 +10,42 @@ use crate::redpiler::compile_graph::{CompileGraph, CompileLink, LinkType, NodeId
 use crate::redpiler::{CompilerInput, CompilerOptions};
 use crate::world::World;
 use mchprs_blocks::{BlockDirection, BlockFace, BlockPos};
-use petgraph::visit::NodeIndexable;
+use rayon::prelude::*;
 use std::collections::{HashMap, VecDeque};
 
 pub struct InputSearch;
 
 impl Pass for InputSearch {
     fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, input: &CompilerInput<'_>) {
-        let mut state = InputSearchState::new(input.plot, graph);
-        state.search();
+        let mut pos_map = HashMap::new();
+        for id in graph.node_indices() {
+            let (pos, _) = graph[id].block.unwrap();
+            pos_map.insert(pos, id);
+        }
+
+        // Every node searches for its own inputs, so they can be searched in parallel. The results
+        // are collected in node order so the edge order doesn't depend on scheduling.
+        let nodes: Vec<_> = graph
+            .node_indices()
+            .map(|idx| (idx, graph[idx].block.unwrap()))
+            .collect();
+        let results: Vec<_> = nodes
+            .into_par_iter()
+            .map(|(idx, block)| {
+                let mut state = InputSearchState::new(input.plot, &pos_map);
+                state.search_node(idx, block);
+                (idx, state.links, state.far_input)
+            })
+            .collect();
+
+        for (idx, links, far_input) in results {
+            for (source, target, link) in links {
+                graph.add_edge(source, target, link);
+            }
+            if far_input.is_some() {
+                graph[idx].comparator_far_input = far_input;
+            }
+        }
     }
 
     fn should_run(&self, _: &CompilerOptions) -> bool {
@@ -29,25 +56,25 @@ impl Pass for InputSearch {
 
 struct InputSearchState<'a> {
     plot: &'a PlotWorld,
-    graph: &'a mut CompileGraph,
-    pos_map: HashMap<BlockPos, NodeIdx>,
+    pos_map: &'a HashMap<BlockPos, NodeIdx>,
+    links: Vec<(NodeIdx, NodeIdx, CompileLink)>,
+    far_input: Option<u8>,
 }
 
 impl<'a> InputSearchState<'a> {
-    fn new(plot: &'a PlotWorld, graph: &'a mut CompileGraph) -> InputSearchState<'a> {
-        let mut pos_map = HashMap::new();
-        for id in graph.node_indices() {
-            let (pos, _) = graph[id].block.unwrap();
-            pos_map.insert(pos, id);
-        }
-
+    fn new(plot: &'a PlotWorld, pos_map: &'a HashMap<BlockPos, NodeIdx>) -> InputSearchState<'a> {
         InputSearchState {
             plot,
-            graph,
             pos_map,
+            links: Vec::new(),
+            far_input: None,
         }
     }
 
+    fn add_link(&mut self, source: NodeIdx, target: NodeIdx, link: CompileLink) {
+        self.links.push((source, target, link));
+    }
+
     fn provides_weak_power(&self, block: Block, side: BlockFace) -> bool {
         match block {
             Block::RedstoneTorch { .. } => true,
@@ -104,7 +131,7 @@ impl<'a> InputSearchState<'a> {
                 let pos = pos.offset(*side);
                 let block = self.plot.get_block(pos);
                 if self.provides_strong_power(block, *side) {
-                    self.graph.add_edge(
+                    self.add_link(
                         self.pos_map[&pos],
                         start_node,
                         CompileLink::new(link_ty, distance),
@@ -135,7 +162,7 @@ impl<'a> InputSearchState<'a> {
                 }
             }
         } else if self.provides_weak_power(block, side) {
-            self.graph.add_edge(
+            self.add_link(
                 self.pos_map[&pos],
                 start_node,
                 CompileLink::new(link_ty, distance),
@@ -241,8 +268,7 @@ impl<'a> InputSearchState<'a> {
         let side_pos = pos.offset(side.block_face());
         let side_block = self.plot.get_block(side_pos);
         if side_block.is_diode() && self.provides_weak_power(side_block, side.block_face()) {
-            self.graph
-                .add_edge(self.pos_map[&side_pos], id, CompileLink::side(0));
+            self.add_link(self.pos_map[&side_pos], id, CompileLink::side(0));
         }
     }
 
@@ -250,8 +276,7 @@ impl<'a> InputSearchState<'a> {
         let side_pos = pos.offset(side.block_face());
         let side_block = self.plot.get_block(side_pos);
         if side_block.is_diode() && self.provides_weak_power(side_block, side.block_face()) {
-            self.graph
-                .add_edge(self.pos_map[&side_pos], id, CompileLink::side(0));
+            self.add_link(self.pos_map[&side_pos], id, CompileLink::side(0));
         } else if matches!(side_block, Block::RedstoneWire { .. }) {
             self.search_wire(id, side_pos, LinkType::Side, 0)
         }
@@ -294,8 +319,7 @@ impl<'a> InputSearchState<'a> {
                 let input_pos = pos.offset(facing.block_face());
                 let input_block = self.plot.get_block(input_pos);
                 if input_block.has_comparator_override() {
-                    self.graph
-                        .add_edge(self.pos_map[&input_pos], id, CompileLink::default(0));
+                    self.add_link(self.pos_map[&input_pos], id, CompileLink::default(0));
                 } else {
                     self.search_diode_inputs(id, pos, facing);
 
@@ -304,7 +328,7 @@ impl<'a> InputSearchState<'a> {
                     if input_block.is_solid() && far_input_block.has_comparator_override() {
                         let far_override =
                             far_input_block.get_comparator_override(self.plot, far_input_pos);
-                        self.graph[id].comparator_far_input = Some(far_override);
+                        self.far_input = Some(far_override);
                     }
                 }
             }
@@ -336,17 +360,6 @@ impl<'a> InputSearchState<'a> {
             _ => {}
         }
     }
-
-    fn search(&mut self) {
-        for i in 0..self.graph.node_bound() {
-            let idx = NodeIdx::new(i);
-            if !self.graph.contains_node(idx) {
-                continue;
-            }
-            let node = &self.graph[idx];
-            self.search_node(idx, node.block.unwrap());
-        }
-    }
 }
 
 fn is_wire(world: &dyn World, pos: BlockPos) -> bool {
@@ -0,0 +1,714 @@
+//! # [`LogicGates`]
+//!
+//! Recognizes common redstone logic gate idioms and lowers them to gate nodes:
+//! - A torch with multiple inputs becomes a [`NodeType::Not`] (NOR)
+//! - A torch with multiple inputs which powers a torch that inverts it becomes a
+//!   [`NodeType::Or`]
+//! - Torches which each invert an input and together power an output torch become a
+//!   [`NodeType::And`]
+//! - Two subtract mode comparators which subtract two inputs from each other and power the same
+//!   components become a [`NodeType::Xor`]
+//! - A repeater which is only locked by other repeaters becomes a [`NodeType::Latch`]
+//!
+//! The components of an `Or`, `And`, `Xor` or `Latch` are kept as stages of the gate so that the
+//! backend can still simulate them exactly, but they read straight from the inputs of the gate
+//! instead of being updated through the graph.
+
+use super::Pass;
+use crate::blocks::ComparatorMode;
+use crate::redpiler::compile_graph::{
+    CompileGraph, CompileLink, LinkType, NodeIdx, NodeType, Stage, StageInput, StageSource,
+};
+use crate::redpiler::{CompilerInput, CompilerOptions};
+use mchprs_blocks::BlockPos;
+use petgraph::visit::{EdgeRef, NodeIndexable};
+use petgraph::Direction;
+use std::collections::HashSet;
+
+pub struct LogicGates;
+
+impl Pass for LogicGates {
+    fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, input: &CompilerInput<'_>) {
+        // Nodes with pending ticks or observable nodes can't become part of a gate
+        let keep: HashSet<BlockPos> = input
+            .ticks
+            .iter()
+            .map(|entry| entry.pos)
+            .chain(input.observable.iter().copied())
+            .collect();
+
+        for i in 0..graph.node_bound() {
+            let idx = NodeIdx::new(i);
+            if !graph.contains_node(idx) {
+                continue;
+            }
+            if let Some(ports) = torch_gate_ports(graph, idx, &keep) {
+                lower_torch_gate(graph, idx, &ports);
+            } else if let Some(locks) = latch_locks(graph, idx, &keep) {
+                lower_latch(graph, idx, &locks);
+            } else if let Some((other, idx_first)) = xor_pair(graph, idx, &keep) {
+                lower_xor(graph, idx, other, idx_first);
+            }
+        }
+
+        // The remaining torches with multiple inputs are NOR gates on their own
+        for idx in graph.node_indices().collect::<Vec<_>>() {
+            let node = &graph[idx];
+            if node.ty == NodeType::Torch
+                && node.stages.is_empty()
+                && graph.edges_directed(idx, Direction::Incoming).count() >= 2
+            {
+                graph[idx].ty = NodeType::Not { delay: 1 };
+            }
+        }
+    }
+}
+
+fn is_plain(graph: &CompileGraph, idx: NodeIdx, ty: NodeType, keep: &HashSet<BlockPos>) -> bool {
+    let node = &graph[idx];
+    node.ty == ty
+        && node.stages.is_empty()
+        && matches!(node.block, Some((pos, _)) if !keep.contains(&pos))
+}
+
+/// Returns the input torches if `idx` is the output torch of a torch AND or OR gate
+fn torch_gate_ports(
+    graph: &CompileGraph,
+    idx: NodeIdx,
+    keep: &HashSet<BlockPos>,
+) -> Option<Vec<NodeIdx>> {
+    if !is_plain(graph, idx, NodeType::Torch, keep) {
+        return None;
+    }
+
+    let ports: Vec<NodeIdx> = graph.neighbors_directed(idx, Direction::Incoming).collect();
+    let distinct: HashSet<NodeIdx> = ports.iter().copied().collect();
+    if ports.is_empty() || distinct.len() != ports.len() || distinct.contains(&idx) {
+        return None;
+    }
+    // Two torches which only invert a single input are a delay line
+    if ports.len() == 1 && graph.edges_directed(ports[0], Direction::Incoming).count() < 2 {
+        return None;
+    }
+    for &port in &ports {
+        let mut inputs = graph
+            .neighbors_directed(port, Direction::Incoming)
+            .peekable();
+        if !is_plain(graph, port, NodeType::Torch, keep)
+            || graph.neighbors_directed(port, Direction::Outgoing).count() != 1
+            || inputs.peek().is_none()
+            || inputs.any(|source| {
+                source == idx
+                    || distinct.contains(&source)
+                    || !updates_first(graph, source, &distinct)
+            })
+        {
+            return None;
+        }
+    }
+    Some(ports)
+}
+
+/// Returns true if `source` updates the given nodes before any other node. The link from an input
+/// to the gate is added last, which makes it the first one to be updated.
+fn updates_first(graph: &CompileGraph, source: NodeIdx, nodes: &HashSet<NodeIdx>) -> bool {
+    graph
+        .neighbors_directed(source, Direction::Outgoing)
+        .skip_while(|idx| nodes.contains(idx))
+        .all(|idx| !nodes.contains(&idx))
+}
+
+fn lower_torch_gate(graph: &mut CompileGraph, idx: NodeIdx, ports: &[NodeIdx]) {
+    let mut stages = Vec::with_capacity(ports.len() + 1);
+    let mut output_inputs = Vec::with_capacity(ports.len());
+    for (i, &port) in ports.iter().enumerate() {
+        let inputs: Vec<(NodeIdx, u8)> = graph
+            .edges_directed(port, Direction::Incoming)
+            .map(|edge| (edge.source(), edge.weight().ss))
+            .collect();
+        for &(source, ss) in &inputs {
+            // The gate still has to be updated by the inputs of the torches
+            if graph.find_edge(source, idx).is_none() {
+                graph.add_edge(source, idx, CompileLink::default(ss));
+            }
+        }
+
+        let edge = graph.find_edge(port, idx).unwrap();
+        output_inputs.push(StageInput {
+            source: StageSource::Stage(i),
+            ty: LinkType::Default,
+            ss: graph[edge].ss,
+        });
+
+        let node = &graph[port];
+        stages.push(Stage {
+            ty: NodeType::Torch,
+            facing_diode: node.facing_diode,
+            state: node.state.clone(),
+            inputs: inputs
+                .into_iter()
+                .map(|(source, ss)| StageInput {
+                    source: StageSource::Input(source),
+                    ty: LinkType::Default,
+                    ss,
+                })
+                .collect(),
+            output: false,
+            block: node.block,
+        });
+    }
+    for &port in ports {
+        graph.remove_node(port);
+    }
+
+    let node = &mut graph[idx];
+    stages.push(Stage {
+        ty: NodeType::Torch,
+        facing_diode: node.facing_diode,
+        state: node.state.clone(),
+        inputs: output_inputs,
+        output: true,
+        block: None,
+    });
+    node.ty = match ports {
+        [_] => NodeType::Or { delay: 2 },
+        _ => NodeType::And { delay: 2 },
+    };
+    node.stages = stages;
+}
+
+/// Returns the locking repeaters if `idx` is a repeater which is only locked by repeaters
+fn latch_locks(
+    graph: &CompileGraph,
+    idx: NodeIdx,
+    keep: &HashSet<BlockPos>,
+) -> Option<Vec<NodeIdx>> {
+    let is_repeater = |idx: NodeIdx| match graph[idx].ty {
+        NodeType::Repeater(delay) => is_plain(graph, idx, NodeType::Repeater(delay), keep),
+        _ => false,
+    };
+    if !is_repeater(idx) {
+        return None;
+    }
+
+    let mut has_data = false;
+    let mut locks = Vec::new();
+    for edge in graph.edges_directed(idx, Direction::Incoming) {
+        match edge.weight().ty {
+            LinkType::Default => has_data = true,
+            LinkType::Side => locks.push(edge.source()),
+        }
+    }
+    let distinct: HashSet<NodeIdx> = locks.iter().copied().collect();
+    if !has_data || locks.is_empty() || distinct.len() != locks.len() || distinct.contains(&idx) {
+        return None;
+    }
+    for &lock in &locks {
+        let mut inputs = graph.edges_directed(lock, Direction::Incoming).peekable();
+        if !is_repeater(lock)
+            || graph.neighbors_directed(lock, Direction::Outgoing).count() != 1
+            || inputs.peek().is_none()
+            || inputs.any(|edge| {
+                let source = edge.source();
+                // The inputs of the locking repeaters become side inputs of the latch, so they
+                // can't also be data inputs
+                edge.weight().ty == LinkType::Side
+                    || source == idx
+                    || distinct.contains(&source)
+                    || graph.find_edge(source, idx).is_some()
+                    || !updates_first(graph, source, &distinct)
+            })
+        {
+            return None;
+        }
+    }
+    Some(locks)
+}
+
+fn lower_latch(graph: &mut CompileGraph, idx: NodeIdx, locks: &[NodeIdx]) {
+    let mut stages = Vec::with_capacity(locks.len() + 1);
+    let mut output_inputs: Vec<StageInput> = graph
+        .edges_directed(idx, Direction::Incoming)
+        .filter(|edge| edge.weight().ty == LinkType::Default)
+        .map(|edge| StageInput {
+            source: StageSource::Input(edge.source()),
+            ty: LinkType::Default,
+            ss: edge.weight().ss,
+        })
+        .collect();
+    for (i, &lock) in locks.iter().enumerate() {
+        let inputs: Vec<(NodeIdx, u8)> = graph
+            .edges_directed(lock, Direction::Incoming)
+            .map(|edge| (edge.source(), edge.weight().ss))
+            .collect();
+        for &(source, ss) in &inputs {
+            // The latch still has to be updated by the inputs of the locking repeaters
+            if graph.find_edge(source, idx).is_none() {
+                graph.add_edge(source, idx, CompileLink::side(ss));
+            }
+        }
+
+        let edge = graph.find_edge(lock, idx).unwrap();
+        output_inputs.push(StageInput {
+            source: StageSource::Stage(i),
+            ty: LinkType::Side,
+            ss: graph[edge].ss,
+        });
+
+        let node = &graph[lock];
+        stages.push(Stage {
+            ty: node.ty,
+            facing_diode: node.facing_diode,
+            state: node.state.clone(),
+            inputs: inputs
+                .into_iter()
+                .map(|(source, ss)| StageInput {
+                    source: StageSource::Input(source),
+                    ty: LinkType::Default,
+                    ss,
+                })
+                .collect(),
+            output: false,
+            block: node.block,
+        });
+    }
+    for &lock in locks {
+        graph.remove_node(lock);
+    }
+
+    let node = &mut graph[idx];
+    let NodeType::Repeater(delay) = node.ty else {
+        unreachable!("latches are lowered from repeaters");
+    };
+    stages.push(Stage {
+        ty: node.ty,
+        facing_diode: node.facing_diode,
+        state: node.state.clone(),
+        inputs: output_inputs,
+        output: true,
+        block: None,
+    });
+    node.ty = NodeType::Latch { delay };
+    node.stages = stages;
+}
+
+/// Returns the default and side input of a subtract mode comparator with exactly one of each
+fn subtract_inputs(
+    graph: &CompileGraph,
+    idx: NodeIdx,
+    keep: &HashSet<BlockPos>,
+) -> Option<(NodeIdx, NodeIdx)> {
+    if !is_plain(
+        graph,
+        idx,
+        NodeType::Comparator(ComparatorMode::Subtract),
+        keep,
+    ) || graph[idx].comparator_far_input.is_some()
+    {
+        return None;
+    }
+
+    let mut default = None;
+    let mut side = None;
+    for edge in graph.edges_directed(idx, Direction::Incoming) {
+        let input = match edge.weight().ty {
+            LinkType::Default => &mut default,
+            LinkType::Side => &mut side,
+        };
+        if input.replace(edge.source()).is_some() {
+            return None;
+        }
+    }
+    Some((default?, side?))
+}
+
+fn sorted_outputs(graph: &CompileGraph, idx: NodeIdx) -> Vec<(NodeIdx, bool, u8)> {
+    let mut outputs: Vec<_> = graph
+        .edges_directed(idx, Direction::Outgoing)
+        .map(|edge| {
+            let link = edge.weight();
+            (edge.target(), link.ty == LinkType::Side, link.ss)
+        })
+        .collect();
+    outputs.sort_unstable();
+    outputs
+}
+
+/// Returns true if `source` updates `first` right before `second`, false if it updates them the
+/// other way around and `None` if it updates other nodes in between
+fn updated_first(
+    graph: &CompileGraph,
+    source: NodeIdx,
+    first: NodeIdx,
+    second: NodeIdx,
+) -> Option<bool> {
+    let updates: Vec<NodeIdx> = graph
+        .neighbors_directed(source, Direction::Outgoing)
+        .collect();
+    let first = updates.iter().position(|&idx| idx == first)?;
+    let second = updates.iter().position(|&idx| idx == second)?;
+    (first.abs_diff(second) == 1).then_some(first < second)
+}
+
+/// Returns the other comparator if `idx` is one of the comparators of a comparator XOR gate,
+/// along with whether `idx` ticks first when both comparators tick on the same tick
+fn xor_pair(
+    graph: &CompileGraph,
+    idx: NodeIdx,
+    keep: &HashSet<BlockPos>,
+) -> Option<(NodeIdx, bool)> {
+    let (a, b) = subtract_inputs(graph, idx, keep)?;
+    if a == b || [a, b].contains(&idx) {
+        return None;
+    }
+    let outputs = sorted_outputs(graph, idx);
+    if outputs.is_empty() {
+        return None;
+    }
+
+    graph
+        .neighbors_directed(b, Direction::Outgoing)
+        .filter(|&other| other != idx && other != a)
+        .find_map(|other| {
+            // The gate ticks both comparators with the same priority and in one tick, so both
+            // inputs have to update them right after each other and in the same order
+            let idx_first = updated_first(graph, a, idx, other)?;
+            let matches = subtract_inputs(graph, other, keep) == Some((b, a))
+                && graph[other].facing_diode == graph[idx].facing_diode
+                && sorted_outputs(graph, other) == outputs
+                && updated_first(graph, b, idx, other) == Some(idx_first);
+            matches.then_some((other, idx_first))
+        })
+}
+
+fn lower_xor(graph: &mut CompileGraph, idx: NodeIdx, other: NodeIdx, idx_first: bool) {
+    let stage = |graph: &CompileGraph, comparator: NodeIdx| {
+        let node = &graph[comparator];
+        Stage {
+            ty: node.ty,
+            facing_diode: node.facing_diode,
+            state: node.state.clone(),
+            inputs: graph
+                .edges_directed(comparator, Direction::Incoming)
+                .map(|edge| StageInput {
+                    source: StageSource::Input(edge.source()),
+                    ty: edge.weight().ty,
+                    ss: edge.weight().ss,
+                })
+                .collect(),
+            output: true,
+            block: node.block,
+        }
+    };
+    let mut stages = vec![stage(graph, idx), stage(graph, other)];
+    if !idx_first {
+        stages.reverse();
+    }
+
+    // Both comparators power the same nodes, which might refer to the other comparator
+    for target in graph
+        .neighbors_directed(other, Direction::Outgoing)
+        .collect::<Vec<_>>()
+    {
+        for input in graph[target]
+            .stages
+            .iter_mut()
+            .flat_map(|stage| stage.inputs.iter_mut())
+        {
+            if input.source == StageSource::Input(other) {
+                input.source = StageSource::Input(idx);
+            }
+        }
+    }
+    graph.remove_node(other);
+
+    let node = &mut graph[idx];
+    let output_strength = stages
+        .iter()
+        .map(|stage| stage.state.output_strength)
+        .max()
+        .unwrap_or(0);
+    node.ty = NodeType::Xor { delay: 1 };
+    node.state.powered = output_strength > 0;
+    node.state.output_strength = output_strength;
+    node.stages = stages;
+}
+
+#[cfg(test)]
+fn add_test_node(
+    graph: &mut CompileGraph,
+    x: i32,
+    ty: NodeType,
+    block: crate::blocks::Block,
+    powered: bool,
+) -> NodeIdx {
+    use crate::redpiler::compile_graph::{CompileNode, NodeState};
+
+    graph.add_node(CompileNode {
+        ty,
+        block: Some((BlockPos::new(x, 0, 0), block.get_id())),
+        state: NodeState::simple(powered),
+        facing_diode: false,
+        comparator_far_input: None,
+        stages: Vec::new(),
+    })
+}
+
+/// Lowers the graph returned by `build` and checks that it behaves exactly like the components
+/// while the levers are toggled on the given ticks. The nodes at the given x positions must have
+/// been lowered to the given gates.
+#[cfg(test)]
+fn assert_lowered_matches(
+    build: impl Fn() -> CompileGraph,
+    levers: &[(i32, &[u32])],
+    gates: &[(i32, NodeType)],
+) {
+    use crate::plot::{PlotWorld, PLOT_WIDTH};
+    use crate::redpiler::backend::direct::DirectBackend;
+    use crate::redpiler::backend::JITBackend;
+    use crate::world::storage::Chunk;
+    use crate::world::World;
+    use mchprs_blocks::block_entities::BlockEntity;
+
+    let new_plot = || PlotWorld {
+        x: 0,
+        z: 0,
+        chunks: (0..PLOT_WIDTH * PLOT_WIDTH)
+            .map(|i| Chunk::empty(i / PLOT_WIDTH, i % PLOT_WIDTH))
+            .collect(),
+        to_be_ticked: Vec::new(),
+    };
+    let mut components_plot = new_plot();
+    let mut lowered_plot = new_plot();
+    let components_graph = build();
+    let max_x = components_graph
+        .node_weights()
+        .filter_map(|node| node.block.map(|(pos, _)| pos.x))
+        .max()
+        .unwrap();
+    let mut lowered_graph = build();
+    let input = CompilerInput {
+        plot: &lowered_plot,
+        ticks: &[],
+        observable: &[],
+        progress: &Default::default(),
+    };
+    LogicGates.run_pass(&mut lowered_graph, &Default::default(), &input);
+
+    let mut components = DirectBackend::default();
+    components.compile(components_graph, Vec::new());
+    let mut lowered = DirectBackend::default();
+    lowered.compile(lowered_graph, Vec::new());
+    // The gates can still be found at the block of their node
+    for &(x, ty) in gates {
+        assert_eq!(lowered.inspect(BlockPos::new(x, 0, 0)).unwrap().ty, ty);
+    }
+
+    let mut gate_changes = vec![0; gates.len()];
+    // Stops with ticks still pending
+    for tick in 0..40 {
+        for (backend, plot) in [
+            (&mut components, &mut components_plot),
+            (&mut lowered, &mut lowered_plot),
+        ] {
+            for &(x, toggles) in levers {
+                if toggles.contains(&tick) {
+                    backend.on_use_block(plot, BlockPos::new(x, 0, 0)).unwrap();
+                }
+            }
+            backend.tick(plot);
+            backend.flush(plot, false);
+        }
+        for x in 0..=max_x {
+            let pos = BlockPos::new(x, 0, 0);
+            assert_eq!(
+                components_plot.get_block(pos),
+                lowered_plot.get_block(pos),
+                "block at {} differs on tick {}",
+                pos,
+                tick
+            );
+        }
+        for (&(x, _), changes) in gates.iter().zip(&mut gate_changes) {
+            let pos = BlockPos::new(x, 0, 0);
+            *changes += lowered.snapshot(pos).unwrap().powered as u32;
+        }
+    }
+    assert!(gate_changes.iter().all(|&changes| changes > 0));
+
+    // The pending ticks of the components are handed back to the world
+    components.reset(&mut components_plot, false);
+    lowered.reset(&mut lowered_plot, false);
+    let pending = |plot: &PlotWorld| {
+        let mut ticks: Vec<_> = plot
+            .to_be_ticked
+            .iter()
+            .map(|entry| (entry.pos.x, entry.ticks_left, entry.tick_priority))
+            .collect();
+        ticks.sort();
+        ticks
+    };
+    assert!(!pending(&lowered_plot).is_empty());
+    assert_eq!(pending(&components_plot), pending(&lowered_plot));
+    let output_strength = |plot: &PlotWorld, x| match plot.get_block_entity(BlockPos::new(x, 0, 0))
+    {
+        Some(BlockEntity::Comparator { output_strength }) => Some(*output_strength),
+        _ => None,
+    };
+    for x in 0..=max_x {
+        assert_eq!(
+            output_strength(&components_plot, x),
+            output_strength(&lowered_plot, x)
+        );
+    }
+}
+
+#[test]
+fn lowered_and_xor_match_components() {
+    use crate::blocks::{Block, Lever, RedstoneComparator};
+
+    let build = || {
+        let mut graph = CompileGraph::new();
+        let lever = Block::Lever {
+            lever: Lever::default(),
+        };
+        let torch = |lit| Block::RedstoneTorch { lit };
+        let lamp = Block::RedstoneLamp { lit: false };
+        let comparator = Block::RedstoneComparator {
+            comparator: RedstoneComparator {
+                mode: ComparatorMode::Subtract,
+                ..Default::default()
+            },
+        };
+        let subtract = NodeType::Comparator(ComparatorMode::Subtract);
+        let mut add = |x, ty, block, powered| add_test_node(&mut graph, x, ty, block, powered);
+
+        let a = add(0, NodeType::Lever, lever, false);
+        let b = add(1, NodeType::Lever, lever, false);
+        let ta = add(2, NodeType::Torch, torch(true), true);
+        let tb = add(3, NodeType::Torch, torch(true), true);
+        let and = add(4, NodeType::Torch, torch(false), false);
+        let and_lamp = add(5, NodeType::Lamp, lamp, false);
+        let c1 = add(6, subtract, comparator, false);
+        let c2 = add(7, subtract, comparator, false);
+        let xor_lamp = add(8, NodeType::Lamp, lamp, false);
+
+        let link = CompileLink::default;
+        graph.add_edge(a, c1, link(0));
+        graph.add_edge(b, c1, CompileLink::side(0));
+        graph.add_edge(b, c2, link(0));
+        graph.add_edge(a, c2, CompileLink::side(0));
+        graph.add_edge(c1, xor_lamp, link(1));
+        graph.add_edge(c2, xor_lamp, link(1));
+        // Links added last are updated first, so the levers update the torches of the AND gate
+        // before the comparators
+        graph.add_edge(a, ta, link(0));
+        graph.add_edge(b, tb, link(0));
+        graph.add_edge(ta, and, link(0));
+        graph.add_edge(tb, and, link(0));
+        graph.add_edge(and, and_lamp, link(0));
+        graph
+    };
+
+    // Toggle the inputs with pulses of different lengths so that they overlap in different ways
+    let a_toggles = [0, 1, 4, 6, 7, 8, 15, 20, 21, 30, 38];
+    let b_toggles = [0, 2, 4, 5, 8, 9, 10, 16, 20, 31, 39];
+    assert_lowered_matches(
+        build,
+        &[(0, &a_toggles), (1, &b_toggles)],
+        &[
+            (4, NodeType::And { delay: 2 }),
+            (6, NodeType::Xor { delay: 1 }),
+        ],
+    );
+}
+
+#[test]
+fn lowered_or_and_not_match_components() {
+    use crate::blocks::{Block, Lever};
+
+    let build = || {
+        let mut graph = CompileGraph::new();
+        let lever = Block::Lever {
+            lever: Lever::default(),
+        };
+        let torch = |lit| Block::RedstoneTorch { lit };
+        let lamp = Block::RedstoneLamp { lit: false };
+        let mut add = |x, ty, block, powered| add_test_node(&mut graph, x, ty, block, powered);
+
+        let a = add(0, NodeType::Lever, lever, false);
+        let b = add(1, NodeType::Lever, lever, false);
+        let nor = add(2, NodeType::Torch, torch(true), true);
+        let or = add(3, NodeType::Torch, torch(false), false);
+        let or_lamp = add(4, NodeType::Lamp, lamp, false);
+        let not = add(5, NodeType::Torch, torch(true), true);
+        let not_lamp = add(6, NodeType::Lamp, Block::RedstoneLamp { lit: true }, true);
+
+        let link = CompileLink::default;
+        graph.add_edge(a, not, link(0));
+        graph.add_edge(b, not, link(0));
+        graph.add_edge(not, not_lamp, link(0));
+        // Links added last are updated first, so the levers update the NOR torch of the OR gate
+        // before the other torch
+        graph.add_edge(a, nor, link(0));
+        graph.add_edge(b, nor, link(0));
+        graph.add_edge(nor, or, link(0));
+        graph.add_edge(or, or_lamp, link(0));
+        graph
+    };
+
+    let a_toggles = [0, 1, 4, 6, 7, 8, 15, 20, 21, 30, 38];
+    let b_toggles = [0, 2, 4, 5, 8, 9, 10, 16, 20, 31, 39];
+    assert_lowered_matches(
+        build,
+        &[(0, &a_toggles), (1, &b_toggles)],
+        &[
+            (3, NodeType::Or { delay: 2 }),
+            (5, NodeType::Not { delay: 1 }),
+        ],
+    );
+}
+
+#[test]
+fn lowered_latch_matches_components() {
+    use crate::blocks::{Block, Lever, RedstoneRepeater};
+
+    let build = || {
+        let mut graph = CompileGraph::new();
+        let lever = Block::Lever {
+            lever: Lever::default(),
+        };
+        let repeater = |delay| Block::RedstoneRepeater {
+            repeater: RedstoneRepeater {
+                delay,
+                ..Default::default()
+            },
+        };
+        let lamp = Block::RedstoneLamp { lit: false };
+        let mut add = |x, ty, block, powered| add_test_node(&mut graph, x, ty, block, powered);
+
+        let data = add(0, NodeType::Lever, lever, false);
+        let clock = add(1, NodeType::Lever, lever, false);
+        let lock = add(2, NodeType::Repeater(2), repeater(2), false);
+        let latch = add(3, NodeType::Repeater(1), repeater(1), false);
+        let latch_lamp = add(4, NodeType::Lamp, lamp, false);
+
+        let link = CompileLink::default;
+        graph[lock].facing_diode = true;
+        graph.add_edge(data, latch, link(0));
+        graph.add_edge(clock, lock, link(0));
+        graph.add_edge(lock, latch, CompileLink::side(0));
+        graph.add_edge(latch, latch_lamp, link(0));
+        graph
+    };
+
+    // The data changes both while the latch is locked and while it is unlocked, and the clock
+    // pulses are shorter than the delay of the locking repeater
+    let data_toggles = [0, 3, 5, 6, 9, 12, 14, 18, 22, 23, 30, 33, 38];
+    let clock_toggles = [2, 3, 8, 11, 13, 20, 24, 26, 27, 35, 39];
+    assert_lowered_matches(
+        build,
+        &[(0, &data_toggles), (1, &clock_toggles)],
+        &[(3, NodeType::Latch { delay: 1 })],
+    );
+}
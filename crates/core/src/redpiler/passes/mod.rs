@@ -1,13 +1,18 @@
+mod analog_range;
 mod clamp_weights;
 mod coalesce;
 mod constant_coalesce;
 mod constant_fold;
+mod dead_nodes;
 mod dedup_links;
+mod delay_line_fold;
 mod identify_nodes;
 mod input_search;
+mod logic_gates;
 mod unreachable_output;
 
 use super::compile_graph::CompileGraph;
+use super::report::PassReport;
 use super::{CompilerInput, CompilerOptions};
 use std::time::Instant;
 use tracing::trace;
@@ -18,9 +23,13 @@ pub const DEFAULT_PASS_MANAGER: PassManager<'_> = PassManager::new(&[
     &clamp_weights::ClampWeights,
     &dedup_links::DedupLinks,
     &constant_fold::ConstantFold,
+    &analog_range::AnalogRange,
     &unreachable_output::UnreachableOutput,
+    &dead_nodes::DeadNodes,
     &constant_coalesce::ConstantCoalesce,
     &coalesce::Coalesce,
+    &delay_line_fold::DelayLineFold,
+    &logic_gates::LogicGates,
 ]);
 
 pub struct PassManager<'p> {
@@ -32,26 +41,49 @@ impl<'p> PassManager<'p> {
         Self { passes }
     }
 
-    pub fn run_passes(&self, options: &CompilerOptions, input: CompilerInput<'_>) -> CompileGraph {
+    /// Runs every pass that should run with the given options, returning the final graph along
+    /// with statistics for each pass that ran. If the compile is cancelled through
+    /// [`CompilerInput::progress`], the remaining passes are skipped.
+    pub fn run_passes(
+        &self,
+        options: &CompilerOptions,
+        input: CompilerInput<'_>,
+    ) -> (CompileGraph, Vec<PassReport>) {
         let mut graph = CompileGraph::new();
+        let mut reports = Vec::new();
 
         for &pass in self.passes {
+            if input.progress.is_cancelled() {
+                break;
+            }
             if !pass.should_run(options) {
                 trace!("Skipping pass: {}", pass.name());
                 continue;
             }
 
             trace!("Running pass: {}", pass.name());
+            input.progress.start_pass(pass.name());
+            let nodes_before = graph.node_count();
+            let edges_before = graph.edge_count();
             let start = Instant::now();
 
             pass.run_pass(&mut graph, options, &input);
 
-            trace!("Completed pass in {:?}", start.elapsed());
+            let time = start.elapsed();
+            trace!("Completed pass in {:?}", time);
             trace!("node_count: {}", graph.node_count());
             trace!("edge_count: {}", graph.edge_count());
+            reports.push(PassReport {
+                name: pass.name(),
+                time,
+                nodes_before,
+                nodes_after: graph.node_count(),
+                edges_before,
+                edges_after: graph.edge_count(),
+            });
         }
 
-        graph
+        (graph, reports)
     }
 }
 
@@ -0,0 +1,135 @@
+//! Statistics collected while compiling a plot. These are meant for tracking how well the
+//! optimization passes perform over time, see [`CompileReport`].
+
+use super::backend::FinalGraphStats;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::time::Duration;
+
+/// Statistics for a single pass that ran during compilation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PassReport {
+    pub name: &'static str,
+    #[serde(rename = "time_us", serialize_with = "serialize_micros")]
+    pub time: Duration,
+    pub nodes_before: usize,
+    pub nodes_after: usize,
+    pub edges_before: usize,
+    pub edges_after: usize,
+}
+
+impl PassReport {
+    /// The number of nodes removed by this pass. This is negative for passes that add nodes.
+    pub fn nodes_removed(&self) -> isize {
+        self.nodes_before as isize - self.nodes_after as isize
+    }
+
+    /// The number of edges removed by this pass. This is negative for passes that add edges.
+    pub fn edges_removed(&self) -> isize {
+        self.edges_before as isize - self.edges_after as isize
+    }
+
+    /// The name of the pass without its module path
+    pub fn short_name(&self) -> &'static str {
+        self.name.rsplit("::").next().unwrap_or(self.name)
+    }
+}
+
+/// Returned by [`Compiler::compile`](super::Compiler::compile). Prints as a table and can be
+/// serialized to JSON using [`CompileReport::to_json`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CompileReport {
+    /// When the graph was loaded from a [`GraphCache`](super::GraphCache), this only contains a
+    /// single entry for loading the graph
+    pub passes: Vec<PassReport>,
+    pub from_cache: bool,
+    /// Statistics of the final graph, if a backend was compiled
+    pub backend: Option<FinalGraphStats>,
+    #[serde(rename = "backend_time_us", serialize_with = "serialize_micros")]
+    pub backend_time: Duration,
+    #[serde(rename = "total_time_us", serialize_with = "serialize_micros")]
+    pub total_time: Duration,
+}
+
+impl CompileReport {
+    pub fn to_json(&self) -> String {
+        // Serializing can't fail, all keys are strings
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// The node count of the final graph
+    pub fn node_count(&self) -> usize {
+        self.passes.last().map_or(0, |pass| pass.nodes_after)
+    }
+
+    /// The edge count of the final graph
+    pub fn edge_count(&self) -> usize {
+        self.passes.last().map_or(0, |pass| pass.edges_after)
+    }
+}
+
+impl fmt::Display for CompileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<24} {:>10} {:>8} {:>8} {:>8} {:>8}",
+            "pass", "time", "nodes", "removed", "edges", "removed"
+        )?;
+        for pass in &self.passes {
+            writeln!(
+                f,
+                "{:<24} {:>10} {:>8} {:>8} {:>8} {:>8}",
+                pass.short_name(),
+                format!("{:.2?}", pass.time),
+                pass.nodes_after,
+                pass.nodes_removed(),
+                pass.edges_after,
+                pass.edges_removed()
+            )?;
+        }
+        if let Some(stats) = &self.backend {
+            writeln!(
+                f,
+                "backend compiled in {:.2?}: {} default links, {} side links, {} update links, {} bytes of nodes",
+                self.backend_time,
+                stats.default_link_count,
+                stats.side_link_count,
+                stats.update_link_count,
+                stats.nodes_bytes
+            )?;
+        }
+        write!(f, "total: {:.2?}", self.total_time)
+    }
+}
+
+fn serialize_micros<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_micros() as u64)
+}
+
+#[test]
+fn compile_report_json() {
+    let report = CompileReport {
+        passes: vec![PassReport {
+            name: "mchprs_core::redpiler::passes::dead_nodes::DeadNodes",
+            time: Duration::from_micros(1500),
+            nodes_before: 10,
+            nodes_after: 7,
+            edges_before: 12,
+            edges_after: 8,
+        }],
+        from_cache: false,
+        backend: Some(FinalGraphStats::default()),
+        backend_time: Duration::from_micros(20),
+        total_time: Duration::from_millis(2),
+    };
+    assert_eq!(report.passes[0].short_name(), "DeadNodes");
+    assert_eq!(report.passes[0].nodes_removed(), 3);
+    assert!(report.to_string().contains("DeadNodes"));
+
+    let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+    assert_eq!(json["passes"][0]["time_us"], 1500);
+    assert_eq!(json["passes"][0]["edges_after"], 8);
+    assert_eq!(json["backend"]["nodes_bytes"], 0);
+    assert_eq!(json["total_time_us"], 2000);
+    assert_eq!(json["from_cache"], false);
+}
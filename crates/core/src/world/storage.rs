@@ -1,9 +1,14 @@
 use itertools::Itertools;
 use mchprs_blocks::block_entities::BlockEntity;
 use mchprs_blocks::BlockPos;
+use mchprs_network::packets::clientbound::{
+    C3BMultiBlockChangeRecord, CChunkData, CChunkDataBlockEntity, CChunkDataSection,
+    CMultiBlockChange,
+};
+use mchprs_network::packets::{EncodeResult, PacketEncodeError, PalettedContainer};
 use mchprs_save_data::plot_data::{ChunkData, ChunkSectionData};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::mem;
 
@@ -42,28 +47,28 @@ impl BitBuffer {
             8 => fast_arr_idx::<8>,
             7 => fast_arr_idx::<7>,
             4 => fast_arr_idx::<4>,
+            3 => fast_arr_idx::<3>,
+            2 => fast_arr_idx::<2>,
+            64 => fast_arr_idx::<64>,
             _ => unreachable!("entries_per_long cannot be {}", entries_per_long),
         }
     }
 
     pub fn create(bits_per_entry: u8, entries: usize) -> BitBuffer {
-        // 4..9, 15
+        // 0, 4..9, 15..32
+        if bits_per_entry == 0 {
+            return BitBuffer::load(entries, 0, Vec::new());
+        }
         let entries_per_long = 64 / bits_per_entry as u64;
         // Rounding up div
         let longs_len = (entries + entries_per_long as usize - 1) / entries_per_long as usize;
         let longs = vec![0; longs_len];
-        BitBuffer {
-            bits_per_entry: bits_per_entry as u64,
-            longs,
-            entries,
-            entries_per_long,
-            mask: (1 << bits_per_entry) - 1,
-            fast_arr_idx: BitBuffer::find_fast_arr_idx_fn(entries_per_long as usize),
-        }
+        BitBuffer::load(entries, bits_per_entry, longs)
     }
 
     fn load(entries: usize, bits_per_entry: u8, longs: Vec<u64>) -> BitBuffer {
-        let entries_per_long = 64 / bits_per_entry as u64;
+        // With 0 bits per entry every entry is 0 and there is no data, this is never indexed
+        let entries_per_long = 64_u64.checked_div(bits_per_entry as u64).unwrap_or(64);
         BitBuffer {
             bits_per_entry: bits_per_entry as u64,
             longs,
@@ -75,6 +80,9 @@ impl BitBuffer {
     }
 
     pub fn get_entry(&self, word_idx: usize) -> u32 {
+        if self.bits_per_entry == 0 {
+            return 0;
+        }
         // Find the set of indices.
         let arr_idx = (self.fast_arr_idx)(word_idx);
         let sub_idx =
@@ -85,6 +93,16 @@ impl BitBuffer {
     }
 
     pub fn set_entry(&mut self, word_idx: usize, word: u32) {
+        if self.bits_per_entry == 0 {
+            debug_assert_eq!(word, 0, "entry does not fit in 0 bits");
+            return;
+        }
+        debug_assert!(
+            word as u64 <= self.mask,
+            "entry {} does not fit in {} bits",
+            word,
+            self.bits_per_entry
+        );
         // Find the set of indices.
         let arr_idx = (self.fast_arr_idx)(word_idx);
         let sub_idx =
@@ -108,6 +126,8 @@ fn bitbuffer_format() {
     assert_eq!(buffer.longs[1], 0x01018A7260F68C87);
 }
 
+/// Entries are stored either as indices into a palette, or directly once there are too many
+/// distinct values. With 0 bits per entry, the buffer holds a single value, the only palette entry.
 #[derive(Debug, Clone)]
 pub struct PalettedBitBuffer {
     data: BitBuffer,
@@ -119,12 +139,17 @@ pub struct PalettedBitBuffer {
 }
 
 impl PalettedBitBuffer {
+    /// Bits per entry used once the single value palette outgrows itself
+    const MIN_PALETTE_BITS: u8 = 4;
+    /// Bits per entry of the global palette. Clients can't read direct entries any wider.
+    const GLOBAL_PALETTE_BITS: u8 = 15;
+
+    /// Creates a buffer where every entry is 0
     pub fn new(entries: usize, direct_threshold: u64) -> PalettedBitBuffer {
-        let palette = vec![0];
         PalettedBitBuffer {
-            data: BitBuffer::create(4, entries),
-            palette,
-            max_entries: 16,
+            data: BitBuffer::create(0, entries),
+            palette: vec![0],
+            max_entries: 1,
             use_palette: true,
             direct_threshold,
         }
@@ -140,12 +165,18 @@ impl PalettedBitBuffer {
         PalettedBitBuffer {
             data: BitBuffer::load(entries, bits_per_entry, longs),
             palette,
-            use_palette: bits_per_entry < 9,
-            max_entries: 1 << bits_per_entry,
+            use_palette: (bits_per_entry as u64) < direct_threshold,
+            max_entries: 1_u32.checked_shl(bits_per_entry as u32).unwrap_or(u32::MAX),
             direct_threshold,
         }
     }
 
+    /// Bits per entry needed to store `val` directly. As of 1.16, the global palette requires 15
+    /// bits, more are only used for ids outside of it.
+    fn direct_bits(val: u32) -> u8 {
+        ((u32::BITS - val.leading_zeros()) as u8).max(PalettedBitBuffer::GLOBAL_PALETTE_BITS)
+    }
+
     fn resize_buffer(&mut self) {
         assert!(
             self.use_palette,
@@ -153,26 +184,19 @@ impl PalettedBitBuffer {
         );
         let old_bits_per_entry = self.data.bits_per_entry;
         // It is more efficient to use the global palette when the bits reaches the treshold
-        // As of 1.16, the global palette requires 15 bits
-        let new_bits = if old_bits_per_entry + 1 >= self.direct_threshold {
-            self.max_entries = 1 << 15;
-            self.use_palette = false;
-            15
-        } else {
-            self.max_entries <<= 1;
-            old_bits_per_entry as u8 + 1
-        };
+        if old_bits_per_entry + 1 >= self.direct_threshold {
+            let largest = self.palette.iter().copied().max().unwrap_or(0);
+            self.resize_direct(PalettedBitBuffer::direct_bits(largest));
+            return;
+        }
+        let new_bits = (old_bits_per_entry as u8 + 1).max(PalettedBitBuffer::MIN_PALETTE_BITS);
+        self.max_entries = 1 << new_bits;
         // Swap out the old buffer
         let mut old_buffer = BitBuffer::create(new_bits, self.data.entries);
         mem::swap(&mut self.data, &mut old_buffer);
         // Copy entries into new buffer
-        if new_bits == 15 {
-            for entry_idx in 0..old_buffer.entries {
-                let entry = self.palette[old_buffer.get_entry(entry_idx) as usize];
-                self.data.set_entry(entry_idx, entry);
-            }
-            // Deallocate the old palette
-            self.palette = Vec::new();
+        if old_bits_per_entry == 0 {
+            // Every entry was index 0, which the new buffer is already filled with
         } else {
             for entry_idx in 0..old_buffer.entries {
                 let entry = old_buffer.get_entry(entry_idx);
@@ -181,6 +205,24 @@ impl PalettedBitBuffer {
         }
     }
 
+    /// Stores the entries directly with `bits` per entry, either when leaving the palette or when
+    /// a direct buffer gets an id which does not fit its current bits
+    fn resize_direct(&mut self, bits: u8) {
+        let mut old_buffer = BitBuffer::create(bits, self.data.entries);
+        mem::swap(&mut self.data, &mut old_buffer);
+        for entry_idx in 0..old_buffer.entries {
+            let entry = if self.use_palette {
+                self.palette[old_buffer.get_entry(entry_idx) as usize]
+            } else {
+                old_buffer.get_entry(entry_idx)
+            };
+            self.data.set_entry(entry_idx, entry);
+        }
+        self.use_palette = false;
+        // Deallocate the old palette
+        self.palette = Vec::new();
+    }
+
     pub fn get_entry(&self, index: usize) -> u32 {
         if self.use_palette {
             self.palette[self.data.get_entry(index) as usize]
@@ -204,6 +246,10 @@ impl PalettedBitBuffer {
                 self.data.set_entry(index, palette_index as u32);
             }
         } else {
+            let bits = PalettedBitBuffer::direct_bits(val);
+            if bits as u64 > self.data.bits_per_entry {
+                self.resize_direct(bits);
+            }
             self.data.set_entry(index, val);
         }
     }
@@ -211,12 +257,124 @@ impl PalettedBitBuffer {
     pub fn entries(&self) -> usize {
         self.data.entries
     }
+
+    /// Returns the palette, or `None` if entries are stored directly. Entries are only removed
+    /// from the palette by [`compact`](PalettedBitBuffer::compact), so it may contain values
+    /// which are no longer in the buffer.
+    pub fn palette(&self) -> Option<&[u32]> {
+        self.use_palette.then_some(&self.palette[..])
+    }
+
+    /// Removes palette entries which are no longer used and stores the buffer with the fewest
+    /// bits per entry that fit the remaining values. A buffer holding only one value switches to
+    /// the single value palette, and a direct buffer switches back to a palette if it can.
+    pub fn compact(&mut self) {
+        let entries = self.entries();
+        // The values still in use, in palette order or in order of first occurrence when the
+        // entries are stored directly
+        let mut values = Vec::new();
+        if self.use_palette {
+            let mut used = vec![false; self.palette.len()];
+            for i in 0..entries {
+                used[self.data.get_entry(i) as usize] = true;
+            }
+            values.extend(
+                self.palette
+                    .iter()
+                    .zip(used)
+                    .filter_map(|(&value, used)| used.then_some(value)),
+            );
+        } else {
+            let mut seen = HashSet::new();
+            for i in 0..entries {
+                let value = self.data.get_entry(i);
+                if seen.insert(value) {
+                    values.push(value);
+                }
+            }
+        }
+
+        let bits = match values.len() {
+            1 => 0,
+            len => (usize::BITS - (len - 1).leading_zeros()).max(4) as u8,
+        };
+        if bits as u64 >= self.direct_threshold
+            || (bits as u64 == self.data.bits_per_entry && values.len() == self.palette.len())
+        {
+            return;
+        }
+
+        let indices: HashMap<u32, u32> = values
+            .iter()
+            .enumerate()
+            .map(|(index, &value)| (value, index as u32))
+            .collect();
+        let mut data = BitBuffer::create(bits, entries);
+        for i in 0..entries {
+            data.set_entry(i, indices[&self.get_entry(i)]);
+        }
+        self.data = data;
+        self.palette = values;
+        self.max_entries = 1 << bits;
+        self.use_palette = true;
+    }
 }
 
+#[test]
+fn palette_compaction() {
+    let mut buffer = PalettedBitBuffer::new(4096, 9);
+    assert_eq!(buffer.data.bits_per_entry, 0);
+    assert!(buffer.data.longs.is_empty());
+
+    for i in 0..20 {
+        buffer.set_entry(i, 100 + i as u32);
+    }
+    assert_eq!(buffer.data.bits_per_entry, 5);
+    for i in (0..20).filter(|&i| i != 3) {
+        buffer.set_entry(i, 0);
+    }
+    buffer.compact();
+    assert_eq!(buffer.palette(), Some(&[0, 103][..]));
+    assert_eq!(buffer.data.bits_per_entry, 4);
+    assert_eq!(buffer.get_entry(3), 103);
+    assert_eq!(buffer.get_entry(4), 0);
+
+    // A buffer of a single value needs no data at all
+    buffer.set_entry(3, 0);
+    buffer.compact();
+    assert_eq!(buffer.palette(), Some(&[0][..]));
+    assert_eq!(buffer.data.bits_per_entry, 0);
+    assert!(buffer.data.longs.is_empty());
+    assert_eq!(buffer.get_entry(3), 0);
+
+    // Direct buffers go back to using a palette
+    for i in 0..300 {
+        buffer.set_entry(i, i as u32);
+    }
+    assert_eq!(buffer.palette(), None);
+    for i in 2..300 {
+        buffer.set_entry(i, 7);
+    }
+    buffer.compact();
+    assert_eq!(buffer.palette(), Some(&[0, 1, 7][..]));
+    assert_eq!(buffer.data.bits_per_entry, 4);
+    assert_eq!(
+        (0..4).map(|i| buffer.get_entry(i)).collect::<Vec<_>>(),
+        [0, 1, 7, 7]
+    );
+    assert_eq!(buffer.get_entry(299), 7);
+    assert_eq!(buffer.get_entry(4095), 0);
+}
+
+const SECTION_BLOCKS: usize = 16 * 16 * 16;
+
+#[derive(Clone)]
 pub struct ChunkSection {
     buffer: PalettedBitBuffer,
     block_count: u32,
-    changed_blocks: [i16; 16 * 16 * 16],
+    /// One bit per block, set if the block changed since the last time changes were drained.
+    /// Blocks are written to the buffer directly, so this only tracks what to send to clients.
+    changed_blocks: [u64; SECTION_BLOCKS / 64],
     changed: bool,
 }
 
@@ -226,29 +384,25 @@ impl ChunkSection {
     }
 
     fn get_block(&self, x: u32, y: u32, z: u32) -> u32 {
-        let idx = ChunkSection::get_index(x, y, z);
-        if self.changed_blocks[idx] >= 0 {
-            self.changed_blocks[idx] as u32
-        } else {
-            self.buffer.get_entry(idx)
-        }
+        self.buffer.get_entry(ChunkSection::get_index(x, y, z))
     }
 
     /// Sets a block in the chunk sections. Returns true if a block was changed.
     fn set_block(&mut self, x: u32, y: u32, z: u32, block: u32) -> bool {
-        let old_block = self.get_block(x, y, z);
-        if old_block == 0 && block != 0 {
+        let idx = ChunkSection::get_index(x, y, z);
+        let old_block = self.buffer.get_entry(idx);
+        if old_block == block {
+            return false;
+        }
+        if old_block == 0 {
             self.block_count += 1;
-        } else if old_block != 0 && block == 0 {
+        } else if block == 0 {
             self.block_count -= 1;
         }
-        let idx = ChunkSection::get_index(x, y, z);
-        let changed = old_block != block;
-        if changed {
-            self.changed = true;
-            self.changed_blocks[idx] = block as i16;
-        }
-        changed
+        self.buffer.set_entry(idx, block);
+        self.changed_blocks[idx / 64] |= 1 << (idx % 64);
+        self.changed = true;
+        true
     }
 
     fn load(data: Option<ChunkSectionData>) -> ChunkSection {
@@ -265,13 +419,13 @@ impl ChunkSection {
         ChunkSection {
             buffer,
             block_count: data.block_count as u32,
-            changed_blocks: [-1; 16 * 16 * 16],
+            changed_blocks: [0; SECTION_BLOCKS / 64],
             changed: false,
         }
     }
 
     fn save(&mut self) -> Option<ChunkSectionData> {
-        self.flush();
+        self.buffer.compact();
         if self.buffer.use_palette && self.buffer.palette.len() == 1 && self.buffer.palette[0] == 0
         {
             // chunk section is completely air
@@ -302,22 +456,81 @@ impl ChunkSection {
         })
     }
 
-    fn compress(&mut self) {
-        let mut new_buffer = PalettedBitBuffer::new(4096, 9);
-        for i in 0..4096 {
-            new_buffer.set_entry(i, self.buffer.get_entry(i));
+    /// Returns the blocks changed since the last call
+    fn drain_changes(&mut self) -> Vec<C3BMultiBlockChangeRecord> {
+        if !self.changed {
+            return Vec::new();
         }
-        self.buffer = new_buffer;
-    }
 
-    fn flush(&mut self) {
-        if self.changed {
-            for (i, block) in self.changed_blocks.iter().enumerate() {
-                if *block >= 0 {
-                    self.buffer.set_entry(i, *block as u32);
-                }
+        let mut records = Vec::new();
+        for (word_idx, word) in self.changed_blocks.iter_mut().enumerate() {
+            while *word != 0 {
+                let i = word_idx * 64 + word.trailing_zeros() as usize;
+                // Clear the lowest set bit
+                *word &= *word - 1;
+                records.push(C3BMultiBlockChangeRecord {
+                    x: (i & 0xF) as u8,
+                    y: (i >> 8) as u8,
+                    z: ((i >> 4) & 0xF) as u8,
+                    block_id: self.buffer.get_entry(i),
+                });
             }
         }
+        self.changed = false;
+        records
+    }
+
+    /// Fails if the section stores ids directly which do not fit in the global palette
+    fn encode_packet(&self) -> EncodeResult<CChunkDataSection> {
+        let buffer = &self.buffer;
+        let bits_per_entry = buffer.data.bits_per_entry as u8;
+        if buffer.palette().is_none() && bits_per_entry > PalettedBitBuffer::GLOBAL_PALETTE_BITS {
+            return Err(PacketEncodeError::TooManyBitsPerEntry(bits_per_entry));
+        }
+        let palette = buffer
+            .palette()
+            .map(|palette| palette.iter().map(|&id| id as i32).collect());
+        Ok(CChunkDataSection {
+            block_count: self.block_count as i16,
+            block_states: PalettedContainer {
+                bits_per_entry,
+                palette,
+                data_array: buffer.data.longs.clone(),
+            },
+            // Single valued palette, every block is in the plains biome
+            biomes: PalettedContainer {
+                bits_per_entry: 0,
+                palette: Some(vec![0]),
+                data_array: Vec::new(),
+            },
+        })
+    }
+
+    /// Hashes the blocks of this section. Sections containing the same blocks may still hash
+    /// differently, as the layout of the palette depends on the order blocks were placed in. The
+    /// hash does not change between builds, so it can be stored.
+    pub fn content_hash(&self) -> [u8; 16] {
+        let buffer = &self.buffer;
+        let mut context = md5::Context::new();
+        context.consume([buffer.use_palette as u8, buffer.data.bits_per_entry as u8]);
+        context.consume((buffer.palette.len() as u32).to_le_bytes());
+        for entry in &buffer.palette {
+            context.consume(entry.to_le_bytes());
+        }
+        for long in &buffer.data.longs {
+            context.consume(long.to_le_bytes());
+        }
+        context.compute().0
+    }
+
+    /// Returns false if there is definitely no block in this section matching `pred`. This only
+    /// looks at the palette, so it is much cheaper than checking every block, but it may return
+    /// true when no block matches.
+    pub fn may_contain(&self, pred: impl FnMut(u32) -> bool) -> bool {
+        match self.buffer.palette() {
+            Some(palette) => palette.iter().copied().any(pred),
+            None => true,
+        }
     }
 }
 
@@ -326,12 +539,106 @@ impl Default for ChunkSection {
         ChunkSection {
             buffer: PalettedBitBuffer::new(4096, 9),
             block_count: 0,
-            changed_blocks: [-1; 16 * 16 * 16],
+            changed_blocks: [0; SECTION_BLOCKS / 64],
             changed: false,
         }
     }
 }
 
+#[test]
+fn section_may_contain() {
+    let mut section = ChunkSection::default();
+    assert!(!section.may_contain(|id| id == 5));
+
+    section.set_block(1, 2, 3, 5);
+    assert!(section.may_contain(|id| id == 5));
+
+    // Replacing the block keeps it in the palette
+    section.set_block(1, 2, 3, 0);
+    assert!(section.may_contain(|id| id == 5));
+    assert!(!section.may_contain(|id| id == 6));
+}
+
+#[test]
+fn section_large_state_ids() {
+    let mut section = ChunkSection::default();
+    assert!(section.set_block(1, 2, 3, 40000));
+    assert!(section.set_block(4, 5, 6, u32::MAX >> 1));
+    assert!(!section.set_block(1, 2, 3, 40000));
+    assert_eq!(section.get_block(1, 2, 3), 40000);
+    assert_eq!(section.block_count, 2);
+
+    let records = section.drain_changes();
+    let ids: Vec<u32> = records.iter().map(|r| r.block_id).collect();
+    assert_eq!(ids, [40000, u32::MAX >> 1]);
+    assert!(section.drain_changes().is_empty());
+
+    let data = section.save().unwrap();
+    let loaded = ChunkSection::load(Some(data));
+    assert_eq!(loaded.get_block(1, 2, 3), 40000);
+    assert_eq!(loaded.get_block(4, 5, 6), u32::MAX >> 1);
+
+    // With more than 256 distinct ids the section stores them directly, large ids must not spill
+    // into the neighbouring entries
+    let mut section = ChunkSection::default();
+    let id = |idx: u32| if idx & 1 == 0 { 32768 + idx } else { idx };
+    for idx in 0..300 {
+        section.set_block(idx & 0xF, idx >> 8, (idx >> 4) & 0xF, id(idx));
+    }
+    assert_eq!(section.buffer.palette(), None);
+    assert_eq!(section.buffer.data.bits_per_entry, 16);
+    section.set_block(15, 15, 15, u32::MAX);
+    assert_eq!(section.buffer.data.bits_per_entry, 32);
+    for idx in 0..300 {
+        assert_eq!(
+            section.get_block(idx & 0xF, idx >> 8, (idx >> 4) & 0xF),
+            id(idx)
+        );
+    }
+    assert_eq!(section.get_block(15, 15, 15), u32::MAX);
+
+    let loaded = ChunkSection::load(section.save());
+    for idx in 0..300 {
+        assert_eq!(
+            loaded.get_block(idx & 0xF, idx >> 8, (idx >> 4) & 0xF),
+            id(idx)
+        );
+    }
+    assert_eq!(loaded.get_block(15, 15, 15), u32::MAX);
+}
+
+#[test]
+fn section_save_compacts() {
+    let mut section = ChunkSection::default();
+    for x in 0..16 {
+        section.set_block(x, 0, 0, x + 1);
+    }
+    for x in 0..16 {
+        section.set_block(x, 0, 0, 0);
+    }
+    // Only air is left, so nothing is saved
+    assert!(section.save().is_none());
+
+    section.set_block(1, 2, 3, 5);
+    let data = section.save().unwrap();
+    assert_eq!(data.palette, [0, 5]);
+    assert_eq!(data.bits_per_block, 4);
+    let loaded = ChunkSection::load(Some(data));
+    assert_eq!(loaded.get_block(1, 2, 3), 5);
+
+    let mut full = ChunkSection::default();
+    for idx in 0..SECTION_BLOCKS as u32 {
+        full.set_block(idx & 0xF, idx >> 8, (idx >> 4) & 0xF, 1);
+    }
+    let data = full.save().unwrap();
+    assert_eq!((data.bits_per_block, data.palette.len()), (0, 1));
+    assert!(data.data.is_empty());
+    let loaded = ChunkSection::load(Some(data));
+    assert_eq!(loaded.get_block(15, 15, 15), 1);
+    assert_eq!(loaded.block_count, 4096);
+}
+
+#[derive(Clone)]
 pub struct Chunk {
     pub sections: [ChunkSection; 16],
     pub x: i32,
@@ -353,6 +660,22 @@ impl Chunk {
         top_most
     }
 
+    /// Returns the y coordinate above the highest non-air block in the column at `x` and `z`, or
+    /// 0 if the column is empty. This is what the `MOTION_BLOCKING` heightmap contains.
+    pub fn get_height(&self, x: u32, z: u32) -> u32 {
+        for (section_y, section) in self.sections.iter().enumerate().rev() {
+            if section.block_count == 0 {
+                continue;
+            }
+            for y in (0..16).rev() {
+                if section.get_block(x, y, z) != 0 {
+                    return section_y as u32 * 16 + y + 1;
+                }
+            }
+        }
+        0
+    }
+
     /// Sets a block in the chunk. Returns true if a block was changed.
     pub fn set_block(&mut self, x: u32, y: u32, z: u32, block_id: u32) -> bool {
         let section_y = (y >> 4) as usize;
@@ -380,6 +703,75 @@ impl Chunk {
         self.block_entities.insert(pos, block_entity);
     }
 
+    /// Encodes the chunk into a packet that can be sent to clients. Fails if a section contains
+    /// ids outside of the global palette.
+    pub fn encode_packet(&self) -> EncodeResult<CChunkData> {
+        let mut heightmap_buffer = BitBuffer::create(9, 16 * 16);
+        for x in 0..16 {
+            for z in 0..16 {
+                heightmap_buffer.set_entry((z * 16) + x, self.get_height(x as u32, z as u32));
+            }
+        }
+        let heightmap_longs: Vec<i64> = heightmap_buffer
+            .longs
+            .into_iter()
+            .map(|x| x as i64)
+            .collect();
+        let mut heightmaps = nbt::Blob::new();
+        heightmaps
+            .insert("MOTION_BLOCKING", heightmap_longs)
+            .unwrap();
+
+        let chunk_sections = self
+            .sections
+            .iter()
+            .map(ChunkSection::encode_packet)
+            .collect::<EncodeResult<_>>()?;
+
+        let block_entities = self
+            .block_entities
+            .iter()
+            .filter_map(|(pos, block_entity)| {
+                // Clients only need to know about the text on signs
+                let data = block_entity.to_nbt(true)?;
+                Some(CChunkDataBlockEntity {
+                    x: (pos.x & 0xF) as i8,
+                    z: (pos.z & 0xF) as i8,
+                    y: pos.y as i16,
+                    ty: block_entity.ty(),
+                    data,
+                })
+            })
+            .collect();
+
+        Ok(CChunkData {
+            chunk_x: self.x,
+            chunk_z: self.z,
+            heightmaps,
+            chunk_sections,
+            block_entities,
+        })
+    }
+
+    /// Returns a multi block change packet for every section with blocks changed since the last
+    /// call. The changes are written to the chunk storage and are not returned again.
+    pub fn drain_multi_block_changes(&mut self) -> Vec<CMultiBlockChange> {
+        let mut packets = Vec::new();
+        for (section_y, section) in self.sections.iter_mut().enumerate() {
+            let records = section.drain_changes();
+            if records.is_empty() {
+                continue;
+            }
+            packets.push(CMultiBlockChange {
+                chunk_x: self.x,
+                chunk_z: self.z,
+                chunk_y: section_y as u32,
+                records,
+            });
+        }
+        packets
+    }
+
     pub fn save(&mut self) -> ChunkData {
         ChunkData {
             sections: self
@@ -407,10 +799,11 @@ impl Chunk {
         }
     }
 
+    /// Compacts the palette of every section, this also happens when the chunk is saved
     pub fn compress(&mut self) {
         self.sections
             .iter_mut()
-            .for_each(|section| section.compress());
+            .for_each(|section| section.buffer.compact());
     }
 
     pub fn empty(x: i32, z: i32) -> Chunk {
@@ -422,3 +815,104 @@ impl Chunk {
         }
     }
 }
+
+#[test]
+fn encode_chunk_packet() {
+    use mchprs_blocks::block_entities::SignBlockEntity;
+    use mchprs_network::packets::clientbound::ClientBoundPacket;
+
+    let mut chunk = Chunk::empty(2, 3);
+    chunk.set_block(1, 2, 3, 5);
+    chunk.set_block(4, 5, 6, 7);
+    chunk.set_block(0, 20, 0, 7);
+    chunk.set_block(5, 0, 5, 1);
+    let sign = SignBlockEntity {
+        rows: Default::default(),
+    };
+    chunk.set_block_entity(BlockPos::new(33, 20, 48), BlockEntity::Sign(Box::new(sign)));
+    chunk.set_block_entity(
+        BlockPos::new(34, 2, 49),
+        BlockEntity::Comparator { output_strength: 3 },
+    );
+
+    let packet = chunk.encode_packet().unwrap();
+    assert_eq!((packet.chunk_x, packet.chunk_z), (2, 3));
+    assert_eq!(packet.chunk_sections.len(), 16);
+
+    let section = &packet.chunk_sections[0];
+    assert_eq!(section.block_count, 3);
+    let palette = section.block_states.palette.as_ref().unwrap();
+    let mut expected = PalettedBitBuffer::new(4096, 9);
+    expected.set_entry(ChunkSection::get_index(1, 2, 3), 5);
+    expected.set_entry(ChunkSection::get_index(4, 5, 6), 7);
+    expected.set_entry(ChunkSection::get_index(5, 0, 5), 1);
+    assert_eq!(palette, &[0, 5, 7, 1]);
+    assert_eq!(section.block_states.data_array, expected.data.longs);
+    assert_eq!(packet.chunk_sections[1].block_count, 1);
+
+    // The heightmap holds the y above the top most block, and 0 for empty columns
+    let heightmap = match &packet.heightmaps["MOTION_BLOCKING"] {
+        nbt::Value::LongArray(longs) => longs.iter().map(|&x| x as u64).collect(),
+        value => panic!("expected a long array, got {:?}", value),
+    };
+    let heightmap = BitBuffer::load(256, 9, heightmap);
+    assert_eq!(heightmap.get_entry(3 * 16 + 1), 3);
+    assert_eq!(heightmap.get_entry(20), 0);
+    assert_eq!(heightmap.get_entry(5 * 16 + 5), 1);
+    assert_eq!(heightmap.get_entry(0), 21);
+
+    // Only signs are sent to clients
+    assert_eq!(packet.block_entities.len(), 1);
+    let sign = &packet.block_entities[0];
+    assert_eq!((sign.x, sign.y, sign.z), (1, 20, 0));
+
+    packet.encode().unwrap();
+
+    // Direct entries are sent with the 15 bits of the global palette, wider ones can't be read
+    for idx in 0..300 {
+        chunk.set_block(idx & 0xF, 32 + (idx >> 8), (idx >> 4) & 0xF, idx + 1);
+    }
+    let packet = chunk.encode_packet().unwrap();
+    let block_states = &packet.chunk_sections[2].block_states;
+    assert_eq!(block_states.bits_per_entry, 15);
+    assert_eq!(block_states.palette, None);
+    packet.encode().unwrap();
+    chunk.set_block(15, 47, 15, 32768);
+    assert!(matches!(
+        chunk.encode_packet(),
+        Err(PacketEncodeError::TooManyBitsPerEntry(16))
+    ));
+}
+
+#[test]
+fn drain_multi_block_changes() {
+    let mut chunk = Chunk::empty(2, 3);
+    chunk.set_block(1, 2, 3, 5);
+    chunk.set_block(4, 37, 6, 7);
+    assert!(chunk.set_block(4, 37, 6, 8));
+
+    let packets = chunk.drain_multi_block_changes();
+    assert_eq!(packets.len(), 2);
+    assert_eq!(
+        (packets[0].chunk_x, packets[0].chunk_z, packets[0].chunk_y),
+        (2, 3, 0)
+    );
+    let record = &packets[0].records[0];
+    assert_eq!(
+        (record.x, record.y, record.z, record.block_id),
+        (1, 2, 3, 5)
+    );
+    assert_eq!(packets[1].chunk_y, 2);
+    let record = &packets[1].records[0];
+    assert_eq!(
+        (record.x, record.y, record.z, record.block_id),
+        (4, 5, 6, 8)
+    );
+
+    // The changes are kept in storage but only sent once
+    assert!(chunk.drain_multi_block_changes().is_empty());
+    assert_eq!(chunk.get_block(1, 2, 3), 5);
+    assert_eq!(chunk.get_block(4, 37, 6), 8);
+    assert!(!chunk.set_block(1, 2, 3, 5));
+    assert!(chunk.drain_multi_block_changes().is_empty());
+}
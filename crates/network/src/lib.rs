@@ -1,2 +1,7 @@
-mod nbt_map;
+pub mod nbt_map;
 pub mod packets;
+pub mod status;
+
+/// The protocol version of the only Minecraft version supported
+pub const PROTOCOL_VERSION: i32 = 758;
+pub const MINECRAFT_VERSION: &str = "1.18.2";
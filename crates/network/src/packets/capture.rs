@@ -0,0 +1,386 @@
+//! Recording packets to a capture file and reading them back, so a session against a build can be
+//! replayed deterministically in tests.
+//!
+//! A capture starts with a header of [`CAPTURE_MAGIC`], the capture format version and the
+//! protocol version of the session. It is followed by one record per packet:
+//!
+//! | Field     | Type    | Notes                                               |
+//! |-----------|---------|-----------------------------------------------------|
+//! | Direction | u8      | 0 for serverbound, 1 for clientbound                |
+//! | Timestamp | VarLong | Microseconds since the capture was started          |
+//! | Packet ID | VarInt  |                                                     |
+//! | Length    | VarInt  | Length of the packet data                           |
+//! | Data      | Bytes   | The uncompressed packet data, without the packet ID |
+
+use super::clientbound::CPlayPacket;
+use super::codec::MAX_DATA_LENGTH;
+use super::serverbound::SPlayPacket;
+use super::{
+    DecodeResult, EncodeResult, PacketDecodeError, PacketDecoderExt, PacketEncoder,
+    PacketEncoderExt,
+};
+use crate::PROTOCOL_VERSION;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Cursor, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+pub const CAPTURE_MAGIC: &[u8; 8] = b"MCHPRSPC";
+const CAPTURE_VERSION: u8 = 1;
+
+pub type CaptureResult<T> = std::result::Result<T, CaptureError>;
+
+#[derive(Debug)]
+pub enum CaptureError {
+    /// A record or the packet in it could not be decoded
+    Decode(PacketDecodeError),
+    /// The file did not start with a supported capture header
+    InvalidHeader,
+    /// A record had a direction other than serverbound or clientbound
+    InvalidPacketDirection(u8),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::Decode(err) => write!(f, "{}", err),
+            CaptureError::InvalidHeader => write!(f, "not a packet capture"),
+            CaptureError::InvalidPacketDirection(direction) => {
+                write!(f, "invalid packet direction {}", direction)
+            }
+        }
+    }
+}
+
+impl Error for CaptureError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CaptureError::Decode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<PacketDecodeError> for CaptureError {
+    fn from(err: PacketDecodeError) -> CaptureError {
+        CaptureError::Decode(err)
+    }
+}
+
+impl From<io::Error> for CaptureError {
+    fn from(err: io::Error) -> CaptureError {
+        CaptureError::Decode(PacketDecodeError::Io(err))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketDirection {
+    /// Sent by the client to the server
+    Serverbound,
+    /// Sent by the server to the client
+    Clientbound,
+}
+
+impl PacketDirection {
+    fn id(self) -> u8 {
+        match self {
+            PacketDirection::Serverbound => 0,
+            PacketDirection::Clientbound => 1,
+        }
+    }
+
+    fn from_id(id: u8) -> CaptureResult<PacketDirection> {
+        match id {
+            0 => Ok(PacketDirection::Serverbound),
+            1 => Ok(PacketDirection::Clientbound),
+            id => Err(CaptureError::InvalidPacketDirection(id)),
+        }
+    }
+}
+
+/// Writes packets to a capture. Timestamps are taken relative to when the capture was created.
+pub struct PacketCapture<W: Write> {
+    writer: W,
+    start: Instant,
+    /// Each record is encoded here first, so it is written in one go
+    record: Vec<u8>,
+}
+
+impl PacketCapture<BufWriter<File>> {
+    /// Creates a capture file at `path`, replacing it if it already exists
+    pub fn create(path: impl AsRef<Path>) -> EncodeResult<PacketCapture<BufWriter<File>>> {
+        PacketCapture::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> PacketCapture<W> {
+    pub fn new(mut writer: W) -> EncodeResult<PacketCapture<W>> {
+        let mut header = CAPTURE_MAGIC.to_vec();
+        header.write_unsigned_byte(CAPTURE_VERSION)?;
+        header.write_varint(PROTOCOL_VERSION)?;
+        writer.write_all(&header)?;
+        Ok(PacketCapture {
+            writer,
+            start: Instant::now(),
+            record: header,
+        })
+    }
+
+    /// Records an encoded packet
+    pub fn record(
+        &mut self,
+        direction: PacketDirection,
+        packet: &PacketEncoder,
+    ) -> EncodeResult<()> {
+        self.record_raw(direction, packet.packet_id as i32, &packet.buffer)
+    }
+
+    /// Records a packet from its id and data. This is used for serverbound packets, which are
+    /// read as raw packet data instead of being encoded.
+    pub fn record_raw(
+        &mut self,
+        direction: PacketDirection,
+        id: i32,
+        data: &[u8],
+    ) -> EncodeResult<()> {
+        let timestamp = self.start.elapsed().as_micros() as i64;
+        self.record.clear();
+        self.record.write_unsigned_byte(direction.id())?;
+        self.record.write_varlong(timestamp)?;
+        self.record.write_varint(id)?;
+        self.record.write_varint(data.len() as i32)?;
+        self.record.write_bytes(data)?;
+        Ok(self.writer.write_all(&self.record)?)
+    }
+
+    /// Flushes the capture and returns the underlying writer
+    pub fn into_inner(mut self) -> EncodeResult<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// A single packet read from a capture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedPacket {
+    pub direction: PacketDirection,
+    /// The time since the capture was started
+    pub timestamp: Duration,
+    pub id: i32,
+    pub data: Vec<u8>,
+}
+
+impl CapturedPacket {
+    /// Decodes the packet as a serverbound play packet
+    pub fn decode_serverbound(&self) -> DecodeResult<SPlayPacket> {
+        SPlayPacket::decode(self.id, &mut Cursor::new(&self.data))
+    }
+
+    /// Decodes the packet as a clientbound play packet
+    pub fn decode_clientbound(&self) -> DecodeResult<CPlayPacket> {
+        CPlayPacket::decode(self.id, &mut Cursor::new(&self.data))
+    }
+}
+
+/// Reads the packets of a capture in the order they were recorded
+pub struct CaptureReader<R: Read> {
+    reader: CaptureDecoder<R>,
+    protocol_version: i32,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> CaptureResult<CaptureReader<BufReader<File>>> {
+        CaptureReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Reads the capture header
+    pub fn new(reader: R) -> CaptureResult<CaptureReader<R>> {
+        let mut reader = CaptureDecoder(reader);
+        let mut magic = [0; CAPTURE_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != CAPTURE_MAGIC || reader.read_unsigned_byte()? != CAPTURE_VERSION {
+            return Err(CaptureError::InvalidHeader);
+        }
+        let protocol_version = reader.read_varint()?;
+        Ok(CaptureReader {
+            reader,
+            protocol_version,
+        })
+    }
+
+    /// The protocol version the capture was recorded with. Packet ids and layouts are only
+    /// meaningful for that version.
+    pub fn protocol_version(&self) -> i32 {
+        self.protocol_version
+    }
+
+    /// Reads the next packet, returning `None` at the end of the capture
+    pub fn read_packet(&mut self) -> CaptureResult<Option<CapturedPacket>> {
+        let mut direction = [0];
+        loop {
+            match self.reader.read(&mut direction) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        let direction = PacketDirection::from_id(direction[0])?;
+
+        let timestamp = self.reader.read_varlong()?;
+        let id = self.reader.read_varint()?;
+        let length = self.reader.read_varint()?;
+        // Negative lengths wrap around to be larger than the maximum
+        if length as usize > MAX_DATA_LENGTH {
+            return Err(PacketDecodeError::InvalidDataLength(length).into());
+        }
+        let data = self.reader.read_bytes(length as usize)?;
+        Ok(Some(CapturedPacket {
+            direction,
+            timestamp: Duration::from_micros(timestamp as u64),
+            id,
+            data,
+        }))
+    }
+
+    /// Reads the next serverbound packet and decodes it, skipping clientbound packets. This is
+    /// what replaying a client session against a server needs.
+    pub fn read_serverbound(&mut self) -> CaptureResult<Option<(Duration, SPlayPacket)>> {
+        while let Some(packet) = self.read_packet()? {
+            if packet.direction == PacketDirection::Serverbound {
+                return Ok(Some((packet.timestamp, packet.decode_serverbound()?)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Gives any reader the packet decoding methods, which are only implemented for streams and
+/// cursors
+struct CaptureDecoder<R: Read>(R);
+
+impl<R: Read> Read for CaptureDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<R: Read> PacketDecoderExt for CaptureDecoder<R> {}
+
+#[test]
+fn record_and_replay() {
+    use super::clientbound::{CBlockChange, CKeepAlive, ClientBoundPacket};
+    use super::serverbound::SKeepAlive;
+
+    let mut capture = PacketCapture::new(Vec::new()).unwrap();
+    let keep_alive = CKeepAlive { id: 42 }.encode().unwrap();
+    capture
+        .record(PacketDirection::Clientbound, &keep_alive)
+        .unwrap();
+    capture
+        .record_raw(PacketDirection::Serverbound, 0x0F, &42i64.to_be_bytes())
+        .unwrap();
+    let mut placement = Vec::new();
+    placement.write_varint(0).unwrap();
+    placement.write_position(9, 7, 8).unwrap();
+    placement.write_varint(1).unwrap();
+    placement.write_float(0.5).unwrap();
+    placement.write_float(1.0).unwrap();
+    placement.write_float(0.5).unwrap();
+    placement.write_bool(false).unwrap();
+    capture
+        .record_raw(PacketDirection::Serverbound, 0x2E, &placement)
+        .unwrap();
+    let block_change = CBlockChange {
+        x: 9,
+        y: -7,
+        z: 8,
+        block_id: 1,
+    };
+    capture
+        .record(
+            PacketDirection::Clientbound,
+            &block_change.encode().unwrap(),
+        )
+        .unwrap();
+    let capture = capture.into_inner().unwrap();
+
+    let mut reader = CaptureReader::new(capture.as_slice()).unwrap();
+    assert_eq!(reader.protocol_version(), PROTOCOL_VERSION);
+    let first = reader.read_packet().unwrap().unwrap();
+    assert_eq!(first.direction, PacketDirection::Clientbound);
+    assert_eq!(first.id, 0x21);
+    assert_eq!(first.data, 42i64.to_be_bytes());
+    assert_eq!(
+        first.decode_clientbound().unwrap(),
+        CPlayPacket::KeepAlive(CKeepAlive { id: 42 })
+    );
+
+    let (keep_alive_time, packet) = reader.read_serverbound().unwrap().unwrap();
+    assert!(keep_alive_time >= first.timestamp);
+    assert!(matches!(
+        packet,
+        SPlayPacket::KeepAlive(SKeepAlive { id: 42 })
+    ));
+    let (placement_time, packet) = reader.read_serverbound().unwrap().unwrap();
+    assert!(placement_time >= keep_alive_time);
+    match packet {
+        SPlayPacket::PlayerBlockPlacement(placement) => {
+            assert_eq!((placement.x, placement.y, placement.z), (9, 7, 8));
+            assert_eq!(placement.face, 1);
+        }
+        packet => panic!("expected a block placement, got {:?}", packet),
+    }
+    assert!(reader.read_serverbound().unwrap().is_none());
+
+    let mut reader = CaptureReader::new(capture.as_slice()).unwrap();
+    let mut clientbound = Vec::new();
+    while let Some(packet) = reader.read_packet().unwrap() {
+        if packet.direction == PacketDirection::Clientbound {
+            clientbound.push(packet.decode_clientbound().unwrap());
+        }
+    }
+    assert_eq!(
+        clientbound,
+        [
+            CPlayPacket::KeepAlive(CKeepAlive { id: 42 }),
+            CPlayPacket::BlockChange(block_change)
+        ]
+    );
+
+    // Interrupted reads are retried
+    struct Interrupting<R>(R, bool);
+    impl<R: Read> Read for Interrupting<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.1 = !self.1;
+            if self.1 {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            self.0.read(buf)
+        }
+    }
+    let mut reader = CaptureReader::new(Interrupting(capture.as_slice(), false)).unwrap();
+    let mut packets = 0;
+    while reader.read_packet().unwrap().is_some() {
+        packets += 1;
+    }
+    assert_eq!(packets, 4);
+
+    // Truncated records and other files are errors
+    let mut truncated = CaptureReader::new(&capture[..capture.len() - 1]).unwrap();
+    let result = loop {
+        match truncated.read_packet() {
+            Ok(Some(_)) => continue,
+            result => break result,
+        }
+    };
+    assert!(result.is_err());
+    assert!(matches!(
+        CaptureReader::new(&b"\x89PNG\r\n\x1a\n\x00"[..]),
+        Err(CaptureError::InvalidHeader)
+    ));
+}
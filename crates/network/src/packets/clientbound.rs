@@ -1,18 +1,23 @@
-use super::{PacketEncoder, PacketEncoderExt, PalettedContainer, SlotData};
+use super::{
+    DecodeResult, EncodeResult, PacketDecodeError, PacketDecoderExt, PacketEncodeError,
+    PacketEncoder, PacketEncoderExt, PalettedContainer,
+};
 use crate::nbt_map::NBTMap;
-use serde::Serialize;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
 
 pub trait ClientBoundPacket {
-    fn encode(&self) -> PacketEncoder;
+    fn encode(&self) -> EncodeResult<PacketEncoder>;
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct CChunkDataSection {
     pub block_count: i16,
     pub block_states: PalettedContainer,
     pub biomes: PalettedContainer,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct CChunkDataBlockEntity {
     pub x: i8,
     pub z: i8,
@@ -21,6 +26,7 @@ pub struct CChunkDataBlockEntity {
     pub data: nbt::Blob,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct CChunkData {
     pub chunk_x: i32,
     pub chunk_z: i32,
@@ -30,82 +36,152 @@ pub struct CChunkData {
 }
 
 impl ClientBoundPacket for CChunkData {
-    fn encode(&self) -> PacketEncoder {
+    fn encode(&self) -> EncodeResult<PacketEncoder> {
         let mut buf = Vec::new();
-        buf.write_int(self.chunk_x);
-        buf.write_int(self.chunk_z);
-        buf.write_nbt_blob(&self.heightmaps);
+        buf.write_int(self.chunk_x)?;
+        buf.write_int(self.chunk_z)?;
+        buf.write_nbt_blob(&self.heightmaps)?;
         let mut data = Vec::new();
         for chunk_section in &self.chunk_sections {
-            data.write_short(chunk_section.block_count);
+            data.write_short(chunk_section.block_count)?;
             let containers = [&chunk_section.block_states, &chunk_section.biomes];
             for container in containers {
-                data.write_unsigned_byte(container.bits_per_entry);
+                data.write_unsigned_byte(container.bits_per_entry)?;
 
                 // Palette
                 if container.bits_per_entry == 0 {
                     // Single valued palette
-                    let palette = container
+                    let item = container
                         .palette
                         .as_ref()
-                        .expect("container with 0 bits per entry should have palette");
-                    let item = *palette.first().expect(
-                        "container with 0 bits per entry should have palette with one entry",
-                    );
-                    data.write_varint(item);
+                        .and_then(|palette| palette.first())
+                        .ok_or(PacketEncodeError::MissingPaletteEntry)?;
+                    data.write_varint(*item)?;
                 } else if let Some(palette) = &container.palette {
                     // Indirect palette
-                    data.write_varint(palette.len() as i32);
+                    data.write_varint(palette.len() as i32)?;
                     for palette_entry in palette {
-                        data.write_varint(*palette_entry);
+                        data.write_varint(*palette_entry)?;
                     }
                 }
 
                 // Data Array
-                data.write_varint(container.data_array.len() as i32);
+                data.write_varint(container.data_array.len() as i32)?;
                 for long in &container.data_array {
-                    data.write_long(*long as i64);
+                    data.write_long(*long as i64)?;
                 }
             }
         }
-        buf.write_varint(data.len() as i32);
-        buf.write_bytes(&data);
+        buf.write_varint(data.len() as i32)?;
+        buf.write_bytes(&data)?;
         // Number of block entities
-        buf.write_varint(self.block_entities.len() as i32);
+        buf.write_varint(self.block_entities.len() as i32)?;
         for block_entity in &self.block_entities {
-            buf.write_byte((block_entity.x << 4) | block_entity.z);
-            buf.write_short(block_entity.y);
-            buf.write_varint(block_entity.ty);
-            buf.write_nbt_blob(&block_entity.data);
+            buf.write_byte((block_entity.x << 4) | block_entity.z)?;
+            buf.write_short(block_entity.y)?;
+            buf.write_varint(block_entity.ty)?;
+            buf.write_nbt_blob(&block_entity.data)?;
         }
 
         // We don't do lighting because we have max ambient light
         // These will all be zeros
 
         // Trust Edges
-        buf.write_bool(true);
+        buf.write_bool(true)?;
 
         // Sky Light Mask
-        buf.write_varint(0);
+        buf.write_varint(0)?;
         // Block Light Mask
-        buf.write_varint(0);
+        buf.write_varint(0)?;
         // Empty Sky Light Mask
-        buf.write_varint(1);
-        buf.write_long(0x3FFFF);
+        buf.write_varint(1)?;
+        buf.write_long(0x3FFFF)?;
         // Empty Block Light Mask
-        buf.write_varint(1);
-        buf.write_long(0x3FFFF);
+        buf.write_varint(1)?;
+        buf.write_long(0x3FFFF)?;
 
         // Sky Light array count
-        buf.write_varint(0);
+        buf.write_varint(0)?;
         // Block Light array count
-        buf.write_varint(0);
+        buf.write_varint(0)?;
 
-        PacketEncoder::new(buf, 0x22)
+        Ok(PacketEncoder::new(buf, 0x22))
     }
 }
 
-#[derive(Debug)]
+impl CChunkData {
+    pub fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        let chunk_x = decoder.read_int()?;
+        let chunk_z = decoder.read_int()?;
+        let heightmaps = decoder.read_nbt_blob()?.unwrap_or_else(nbt::Blob::new);
+        let length = decoder.read_varint()?;
+        if length < 0 {
+            return Err(PacketDecodeError::InvalidDataLength(length));
+        }
+        // The number of sections depends on the height of the world, so they are read until the
+        // data runs out
+        let mut data = Cursor::new(decoder.read_bytes(length as usize)?);
+        let mut chunk_sections = Vec::new();
+        while (data.position() as usize) < data.get_ref().len() {
+            chunk_sections.push(CChunkDataSection {
+                block_count: data.read_short()?,
+                block_states: decode_paletted_container(&mut data, 8)?,
+                biomes: decode_paletted_container(&mut data, 3)?,
+            });
+        }
+        let mut block_entities = Vec::new();
+        for _ in 0..decoder.read_varint()? {
+            let xz = decoder.read_unsigned_byte()?;
+            block_entities.push(CChunkDataBlockEntity {
+                x: (xz >> 4) as i8,
+                z: (xz & 0xF) as i8,
+                y: decoder.read_short()?,
+                ty: decoder.read_varint()?,
+                data: decoder.read_nbt_blob()?.unwrap_or_else(nbt::Blob::new),
+            });
+        }
+        // The lighting that follows is always empty, see `encode`
+        PacketDecoderExt::read_to_end(decoder)?;
+        Ok(CChunkData {
+            chunk_x,
+            chunk_z,
+            heightmaps,
+            chunk_sections,
+            block_entities,
+        })
+    }
+}
+
+/// Reads a paletted container which uses an indirect palette for up to `max_indirect_bits` bits
+/// per entry, and the global palette above that
+fn decode_paletted_container<T: PacketDecoderExt>(
+    decoder: &mut T,
+    max_indirect_bits: u8,
+) -> DecodeResult<PalettedContainer> {
+    let bits_per_entry = decoder.read_unsigned_byte()?;
+    let palette = if bits_per_entry == 0 {
+        Some(vec![decoder.read_varint()?])
+    } else if bits_per_entry <= max_indirect_bits {
+        let mut palette = Vec::new();
+        for _ in 0..decoder.read_varint()? {
+            palette.push(decoder.read_varint()?);
+        }
+        Some(palette)
+    } else {
+        None
+    };
+    let mut data_array = Vec::new();
+    for _ in 0..decoder.read_varint()? {
+        data_array.push(decoder.read_long()? as u64);
+    }
+    Ok(PalettedContainer {
+        bits_per_entry,
+        palette,
+        data_array,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct C3BMultiBlockChangeRecord {
     pub x: u8,
     pub y: u8,
@@ -113,7 +189,7 @@ pub struct C3BMultiBlockChangeRecord {
     pub block_id: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CMultiBlockChange {
     pub chunk_x: i32,
     pub chunk_z: i32,
@@ -122,22 +198,412 @@ pub struct CMultiBlockChange {
 }
 
 impl ClientBoundPacket for CMultiBlockChange {
-    fn encode(&self) -> PacketEncoder {
+    fn encode(&self) -> EncodeResult<PacketEncoder> {
         let mut buf = Vec::with_capacity(self.records.len() * 8 + 12);
         let pos = ((self.chunk_x as i64 & 0x3FFFFF) << 42)
             | ((self.chunk_z as i64 & 0x3FFFFF) << 20)
             | (self.chunk_y as i64 & 0xFFFFF);
-        buf.write_long(pos);
-        buf.write_bool(true); // Always inverse the preceding Update Light packet's "Trust Edges" bool
-        buf.write_varint(self.records.len() as i32); // Length of record array
+        buf.write_long(pos)?;
+        buf.write_bool(true)?; // Always inverse the preceding Update Light packet's "Trust Edges" bool
+        buf.write_varint(self.records.len() as i32)?; // Length of record array
         for record in &self.records {
             let long = ((record.block_id as u64) << 12)
                 | ((record.x as u64) << 8)
                 | ((record.z as u64) << 4)
                 | (record.y as u64);
-            buf.write_varlong(long as i64);
+            buf.write_varlong(long as i64)?;
+        }
+
+        Ok(PacketEncoder::new(buf, 0x3F))
+    }
+}
+
+impl CMultiBlockChange {
+    pub fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        let pos = decoder.read_long()?;
+        decoder.read_bool()?;
+        let mut records = Vec::new();
+        for _ in 0..decoder.read_varint()? {
+            let long = decoder.read_varlong()? as u64;
+            records.push(C3BMultiBlockChangeRecord {
+                x: ((long >> 8) & 0xF) as u8,
+                y: (long & 0xF) as u8,
+                z: ((long >> 4) & 0xF) as u8,
+                block_id: (long >> 12) as u32,
+            });
         }
+        Ok(CMultiBlockChange {
+            chunk_x: (pos >> 42) as i32,
+            chunk_z: (pos << 22 >> 42) as i32,
+            chunk_y: (pos & 0xFFFFF) as u32,
+            records,
+        })
+    }
+}
+
+pub struct CResponse {
+    pub json_response: String,
+}
+
+impl ClientBoundPacket for CResponse {
+    fn encode(&self) -> EncodeResult<PacketEncoder> {
+        let mut buf = Vec::new();
+        buf.write_string(32767, &self.json_response)?;
+        Ok(PacketEncoder::new(buf, 0x00))
+    }
+}
+
+pub struct CPong {
+    pub payload: i64,
+}
+
+impl ClientBoundPacket for CPong {
+    fn encode(&self) -> EncodeResult<PacketEncoder> {
+        let mut buf = Vec::new();
+        buf.write_long(self.payload)?;
+        Ok(PacketEncoder::new(buf, 0x01))
+    }
+}
+
+/// Closes the connection during login, showing the reason to the player
+pub struct CDisconnectLogin {
+    /// The reason as a JSON chat component
+    pub reason: String,
+}
+
+impl CDisconnectLogin {
+    /// Creates the packet from a plain text reason
+    pub fn from_text(reason: &str) -> CDisconnectLogin {
+        CDisconnectLogin {
+            reason: serde_json::json!({ "text": reason }).to_string(),
+        }
+    }
+}
+
+impl ClientBoundPacket for CDisconnectLogin {
+    fn encode(&self) -> EncodeResult<PacketEncoder> {
+        let mut buf = Vec::new();
+        buf.write_string(262144, &self.reason)?;
+        Ok(PacketEncoder::new(buf, 0x00))
+    }
+}
+
+pub struct CLoginSuccess {
+    pub uuid: u128,
+    pub username: String,
+}
+
+impl ClientBoundPacket for CLoginSuccess {
+    fn encode(&self) -> EncodeResult<PacketEncoder> {
+        let mut buf = Vec::new();
+        buf.write_uuid(self.uuid)?;
+        buf.write_string(16, &self.username)?;
+        Ok(PacketEncoder::new(buf, 0x02))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CJoinGameDimensionElement {
+    pub piglin_safe: bool,
+    pub natural: bool,
+    pub ambient_light: f32,
+    pub infiniburn: String,
+    pub respawn_anchor_works: bool,
+    pub has_skylight: bool,
+    pub bed_works: bool,
+    pub effects: String,
+    pub has_raids: bool,
+    pub min_y: i32,
+    pub height: i32,
+    pub logical_height: i32,
+    pub coordinate_scale: f64,
+    pub ultrawarm: bool,
+    pub has_ceiling: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CJoinGameBiomeEffects {
+    pub sky_color: i32,
+    pub water_fog_color: i32,
+    pub fog_color: i32,
+    pub water_color: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CJoinGameBiomeElement {
+    pub precipitation: String,
+    pub effects: CJoinGameBiomeEffects,
+    pub temperature: f32,
+    pub downfall: f32,
+    pub category: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CJoinGameDimensionCodec {
+    #[serde(rename = "minecraft:dimension_type")]
+    pub dimension_types: NBTMap<CJoinGameDimensionElement>,
+    #[serde(rename = "minecraft:worldgen/biome")]
+    pub biomes: NBTMap<CJoinGameBiomeElement>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CJoinGame {
+    pub entity_id: i32,
+    pub is_hardcore: bool,
+    pub gamemode: u8,
+    pub previous_gamemode: u8,
+    pub world_names: Vec<String>,
+    pub dimension_codec: CJoinGameDimensionCodec,
+    pub dimension: CJoinGameDimensionElement,
+    pub world_name: String,
+    pub hashed_seed: i64,
+    pub max_players: i32,
+    pub view_distance: i32,
+    pub simulation_distance: i32,
+    pub reduced_debug_info: bool,
+    pub enable_respawn_screen: bool,
+    pub is_debug: bool,
+    pub is_flat: bool,
+}
+
+impl ClientBoundPacket for CJoinGame {
+    fn encode(&self) -> EncodeResult<PacketEncoder> {
+        let mut buf = Vec::new();
+        buf.write_int(self.entity_id)?;
+        buf.write_bool(self.is_hardcore)?;
+        buf.write_unsigned_byte(self.gamemode)?;
+        buf.write_unsigned_byte(self.previous_gamemode)?;
+        buf.write_varint(self.world_names.len() as i32)?;
+        for world_name in &self.world_names {
+            buf.write_string(32767, world_name)?;
+        }
+        buf.write_nbt(&self.dimension_codec)?;
+        buf.write_nbt(&self.dimension)?;
+        buf.write_string(32767, &self.world_name)?;
+        buf.write_long(self.hashed_seed)?;
+        buf.write_varint(self.max_players)?;
+        buf.write_varint(self.view_distance)?;
+        buf.write_varint(self.simulation_distance)?;
+        buf.write_bool(self.reduced_debug_info)?;
+        buf.write_bool(self.enable_respawn_screen)?;
+        buf.write_bool(self.is_debug)?;
+        buf.write_bool(self.is_flat)?;
+        Ok(PacketEncoder::new(buf, 0x26))
+    }
+}
+
+impl CJoinGame {
+    pub fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        let entity_id = decoder.read_int()?;
+        let is_hardcore = decoder.read_bool()?;
+        let gamemode = decoder.read_unsigned_byte()?;
+        let previous_gamemode = decoder.read_unsigned_byte()?;
+        let mut world_names = Vec::new();
+        for _ in 0..decoder.read_varint()? {
+            world_names.push(decoder.read_string()?);
+        }
+        Ok(CJoinGame {
+            entity_id,
+            is_hardcore,
+            gamemode,
+            previous_gamemode,
+            world_names,
+            dimension_codec: decoder.read_nbt()?,
+            dimension: decoder.read_nbt()?,
+            world_name: decoder.read_string()?,
+            hashed_seed: decoder.read_long()?,
+            max_players: decoder.read_varint()?,
+            view_distance: decoder.read_varint()?,
+            simulation_distance: decoder.read_varint()?,
+            reduced_debug_info: decoder.read_bool()?,
+            enable_respawn_screen: decoder.read_bool()?,
+            is_debug: decoder.read_bool()?,
+            is_flat: decoder.read_bool()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CBlockChange {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    pub block_id: i32,
+}
+
+impl ClientBoundPacket for CBlockChange {
+    fn encode(&self) -> EncodeResult<PacketEncoder> {
+        let mut buf = Vec::new();
+        buf.write_position(self.x, self.y, self.z)?;
+        buf.write_varint(self.block_id)?;
+        Ok(PacketEncoder::new(buf, 0x0C))
+    }
+}
+
+impl CBlockChange {
+    pub fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        let (x, y, z) = decoder.read_position()?;
+        Ok(CBlockChange {
+            x,
+            y,
+            z,
+            block_id: decoder.read_varint()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CKeepAlive {
+    pub id: i64,
+}
+
+impl ClientBoundPacket for CKeepAlive {
+    fn encode(&self) -> EncodeResult<PacketEncoder> {
+        let mut buf = Vec::new();
+        buf.write_long(self.id)?;
+        Ok(PacketEncoder::new(buf, 0x21))
+    }
+}
+
+impl CKeepAlive {
+    pub fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        Ok(CKeepAlive {
+            id: decoder.read_long()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CPlayerPositionAndLook {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub yaw: f32,
+    pub pitch: f32,
+    /// Bit mask of which fields are relative instead of absolute
+    pub flags: u8,
+    pub teleport_id: i32,
+    pub dismount_vehicle: bool,
+}
+
+impl ClientBoundPacket for CPlayerPositionAndLook {
+    fn encode(&self) -> EncodeResult<PacketEncoder> {
+        let mut buf = Vec::new();
+        buf.write_double(self.x)?;
+        buf.write_double(self.y)?;
+        buf.write_double(self.z)?;
+        buf.write_float(self.yaw)?;
+        buf.write_float(self.pitch)?;
+        buf.write_unsigned_byte(self.flags)?;
+        buf.write_varint(self.teleport_id)?;
+        buf.write_bool(self.dismount_vehicle)?;
+        Ok(PacketEncoder::new(buf, 0x38))
+    }
+}
+
+impl CPlayerPositionAndLook {
+    pub fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        Ok(CPlayerPositionAndLook {
+            x: decoder.read_double()?,
+            y: decoder.read_double()?,
+            z: decoder.read_double()?,
+            yaw: decoder.read_float()?,
+            pitch: decoder.read_float()?,
+            flags: decoder.read_unsigned_byte()?,
+            teleport_id: decoder.read_varint()?,
+            dismount_vehicle: decoder.read_bool()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CUpdateViewPosition {
+    pub chunk_x: i32,
+    pub chunk_z: i32,
+}
+
+impl ClientBoundPacket for CUpdateViewPosition {
+    fn encode(&self) -> EncodeResult<PacketEncoder> {
+        let mut buf = Vec::new();
+        buf.write_varint(self.chunk_x)?;
+        buf.write_varint(self.chunk_z)?;
+        Ok(PacketEncoder::new(buf, 0x49))
+    }
+}
+
+impl CUpdateViewPosition {
+    pub fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        Ok(CUpdateViewPosition {
+            chunk_x: decoder.read_varint()?,
+            chunk_z: decoder.read_varint()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CSpawnPosition {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    pub angle: f32,
+}
+
+impl ClientBoundPacket for CSpawnPosition {
+    fn encode(&self) -> EncodeResult<PacketEncoder> {
+        let mut buf = Vec::new();
+        buf.write_position(self.x, self.y, self.z)?;
+        buf.write_float(self.angle)?;
+        Ok(PacketEncoder::new(buf, 0x4B))
+    }
+}
+
+impl CSpawnPosition {
+    pub fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        let (x, y, z) = decoder.read_position()?;
+        Ok(CSpawnPosition {
+            x,
+            y,
+            z,
+            angle: decoder.read_float()?,
+        })
+    }
+}
+
+/// A packet sent by the server in the play state
+#[derive(Debug, Clone, PartialEq)]
+pub enum CPlayPacket {
+    BlockChange(CBlockChange),
+    KeepAlive(CKeepAlive),
+    ChunkData(CChunkData),
+    JoinGame(CJoinGame),
+    PlayerPositionAndLook(CPlayerPositionAndLook),
+    MultiBlockChange(CMultiBlockChange),
+    UpdateViewPosition(CUpdateViewPosition),
+    SpawnPosition(CSpawnPosition),
+    /// A packet without a decoder, the remaining packet data is kept as is
+    Unknown {
+        id: i32,
+        data: Vec<u8>,
+    },
+}
 
-        PacketEncoder::new(buf, 0x3F)
+impl CPlayPacket {
+    /// Decodes the packet body for the packet id `id`. The decoder should only contain this
+    /// packet, since unknown packets and chunk data read it to the end.
+    pub fn decode<T: PacketDecoderExt>(id: i32, decoder: &mut T) -> DecodeResult<CPlayPacket> {
+        Ok(match id {
+            0x0C => CPlayPacket::BlockChange(CBlockChange::decode(decoder)?),
+            0x21 => CPlayPacket::KeepAlive(CKeepAlive::decode(decoder)?),
+            0x22 => CPlayPacket::ChunkData(CChunkData::decode(decoder)?),
+            0x26 => CPlayPacket::JoinGame(CJoinGame::decode(decoder)?),
+            0x38 => CPlayPacket::PlayerPositionAndLook(CPlayerPositionAndLook::decode(decoder)?),
+            0x3F => CPlayPacket::MultiBlockChange(CMultiBlockChange::decode(decoder)?),
+            0x49 => CPlayPacket::UpdateViewPosition(CUpdateViewPosition::decode(decoder)?),
+            0x4B => CPlayPacket::SpawnPosition(CSpawnPosition::decode(decoder)?),
+            id => CPlayPacket::Unknown {
+                id,
+                data: PacketDecoderExt::read_to_end(decoder)?,
+            },
+        })
     }
 }
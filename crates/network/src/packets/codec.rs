@@ -0,0 +1,228 @@
+//! Framing of packets on the wire, see [`FrameEncoder`] and [`FrameDecoder`].
+//!
+//! Without compression, a frame is the packet length followed by the packet id and data. Once a
+//! compression threshold is set, the packet length is followed by the uncompressed data length,
+//! and packets at least as large as the threshold are compressed with zlib. Smaller packets are
+//! sent with a data length of 0.
+
+use super::{
+    varint, DecodeResult, PacketDecodeError, PacketDecoderExt, PacketEncoder, MAX_PACKET_SIZE,
+};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::{self, Cursor, Read, Write};
+use std::mem;
+
+/// The largest uncompressed data length the vanilla client accepts
+pub(super) const MAX_DATA_LENGTH: usize = 8388608;
+
+fn push_varint(buf: &mut Vec<u8>, val: i32) {
+    let (bytes, len) = varint(val);
+    buf.extend_from_slice(&bytes[..len]);
+}
+
+/// Writes packets into frames. The buffers used for framing and compression are kept between
+/// packets, so encoding does not allocate once they have grown large enough.
+pub struct FrameEncoder {
+    compression_threshold: Option<usize>,
+    compression: Compression,
+    frame: Vec<u8>,
+    compressed: Vec<u8>,
+}
+
+impl FrameEncoder {
+    /// Creates an encoder which compresses packets of at least `compression_threshold` bytes, or
+    /// writes uncompressed frames if it is `None`
+    pub fn new(compression_threshold: Option<usize>) -> FrameEncoder {
+        FrameEncoder {
+            compression_threshold,
+            compression: Compression::default(),
+            frame: Vec::new(),
+            compressed: Vec::new(),
+        }
+    }
+
+    pub fn compression_threshold(&self) -> Option<usize> {
+        self.compression_threshold
+    }
+
+    /// Changes the compression threshold, this should be done right after sending the set
+    /// compression packet
+    pub fn set_compression_threshold(&mut self, compression_threshold: Option<usize>) {
+        self.compression_threshold = compression_threshold;
+    }
+
+    /// Encodes `packet` into a frame. The frame is valid until the next packet is encoded.
+    pub fn encode(&mut self, packet: &PacketEncoder) -> io::Result<&[u8]> {
+        let (id, id_len) = varint(packet.packet_id as i32);
+        let id = &id[..id_len];
+        let data_length = id_len + packet.buffer.len();
+
+        self.frame.clear();
+        match self.compression_threshold {
+            None => {
+                push_varint(&mut self.frame, data_length as i32);
+            }
+            Some(threshold) if data_length < threshold => {
+                // The data length of 0 adds another byte
+                push_varint(&mut self.frame, data_length as i32 + 1);
+                self.frame.push(0);
+            }
+            Some(_) => {
+                let mut encoder =
+                    ZlibEncoder::new(mem::take(&mut self.compressed), self.compression);
+                encoder.write_all(id)?;
+                encoder.write_all(&packet.buffer)?;
+                self.compressed = encoder.finish()?;
+
+                let (data_length, data_length_len) = varint(data_length as i32);
+                let packet_length = data_length_len + self.compressed.len();
+                push_varint(&mut self.frame, packet_length as i32);
+                self.frame
+                    .extend_from_slice(&data_length[..data_length_len]);
+                self.frame.extend_from_slice(&self.compressed);
+                self.compressed.clear();
+                return Ok(&self.frame);
+            }
+        }
+        self.frame.extend_from_slice(id);
+        self.frame.extend_from_slice(&packet.buffer);
+        Ok(&self.frame)
+    }
+
+    pub fn write_packet(&mut self, packet: &PacketEncoder, mut w: impl Write) -> io::Result<()> {
+        let frame = self.encode(packet)?;
+        w.write_all(frame)
+    }
+}
+
+/// Reads packets from frames written by a [`FrameEncoder`] with the same compression threshold.
+/// Like the encoder, its buffers are reused between packets.
+pub struct FrameDecoder {
+    compression_threshold: Option<usize>,
+    frame: Vec<u8>,
+    decompressed: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new(compression_threshold: Option<usize>) -> FrameDecoder {
+        FrameDecoder {
+            compression_threshold,
+            frame: Vec::new(),
+            decompressed: Vec::new(),
+        }
+    }
+
+    pub fn compression_threshold(&self) -> Option<usize> {
+        self.compression_threshold
+    }
+
+    pub fn set_compression_threshold(&mut self, compression_threshold: Option<usize>) {
+        self.compression_threshold = compression_threshold;
+    }
+
+    /// Reads a single frame, returning the packet id and a decoder over the rest of the packet.
+    /// The decoder borrows the buffers of this decoder, so it must be dropped before reading the
+    /// next packet.
+    pub fn read_packet<R: PacketDecoderExt>(
+        &mut self,
+        reader: &mut R,
+    ) -> DecodeResult<(i32, Cursor<&[u8]>)> {
+        let length = reader.read_varint()?;
+        if !(1..=MAX_PACKET_SIZE).contains(&length) {
+            return Err(PacketDecodeError::InvalidPacketLength(length));
+        }
+        self.frame.resize(length as usize, 0);
+        reader.read_exact(&mut self.frame)?;
+
+        let threshold = match self.compression_threshold {
+            Some(threshold) => threshold,
+            None => {
+                let mut decoder = Cursor::new(self.frame.as_slice());
+                let id = decoder.read_varint()?;
+                return Ok((id, decoder));
+            }
+        };
+
+        let mut frame = Cursor::new(self.frame.as_slice());
+        let data_length = frame.read_varint()?;
+        if data_length == 0 {
+            let id = frame.read_varint()?;
+            return Ok((id, frame));
+        }
+        // Negative lengths wrap around to be larger than the maximum
+        if !(threshold..=MAX_DATA_LENGTH).contains(&(data_length as usize)) {
+            return Err(PacketDecodeError::InvalidDataLength(data_length));
+        }
+        let data_length = data_length as usize;
+
+        let compressed = &self.frame[frame.position() as usize..];
+        self.decompressed.clear();
+        // Reading one byte more than expected catches frames that inflate to more than the
+        // data length
+        ZlibDecoder::new(compressed)
+            .take(data_length as u64 + 1)
+            .read_to_end(&mut self.decompressed)?;
+        if self.decompressed.len() != data_length {
+            return Err(PacketDecodeError::DataLengthMismatch {
+                expected: data_length,
+                actual: self.decompressed.len(),
+            });
+        }
+        let mut decoder = Cursor::new(self.decompressed.as_slice());
+        let id = decoder.read_varint()?;
+        Ok((id, decoder))
+    }
+}
+
+#[test]
+fn frame_round_trip() {
+    use super::PacketEncoderExt;
+
+    let small = PacketEncoder::new(vec![1, 2, 3], 0x21);
+    let mut large_data = Vec::new();
+    for i in 0..1000 {
+        large_data.write_varint(i).unwrap();
+    }
+    let large = PacketEncoder::new(large_data, 0x22);
+
+    for threshold in [None, Some(0), Some(256)] {
+        let mut encoder = FrameEncoder::new(threshold);
+        let mut stream = Vec::new();
+        for packet in [&small, &large, &small] {
+            encoder.write_packet(packet, &mut stream).unwrap();
+        }
+
+        let mut decoder = FrameDecoder::new(threshold);
+        let mut stream = Cursor::new(stream);
+        for packet in [&small, &large, &small] {
+            let (id, data) = decoder.read_packet(&mut stream).unwrap();
+            assert_eq!(id as u32, packet.packet_id);
+            assert_eq!(&data.get_ref()[data.position() as usize..], packet.buffer);
+        }
+        assert!(decoder.read_packet(&mut stream).is_err());
+    }
+
+    // Frames written by `PacketEncoder` decode the same way
+    let mut stream = Vec::new();
+    small.write_compressed(&mut stream).unwrap();
+    large.write_compressed(&mut stream).unwrap();
+    let mut encoder = FrameEncoder::new(Some(256));
+    assert_eq!(encoder.encode(&small).unwrap(), &stream[..6]);
+    let mut decoder = FrameDecoder::new(Some(256));
+    let mut stream = Cursor::new(stream);
+    assert_eq!(decoder.read_packet(&mut stream).unwrap().0, 0x21);
+    let (id, data) = decoder.read_packet(&mut stream).unwrap();
+    assert_eq!(id, 0x22);
+    assert_eq!(&data.get_ref()[data.position() as usize..], large.buffer);
+
+    // Compressed data that doesn't match its data length is rejected
+    let mut encoder = FrameEncoder::new(Some(0));
+    let mut frame = encoder.encode(&small).unwrap().to_vec();
+    frame[1] += 1;
+    assert!(matches!(
+        FrameDecoder::new(Some(0)).read_packet(&mut Cursor::new(frame)),
+        Err(PacketDecodeError::DataLengthMismatch { .. })
+    ));
+}
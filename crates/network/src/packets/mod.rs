@@ -1,14 +1,15 @@
+pub mod capture;
 pub mod clientbound;
+pub mod codec;
+pub mod serverbound;
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use flate2::bufread::ZlibDecoder;
-use flate2::write::ZlibEncoder;
-use flate2::Compression;
+use serde::de::DeserializeOwned;
 use serde::Serialize;
+use std::error::Error;
+use std::fmt;
 use std::io::{self, Cursor, Read, Write};
 use std::net::TcpStream;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct SlotData {
@@ -17,7 +18,7 @@ pub struct SlotData {
     pub nbt: Option<nbt::Blob>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PalettedContainer {
     pub bits_per_entry: u8,
     pub palette: Option<Vec<i32>>,
@@ -31,6 +32,64 @@ pub enum PacketDecodeError {
     Io(io::Error),
     FromUtf8(std::string::FromUtf8Error),
     Nbt(nbt::Error),
+    /// A VarInt was longer than 5 bytes
+    VarIntTooLong,
+    /// A VarLong was longer than 10 bytes
+    VarLongTooLong,
+    /// The packet length was not positive or larger than the protocol allows
+    InvalidPacketLength(i32),
+    /// The uncompressed data length of a compressed packet was below the compression threshold
+    /// or larger than the protocol allows
+    InvalidDataLength(i32),
+    /// A compressed packet did not inflate to its data length
+    DataLengthMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    /// A string length was negative or larger than the protocol allows
+    InvalidStringLength(i32),
+    /// A packet id which is not valid in the current state
+    UnexpectedPacketId(i32),
+}
+
+impl fmt::Display for PacketDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketDecodeError::Io(err) => write!(f, "{}", err),
+            PacketDecodeError::FromUtf8(err) => write!(f, "invalid string: {}", err),
+            PacketDecodeError::Nbt(err) => write!(f, "invalid nbt: {}", err),
+            PacketDecodeError::VarIntTooLong => write!(f, "VarInt is too long"),
+            PacketDecodeError::VarLongTooLong => write!(f, "VarLong is too long"),
+            PacketDecodeError::InvalidPacketLength(length) => {
+                write!(f, "invalid packet length {}", length)
+            }
+            PacketDecodeError::InvalidDataLength(length) => {
+                write!(f, "invalid data length {}", length)
+            }
+            PacketDecodeError::DataLengthMismatch { expected, actual } => write!(
+                f,
+                "data length is {} but packet inflated to {} bytes",
+                expected, actual
+            ),
+            PacketDecodeError::InvalidStringLength(length) => {
+                write!(f, "invalid string length {}", length)
+            }
+            PacketDecodeError::UnexpectedPacketId(id) => {
+                write!(f, "unexpected packet id {:#04x}", id)
+            }
+        }
+    }
+}
+
+impl Error for PacketDecodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PacketDecodeError::Io(err) => Some(err),
+            PacketDecodeError::FromUtf8(err) => Some(err),
+            PacketDecodeError::Nbt(err) => Some(err),
+            _ => None,
+        }
+    }
 }
 
 impl From<nbt::Error> for PacketDecodeError {
@@ -51,8 +110,128 @@ impl From<std::string::FromUtf8Error> for PacketDecodeError {
     }
 }
 
+/// Io errors are passed through so callers can still tell apart a closed connection, everything
+/// else is invalid data.
+impl From<PacketDecodeError> for io::Error {
+    fn from(err: PacketDecodeError) -> io::Error {
+        match err {
+            PacketDecodeError::Io(err) => err,
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+pub type EncodeResult<T> = std::result::Result<T, PacketEncodeError>;
+
 #[derive(Debug)]
-pub enum PacketEncodeError {}
+pub enum PacketEncodeError {
+    Io(io::Error),
+    Nbt(nbt::Error),
+    /// A string was longer than the maximum length of its field
+    StringTooLong {
+        length: usize,
+        max_length: usize,
+    },
+    /// A paletted container with 0 bits per entry did not have its single value in the palette
+    MissingPaletteEntry,
+    /// A paletted container without a palette used more bits per entry than the global palette
+    /// allows
+    TooManyBitsPerEntry(u8),
+}
+
+impl fmt::Display for PacketEncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketEncodeError::Io(err) => write!(f, "{}", err),
+            PacketEncodeError::Nbt(err) => write!(f, "could not write nbt: {}", err),
+            PacketEncodeError::StringTooLong { length, max_length } => write!(
+                f,
+                "string of {} bytes is longer than the maximum of {} bytes",
+                length, max_length
+            ),
+            PacketEncodeError::MissingPaletteEntry => {
+                write!(f, "single valued container has no palette entry")
+            }
+            PacketEncodeError::TooManyBitsPerEntry(bits) => write!(
+                f,
+                "{} bits per entry is more than the global palette allows",
+                bits
+            ),
+        }
+    }
+}
+
+impl Error for PacketEncodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PacketEncodeError::Io(err) => Some(err),
+            PacketEncodeError::Nbt(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<nbt::Error> for PacketEncodeError {
+    fn from(err: nbt::Error) -> PacketEncodeError {
+        PacketEncodeError::Nbt(err)
+    }
+}
+
+impl From<io::Error> for PacketEncodeError {
+    fn from(err: io::Error) -> PacketEncodeError {
+        PacketEncodeError::Io(err)
+    }
+}
+
+impl From<PacketEncodeError> for io::Error {
+    fn from(err: PacketEncodeError) -> io::Error {
+        match err {
+            PacketEncodeError::Io(err) => err,
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+/// The largest packet a client may send, the largest length that fits in a 3 byte varint
+const MAX_PACKET_SIZE: i32 = 2097151;
+
+/// The longest string the protocol allows, in characters
+const MAX_STRING_LENGTH: usize = 32767;
+
+/// Strings are length prefixed with their size in bytes, which can be up to 4 bytes per character
+fn max_string_bytes(max_length: usize) -> usize {
+    max_length * 4 + 3
+}
+
+/// Encodes `val` as a varint without allocating, returning the buffer and the encoded length
+fn varint(val: i32) -> ([u8; 5], usize) {
+    let mut val = val as u32;
+    let mut buf = [0; 5];
+    let mut len = 0;
+    loop {
+        let mut temp = (val & 0b0111_1111) as u8;
+        val >>= 7;
+        if val != 0 {
+            temp |= 0b1000_0000;
+        }
+        buf[len] = temp;
+        len += 1;
+        if val == 0 {
+            return (buf, len);
+        }
+    }
+}
+
+/// Reads a single uncompressed packet, returning its id and a decoder over the rest of the packet
+pub fn read_packet<T: PacketDecoderExt>(reader: &mut T) -> DecodeResult<(i32, Cursor<Vec<u8>>)> {
+    let length = reader.read_varint()?;
+    if !(1..=MAX_PACKET_SIZE).contains(&length) {
+        return Err(PacketDecodeError::InvalidPacketLength(length));
+    }
+    let mut decoder = Cursor::new(reader.read_bytes(length as usize)?);
+    let id = decoder.read_varint()?;
+    Ok((id, decoder))
+}
 
 impl<T: std::convert::AsRef<[u8]>> PacketDecoderExt for Cursor<T> {}
 impl PacketDecoderExt for TcpStream {}
@@ -101,53 +280,40 @@ pub trait PacketDecoderExt: Read + Sized {
     }
 
     fn read_varint(&mut self) -> DecodeResult<i32> {
-        let mut num_read = 0;
         let mut result = 0i32;
-        let mut read;
-        loop {
-            read = self.read_byte()? as u8;
-            let value = (read & 0b0111_1111) as i32;
-            result |= value << (7 * num_read);
-
-            num_read += 1;
-            if num_read > 5 {
-                panic!("VarInt is too big!");
-            }
+        for num_read in 0..5 {
+            let read = self.read_unsigned_byte()?;
+            result |= ((read & 0b0111_1111) as i32) << (7 * num_read);
             if read & 0b1000_0000 == 0 {
-                break;
+                return Ok(result);
             }
         }
-        Ok(result)
+        Err(PacketDecodeError::VarIntTooLong)
     }
 
     fn read_varlong(&mut self) -> DecodeResult<i64> {
-        let mut num_read = 0;
         let mut result = 0i64;
-        let mut read;
-        loop {
-            read = self.read_byte()? as u8;
-            let value = (read & 0b0111_1111) as i64;
-            result |= value << (7 * num_read);
-
-            num_read += 1;
-            if num_read > 5 {
-                panic!("VarInt is too big!");
-            }
+        for num_read in 0..10 {
+            let read = self.read_unsigned_byte()?;
+            result |= ((read & 0b0111_1111) as i64) << (7 * num_read);
             if read & 0b1000_0000 == 0 {
-                break;
+                return Ok(result);
             }
         }
-        Ok(result)
+        Err(PacketDecodeError::VarLongTooLong)
     }
 
     fn read_string(&mut self) -> DecodeResult<String> {
         let length = self.read_varint()?;
+        if length < 0 || length as usize > max_string_bytes(MAX_STRING_LENGTH) {
+            return Err(PacketDecodeError::InvalidStringLength(length));
+        }
         Ok(String::from_utf8(self.read_bytes(length as usize)?)?)
     }
 
     fn read_to_end(&mut self) -> DecodeResult<Vec<u8>> {
         let mut data = Vec::new();
-        let _ = Read::read_to_end(self, &mut data);
+        Read::read_to_end(self, &mut data)?;
         Ok(data)
     }
 
@@ -162,6 +328,21 @@ pub trait PacketDecoderExt: Read + Sized {
         Ok((x as i32, y as i32, z as i32))
     }
 
+    fn read_slot_data(&mut self) -> DecodeResult<Option<SlotData>> {
+        if !self.read_bool()? {
+            return Ok(None);
+        }
+        Ok(Some(SlotData {
+            item_id: self.read_varint()?,
+            item_count: self.read_byte()?,
+            nbt: self.read_nbt_blob()?,
+        }))
+    }
+
+    fn read_nbt<T: DeserializeOwned>(&mut self) -> DecodeResult<T> {
+        Ok(nbt::from_reader(self)?)
+    }
+
     fn read_nbt_blob(&mut self) -> DecodeResult<Option<nbt::Blob>> {
         match nbt::Blob::from_reader(self) {
             Ok(nbt) => Ok(Some(nbt)),
@@ -172,110 +353,118 @@ pub trait PacketDecoderExt: Read + Sized {
 }
 
 pub trait PacketEncoderExt: Write {
-    fn write_boolean(&mut self, val: bool) {
-        self.write_all(&[val as u8]).unwrap();
+    fn write_boolean(&mut self, val: bool) -> EncodeResult<()> {
+        Ok(self.write_all(&[val as u8])?)
     }
-    fn write_bytes(&mut self, val: &[u8]) {
-        self.write_all(val).unwrap();
+    fn write_bytes(&mut self, val: &[u8]) -> EncodeResult<()> {
+        Ok(self.write_all(val)?)
     }
-    fn write_varint(&mut self, val: i32) {
-        let _ = self.write_all(&PacketEncoder::varint(val));
+    fn write_varint(&mut self, val: i32) -> EncodeResult<()> {
+        let (bytes, len) = varint(val);
+        Ok(self.write_all(&bytes[..len])?)
     }
 
-    fn write_varlong(&mut self, mut val: i64) {
+    fn write_varlong(&mut self, val: i64) -> EncodeResult<()> {
+        // Shift as unsigned so negative values terminate
+        let mut val = val as u64;
         loop {
-            let mut temp = (val & 0b1111_1111) as u8;
+            let mut temp = (val & 0b0111_1111) as u8;
             val >>= 7;
             if val != 0 {
                 temp |= 0b1000_0000;
             }
-            self.write_all(&[temp]).unwrap();
+            self.write_all(&[temp])?;
             if val == 0 {
-                break;
+                return Ok(());
             }
         }
     }
 
-    fn write_byte(&mut self, val: i8) {
-        self.write_all(&[val as u8]).unwrap();
+    fn write_byte(&mut self, val: i8) -> EncodeResult<()> {
+        Ok(self.write_all(&[val as u8])?)
     }
 
-    fn write_unsigned_byte(&mut self, val: u8) {
-        self.write_all(&[val]).unwrap();
+    fn write_unsigned_byte(&mut self, val: u8) -> EncodeResult<()> {
+        Ok(self.write_all(&[val])?)
     }
 
-    fn write_short(&mut self, val: i16) {
-        self.write_i16::<BigEndian>(val).unwrap();
+    fn write_short(&mut self, val: i16) -> EncodeResult<()> {
+        Ok(self.write_i16::<BigEndian>(val)?)
     }
 
-    fn write_unsigned_short(&mut self, val: u16) {
-        self.write_u16::<BigEndian>(val).unwrap();
+    fn write_unsigned_short(&mut self, val: u16) -> EncodeResult<()> {
+        Ok(self.write_u16::<BigEndian>(val)?)
     }
 
-    fn write_int(&mut self, val: i32) {
-        self.write_i32::<BigEndian>(val).unwrap();
+    fn write_int(&mut self, val: i32) -> EncodeResult<()> {
+        Ok(self.write_i32::<BigEndian>(val)?)
     }
 
-    fn write_double(&mut self, val: f64) {
-        self.write_f64::<BigEndian>(val).unwrap();
+    fn write_double(&mut self, val: f64) -> EncodeResult<()> {
+        Ok(self.write_f64::<BigEndian>(val)?)
     }
 
-    fn write_float(&mut self, val: f32) {
-        self.write_f32::<BigEndian>(val).unwrap();
+    fn write_float(&mut self, val: f32) -> EncodeResult<()> {
+        Ok(self.write_f32::<BigEndian>(val)?)
     }
 
-    fn write_string(&mut self, n: usize, val: &str) {
-        if val.len() > n * 4 + 3 {
-            panic!("Tried to write string longer than the max length!");
+    /// Writes a string whose field allows at most `n` characters
+    fn write_string(&mut self, n: usize, val: &str) -> EncodeResult<()> {
+        let max_length = max_string_bytes(n);
+        if val.len() > max_length {
+            return Err(PacketEncodeError::StringTooLong {
+                length: val.len(),
+                max_length,
+            });
         }
-        self.write_varint(val.len() as i32);
-        self.write_all(val.as_bytes()).unwrap();
+        self.write_varint(val.len() as i32)?;
+        Ok(self.write_all(val.as_bytes())?)
     }
 
-    fn write_uuid(&mut self, val: u128) {
-        self.write_u128::<BigEndian>(val).unwrap();
+    fn write_uuid(&mut self, val: u128) -> EncodeResult<()> {
+        Ok(self.write_u128::<BigEndian>(val)?)
     }
 
-    fn write_long(&mut self, val: i64) {
-        self.write_i64::<BigEndian>(val).unwrap();
+    fn write_long(&mut self, val: i64) -> EncodeResult<()> {
+        Ok(self.write_i64::<BigEndian>(val)?)
     }
 
-    fn write_position(&mut self, x: i32, y: i32, z: i32) {
+    fn write_position(&mut self, x: i32, y: i32, z: i32) -> EncodeResult<()> {
         let long =
             ((x as i64 & 0x3FF_FFFF) << 38) | ((z as i64 & 0x3FF_FFFF) << 12) | (y as i64 & 0xFFF);
-        self.write_long(long);
+        self.write_long(long)
     }
 
-    fn write_bool(&mut self, val: bool) {
-        self.write_u8(val as u8).unwrap();
+    fn write_bool(&mut self, val: bool) -> EncodeResult<()> {
+        Ok(self.write_u8(val as u8)?)
     }
 
-    fn write_nbt<T: Serialize>(&mut self, nbt: &T) {
-        let _ = nbt::to_writer(self, nbt, None);
+    fn write_nbt<T: Serialize>(&mut self, nbt: &T) -> EncodeResult<()> {
+        Ok(nbt::to_writer(self, nbt, None)?)
     }
 
-    fn write_nbt_blob(&mut self, blob: &nbt::Blob)
+    fn write_nbt_blob(&mut self, blob: &nbt::Blob) -> EncodeResult<()>
     where
         Self: Sized,
     {
-        blob.to_writer(self).unwrap();
+        Ok(blob.to_writer(self)?)
     }
 
-    fn write_slot_data(&mut self, slot_data: &Option<SlotData>)
+    fn write_slot_data(&mut self, slot_data: &Option<SlotData>) -> EncodeResult<()>
     where
         Self: Sized,
     {
         if let Some(slot) = slot_data {
-            self.write_bool(true);
-            self.write_varint(slot.item_id);
-            self.write_byte(slot.item_count);
+            self.write_bool(true)?;
+            self.write_varint(slot.item_id)?;
+            self.write_byte(slot.item_count)?;
             if let Some(nbt) = &slot.nbt {
-                self.write_nbt_blob(nbt);
+                self.write_nbt_blob(nbt)
             } else {
-                self.write_byte(0); // End tag
+                self.write_byte(0) // End tag
             }
         } else {
-            self.write_bool(false);
+            self.write_bool(false)
         }
     }
 }
@@ -290,74 +479,67 @@ pub struct PacketEncoder {
 }
 
 impl PacketEncoder {
-    fn new(buffer: Vec<u8>, packet_id: u32) -> PacketEncoder {
+    /// Creates a packet from its id and encoded data
+    pub fn new(buffer: Vec<u8>, packet_id: u32) -> PacketEncoder {
         PacketEncoder { buffer, packet_id }
     }
 
-    // This function is seperate because it is needed when writing packet headers
-    fn varint(val: i32) -> Vec<u8> {
-        let mut val = val as u32;
-        let mut buf = Vec::new();
-        loop {
-            let mut temp = (val & 0b1111_1111) as u8;
-            val >>= 7;
-            if val != 0 {
-                temp |= 0b1000_0000;
-            }
-            buf.push(temp);
-            if val == 0 {
-                return buf;
-            }
-        }
+    /// Writes the packet using the compressed format with a threshold of 256 bytes. Use a
+    /// [`codec::FrameEncoder`] to reuse buffers between packets or to pick another threshold.
+    pub fn write_compressed(&self, w: impl Write) -> io::Result<()> {
+        codec::FrameEncoder::new(Some(256)).write_packet(self, w)
     }
 
-    pub fn write_compressed(&self, mut w: impl Write) -> io::Result<()> {
-        // TODO: zero allocation
-        let packet_id = PacketEncoder::varint(self.packet_id as i32);
-        let data = [packet_id.as_slice(), self.buffer.as_slice()].concat();
-        if self.buffer.len() < 256 {
-            // Data Length adds another byte
-            let packet_length = PacketEncoder::varint((1 + data.len()) as i32);
-
-            w.write_all(&packet_length)?;
-            // Data Length: 0 because uncompressed
-            w.write_all(&[0])?;
-            w.write_all(&data)?;
-        } else {
-            let data_length = PacketEncoder::varint(data.len() as i32);
-            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-            encoder.write_all(&data)?;
-            let compressed = encoder.finish().unwrap();
-            let packet_length =
-                PacketEncoder::varint((data_length.len() + compressed.len()) as i32);
-
-            w.write_all(&packet_length)?;
-            w.write_all(&data_length)?;
-            w.write_all(&compressed)?;
-        }
-
-        // self.c_cache = Some(finished);
-        // return self.c_cache.as_ref().unwrap();
-
-        Ok(())
+    pub fn write_uncompressed(&self, w: impl Write) -> io::Result<()> {
+        codec::FrameEncoder::new(None).write_packet(self, w)
     }
+}
 
-    pub fn write_uncompressed(&self, mut w: impl Write) -> io::Result<()> {
-        // if let Some(data) = &self.unc_cache {
-        //     return &data;
-        // }
-
-        let packet_id = PacketEncoder::varint(self.packet_id as i32);
-        let length = PacketEncoder::varint((self.buffer.len() + packet_id.len()) as i32);
-
-        // https://github.com/rust-lang/rust/issues/70436
-        w.write_all(&length)?;
-        w.write_all(&packet_id)?;
-        w.write_all(&self.buffer)?;
-
-        // self.unc_cache = Some([&length[..], &packet_id[..], &self.buffer[..]].concat());
-        // return self.c_cache.as_ref().unwrap();
-
-        Ok(())
-    }
+#[test]
+fn malformed_input_is_an_error() {
+    // VarInts are at most 5 bytes, VarLongs at most 10
+    let mut varint = Cursor::new([0xFF; 5]);
+    assert!(matches!(
+        varint.read_varint(),
+        Err(PacketDecodeError::VarIntTooLong)
+    ));
+    let mut varlong = Cursor::new([0xFF; 10]);
+    assert!(matches!(
+        varlong.read_varlong(),
+        Err(PacketDecodeError::VarLongTooLong)
+    ));
+
+    let mut buf = Vec::new();
+    buf.write_varlong(i64::MIN).unwrap();
+    buf.write_varlong(-1).unwrap();
+    assert_eq!(buf.len(), 20);
+    let mut buf = Cursor::new(buf);
+    assert_eq!(buf.read_varlong().unwrap(), i64::MIN);
+    assert_eq!(buf.read_varlong().unwrap(), -1);
+
+    let mut buf = Vec::new();
+    buf.write_varint(-1).unwrap();
+    assert!(matches!(
+        Cursor::new(buf).read_string(),
+        Err(PacketDecodeError::InvalidStringLength(-1))
+    ));
+    let mut buf = Vec::new();
+    buf.write_varint(0x20).unwrap();
+    buf.write_varint(1).unwrap();
+    assert!(matches!(
+        read_packet(&mut Cursor::new(buf)),
+        Err(PacketDecodeError::Io(_))
+    ));
+    assert!(matches!(
+        read_packet(&mut Cursor::new([0])),
+        Err(PacketDecodeError::InvalidPacketLength(0))
+    ));
+
+    assert!(matches!(
+        Vec::new().write_string(16, &"a".repeat(100)),
+        Err(PacketEncodeError::StringTooLong {
+            length: 100,
+            max_length: 67
+        })
+    ));
 }
@@ -0,0 +1,393 @@
+use super::{
+    DecodeResult, EncodeResult, PacketDecoderExt, PacketEncoder, PacketEncoderExt, SlotData,
+};
+
+pub trait ServerBoundPacket: Sized {
+    fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self>;
+}
+
+/// Sent in the handshaking state, `next_state` is 1 for status and 2 for login
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SHandshake {
+    pub protocol_version: i32,
+    pub server_address: String,
+    pub server_port: u16,
+    pub next_state: i32,
+}
+
+impl ServerBoundPacket for SHandshake {
+    fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        Ok(SHandshake {
+            protocol_version: decoder.read_varint()?,
+            server_address: decoder.read_string()?,
+            server_port: decoder.read_unsigned_short()?,
+            next_state: decoder.read_varint()?,
+        })
+    }
+}
+
+impl SHandshake {
+    /// Encodes the packet the way a client sends it
+    pub fn encode(&self) -> EncodeResult<PacketEncoder> {
+        let mut buf = Vec::new();
+        buf.write_varint(self.protocol_version)?;
+        buf.write_string(255, &self.server_address)?;
+        buf.write_unsigned_short(self.server_port)?;
+        buf.write_varint(self.next_state)?;
+        Ok(PacketEncoder::new(buf, 0x00))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SLoginStart {
+    pub name: String,
+}
+
+impl ServerBoundPacket for SLoginStart {
+    fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        Ok(SLoginStart {
+            name: decoder.read_string()?,
+        })
+    }
+}
+
+impl SLoginStart {
+    /// Encodes the packet the way a client sends it
+    pub fn encode(&self) -> EncodeResult<PacketEncoder> {
+        let mut buf = Vec::new();
+        buf.write_string(16, &self.name)?;
+        Ok(PacketEncoder::new(buf, 0x00))
+    }
+}
+
+/// Sent in the status state to request the server status, this packet has no fields
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SRequest;
+
+impl ServerBoundPacket for SRequest {
+    fn decode<T: PacketDecoderExt>(_decoder: &mut T) -> DecodeResult<Self> {
+        Ok(SRequest)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SPing {
+    pub payload: i64,
+}
+
+impl ServerBoundPacket for SPing {
+    fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        Ok(SPing {
+            payload: decoder.read_long()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SKeepAlive {
+    pub id: i64,
+}
+
+impl ServerBoundPacket for SKeepAlive {
+    fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        Ok(SKeepAlive {
+            id: decoder.read_long()?,
+        })
+    }
+}
+
+/// A chat message, or a command if it starts with a slash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SChatMessage {
+    pub message: String,
+}
+
+impl SChatMessage {
+    /// The command without the leading slash, if this message is a command
+    pub fn command(&self) -> Option<&str> {
+        self.message.strip_prefix('/')
+    }
+}
+
+impl ServerBoundPacket for SChatMessage {
+    fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        Ok(SChatMessage {
+            message: decoder.read_string()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SPlayerPosition {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub on_ground: bool,
+}
+
+impl ServerBoundPacket for SPlayerPosition {
+    fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        Ok(SPlayerPosition {
+            x: decoder.read_double()?,
+            y: decoder.read_double()?,
+            z: decoder.read_double()?,
+            on_ground: decoder.read_bool()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SPlayerPositionAndRotation {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub on_ground: bool,
+}
+
+impl ServerBoundPacket for SPlayerPositionAndRotation {
+    fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        Ok(SPlayerPositionAndRotation {
+            x: decoder.read_double()?,
+            y: decoder.read_double()?,
+            z: decoder.read_double()?,
+            yaw: decoder.read_float()?,
+            pitch: decoder.read_float()?,
+            on_ground: decoder.read_bool()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SPlayerRotation {
+    pub yaw: f32,
+    pub pitch: f32,
+    pub on_ground: bool,
+}
+
+impl ServerBoundPacket for SPlayerRotation {
+    fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        Ok(SPlayerRotation {
+            yaw: decoder.read_float()?,
+            pitch: decoder.read_float()?,
+            on_ground: decoder.read_bool()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SPlayerDigging {
+    /// 0: started digging, 1: cancelled digging, 2: finished digging, 3: drop item stack,
+    /// 4: drop item, 5: shoot arrow / finish eating, 6: swap item in hand
+    pub status: i32,
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    pub face: i8,
+}
+
+impl ServerBoundPacket for SPlayerDigging {
+    fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        let status = decoder.read_varint()?;
+        let (x, y, z) = decoder.read_position()?;
+        Ok(SPlayerDigging {
+            status,
+            x,
+            y,
+            z,
+            face: decoder.read_byte()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SHeldItemChange {
+    /// The selected hotbar slot, from 0 to 8
+    pub slot: i16,
+}
+
+impl ServerBoundPacket for SHeldItemChange {
+    fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        Ok(SHeldItemChange {
+            slot: decoder.read_short()?,
+        })
+    }
+}
+
+/// Sent when a player in creative mode changes a slot of their inventory
+#[derive(Debug)]
+pub struct SCreativeInventoryAction {
+    pub slot: i16,
+    pub clicked_item: Option<SlotData>,
+}
+
+impl ServerBoundPacket for SCreativeInventoryAction {
+    fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        Ok(SCreativeInventoryAction {
+            slot: decoder.read_short()?,
+            clicked_item: decoder.read_slot_data()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SPlayerBlockPlacement {
+    /// 0: main hand, 1: off hand
+    pub hand: i32,
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    pub face: i32,
+    pub cursor_x: f32,
+    pub cursor_y: f32,
+    pub cursor_z: f32,
+    pub inside_block: bool,
+}
+
+impl ServerBoundPacket for SPlayerBlockPlacement {
+    fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        let hand = decoder.read_varint()?;
+        let (x, y, z) = decoder.read_position()?;
+        Ok(SPlayerBlockPlacement {
+            hand,
+            x,
+            y,
+            z,
+            face: decoder.read_varint()?,
+            cursor_x: decoder.read_float()?,
+            cursor_y: decoder.read_float()?,
+            cursor_z: decoder.read_float()?,
+            inside_block: decoder.read_bool()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SUseItem {
+    /// 0: main hand, 1: off hand
+    pub hand: i32,
+}
+
+impl ServerBoundPacket for SUseItem {
+    fn decode<T: PacketDecoderExt>(decoder: &mut T) -> DecodeResult<Self> {
+        Ok(SUseItem {
+            hand: decoder.read_varint()?,
+        })
+    }
+}
+
+/// A packet sent by the client in the play state
+#[derive(Debug)]
+pub enum SPlayPacket {
+    ChatMessage(SChatMessage),
+    KeepAlive(SKeepAlive),
+    PlayerPosition(SPlayerPosition),
+    PlayerPositionAndRotation(SPlayerPositionAndRotation),
+    PlayerRotation(SPlayerRotation),
+    PlayerDigging(SPlayerDigging),
+    HeldItemChange(SHeldItemChange),
+    CreativeInventoryAction(SCreativeInventoryAction),
+    PlayerBlockPlacement(SPlayerBlockPlacement),
+    UseItem(SUseItem),
+    /// A packet without a decoder, the remaining packet data is kept as is
+    Unknown {
+        id: i32,
+        data: Vec<u8>,
+    },
+}
+
+impl SPlayPacket {
+    /// Decodes the packet body for the packet id `id`. The decoder should only contain this
+    /// packet, since unknown packets read it to the end.
+    pub fn decode<T: PacketDecoderExt>(id: i32, decoder: &mut T) -> DecodeResult<SPlayPacket> {
+        Ok(match id {
+            0x03 => SPlayPacket::ChatMessage(SChatMessage::decode(decoder)?),
+            0x0F => SPlayPacket::KeepAlive(SKeepAlive::decode(decoder)?),
+            0x11 => SPlayPacket::PlayerPosition(SPlayerPosition::decode(decoder)?),
+            0x12 => {
+                SPlayPacket::PlayerPositionAndRotation(SPlayerPositionAndRotation::decode(decoder)?)
+            }
+            0x13 => SPlayPacket::PlayerRotation(SPlayerRotation::decode(decoder)?),
+            0x1A => SPlayPacket::PlayerDigging(SPlayerDigging::decode(decoder)?),
+            0x25 => SPlayPacket::HeldItemChange(SHeldItemChange::decode(decoder)?),
+            0x28 => {
+                SPlayPacket::CreativeInventoryAction(SCreativeInventoryAction::decode(decoder)?)
+            }
+            0x2E => SPlayPacket::PlayerBlockPlacement(SPlayerBlockPlacement::decode(decoder)?),
+            0x2F => SPlayPacket::UseItem(SUseItem::decode(decoder)?),
+            id => SPlayPacket::Unknown {
+                id,
+                data: PacketDecoderExt::read_to_end(decoder)?,
+            },
+        })
+    }
+}
+
+#[test]
+fn decode_play_packets() {
+    use super::PacketEncoderExt;
+    use std::io::Cursor;
+
+    let mut buf = Vec::new();
+    buf.write_varint(0).unwrap();
+    buf.write_position(-5, 70, 300).unwrap();
+    buf.write_varint(1).unwrap();
+    buf.write_float(0.5).unwrap();
+    buf.write_float(1.0).unwrap();
+    buf.write_float(0.25).unwrap();
+    buf.write_bool(false).unwrap();
+    let packet = SPlayPacket::decode(0x2E, &mut Cursor::new(buf)).unwrap();
+    match packet {
+        SPlayPacket::PlayerBlockPlacement(placement) => assert_eq!(
+            placement,
+            SPlayerBlockPlacement {
+                hand: 0,
+                x: -5,
+                y: 70,
+                z: 300,
+                face: 1,
+                cursor_x: 0.5,
+                cursor_y: 1.0,
+                cursor_z: 0.25,
+                inside_block: false,
+            }
+        ),
+        packet => panic!("expected a block placement, got {:?}", packet),
+    }
+
+    let mut buf = Vec::new();
+    buf.write_short(36).unwrap();
+    buf.write_slot_data(&Some(SlotData {
+        item_id: 7,
+        item_count: 64,
+        nbt: None,
+    }))
+    .unwrap();
+    let packet = SPlayPacket::decode(0x28, &mut Cursor::new(buf)).unwrap();
+    match packet {
+        SPlayPacket::CreativeInventoryAction(action) => {
+            let item = action.clicked_item.unwrap();
+            assert_eq!((action.slot, item.item_id, item.item_count), (36, 7, 64));
+            assert!(item.nbt.is_none());
+        }
+        packet => panic!("expected a creative inventory action, got {:?}", packet),
+    }
+
+    let mut buf = Vec::new();
+    buf.write_string(256, "/rp c -io").unwrap();
+    let packet = SPlayPacket::decode(0x03, &mut Cursor::new(buf)).unwrap();
+    match packet {
+        SPlayPacket::ChatMessage(chat) => assert_eq!(chat.command(), Some("rp c -io")),
+        packet => panic!("expected a chat message, got {:?}", packet),
+    }
+
+    let packet = SPlayPacket::decode(0x7F, &mut Cursor::new(vec![1, 2, 3])).unwrap();
+    assert!(matches!(
+        packet,
+        SPlayPacket::Unknown { id: 0x7F, data } if data == [1, 2, 3]
+    ));
+
+    // Truncated packets are errors, not panics
+    assert!(SKeepAlive::decode(&mut Cursor::new(vec![0, 0, 1])).is_err());
+}
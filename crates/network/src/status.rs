@@ -0,0 +1,185 @@
+//! The status state, used by clients to show the server in the server list.
+
+use crate::packets::clientbound::{CPong, CResponse, ClientBoundPacket};
+use crate::packets::serverbound::{SPing, SRequest, ServerBoundPacket};
+use crate::packets::{read_packet, PacketDecodeError, PacketDecoderExt};
+use crate::{MINECRAFT_VERSION, PROTOCOL_VERSION};
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+#[derive(Serialize, Debug, Clone)]
+struct StatusVersion {
+    name: String,
+    protocol: i32,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct StatusPlayers {
+    max: usize,
+    online: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct StatusDescription {
+    text: String,
+}
+
+/// The response to a status request. This is serialized to the JSON format the client expects.
+#[derive(Serialize, Debug, Clone)]
+pub struct ServerStatus {
+    version: StatusVersion,
+    players: StatusPlayers,
+    description: StatusDescription,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    favicon: Option<String>,
+}
+
+impl ServerStatus {
+    pub fn new(motd: impl Into<String>, max_players: usize) -> ServerStatus {
+        ServerStatus {
+            version: StatusVersion {
+                name: MINECRAFT_VERSION.to_owned(),
+                protocol: PROTOCOL_VERSION,
+            },
+            players: StatusPlayers {
+                max: max_players,
+                online: 0,
+            },
+            description: StatusDescription { text: motd.into() },
+            favicon: None,
+        }
+    }
+
+    pub fn set_online_players(&mut self, online: usize) {
+        self.players.online = online;
+    }
+
+    /// Sets the icon shown in the server list. This should be a 64x64 PNG image.
+    pub fn set_favicon(&mut self, png: &[u8]) -> io::Result<()> {
+        if !png.starts_with(&PNG_SIGNATURE) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "favicon is not a PNG image",
+            ));
+        }
+        self.favicon = Some(format!("data:image/png;base64,{}", base64::encode(png)));
+        Ok(())
+    }
+
+    /// Reads the favicon from the PNG image at `path`
+    pub fn load_favicon(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.set_favicon(&fs::read(path)?)
+    }
+
+    pub fn to_json(&self) -> String {
+        // Serializing can't fail, all keys are strings
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// Answers a client that sent a handshake with the status next state. The client first requests
+/// the status and then optionally pings the server to measure latency, after which it closes the
+/// connection.
+pub fn serve_status<S>(stream: &mut S, status: &ServerStatus) -> io::Result<()>
+where
+    S: PacketDecoderExt + Write,
+{
+    let (id, mut decoder) = read_packet(stream)?;
+    if id != 0x00 {
+        return Err(PacketDecodeError::UnexpectedPacketId(id).into());
+    }
+    SRequest::decode(&mut decoder)?;
+    let response = CResponse {
+        json_response: status.to_json(),
+    };
+    response.encode()?.write_uncompressed(&mut *stream)?;
+
+    let (id, mut decoder) = match read_packet(stream) {
+        Ok(packet) => packet,
+        // The client closed the connection without pinging
+        Err(PacketDecodeError::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+            return Ok(())
+        }
+        Err(err) => return Err(err.into()),
+    };
+    if id != 0x01 {
+        return Err(PacketDecodeError::UnexpectedPacketId(id).into());
+    }
+    let ping = SPing::decode(&mut decoder)?;
+    CPong {
+        payload: ping.payload,
+    }
+    .encode()?
+    .write_uncompressed(&mut *stream)
+}
+
+#[test]
+fn status_over_loopback() {
+    use crate::packets::serverbound::SHandshake;
+    use crate::packets::{PacketEncoder, PacketEncoderExt};
+    use std::net::{TcpListener, TcpStream};
+
+    let mut status = ServerStatus::new("A redstone server", 20);
+    status.set_online_players(3);
+    status.set_favicon(&PNG_SIGNATURE).unwrap();
+    assert!(status.set_favicon(b"GIF89a").is_err());
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let (_, mut decoder) = read_packet(&mut stream).unwrap();
+        let handshake = SHandshake::decode(&mut decoder).unwrap();
+        assert_eq!(handshake.next_state, 1);
+        serve_status(&mut stream, &status).unwrap();
+
+        // Anything but a status request is rejected
+        let (mut stream, _) = listener.accept().unwrap();
+        let err = serve_status(&mut stream, &status).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    });
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    let handshake = SHandshake {
+        protocol_version: PROTOCOL_VERSION,
+        server_address: "localhost".to_owned(),
+        server_port: addr.port(),
+        next_state: 1,
+    };
+    handshake
+        .encode()
+        .unwrap()
+        .write_uncompressed(&mut client)
+        .unwrap();
+    PacketEncoder::new(Vec::new(), 0x00)
+        .write_uncompressed(&mut client)
+        .unwrap();
+
+    let (id, mut response) = read_packet(&mut client).unwrap();
+    assert_eq!(id, 0x00);
+    let json: serde_json::Value = serde_json::from_str(&response.read_string().unwrap()).unwrap();
+    assert_eq!(json["version"]["protocol"], PROTOCOL_VERSION);
+    assert_eq!(json["players"]["online"], 3);
+    assert_eq!(json["players"]["max"], 20);
+    assert_eq!(json["description"]["text"], "A redstone server");
+    assert_eq!(json["favicon"], "data:image/png;base64,iVBORw0KGgo=");
+
+    let mut ping = Vec::new();
+    ping.write_long(0x0123_4567_89AB_CDEF).unwrap();
+    PacketEncoder::new(ping, 0x01)
+        .write_uncompressed(&mut client)
+        .unwrap();
+    let (id, mut pong) = read_packet(&mut client).unwrap();
+    assert_eq!(id, 0x01);
+    assert_eq!(pong.read_long().unwrap(), 0x0123_4567_89AB_CDEF);
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    PacketEncoder::new(Vec::new(), 0x02)
+        .write_uncompressed(&mut client)
+        .unwrap();
+    server.join().unwrap();
+}
@@ -0,0 +1,153 @@
+//! Everything that happens on a connection before the player joins the plot.
+
+use mchprs_network::packets::capture::PacketCapture;
+use mchprs_network::packets::clientbound::{CDisconnectLogin, CLoginSuccess, ClientBoundPacket};
+use mchprs_network::packets::read_packet;
+use mchprs_network::packets::serverbound::{SHandshake, SLoginStart, ServerBoundPacket};
+use mchprs_network::status::{serve_status, ServerStatus};
+use mchprs_network::{MINECRAFT_VERSION, PROTOCOL_VERSION};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+/// A capture of a play session. Serverbound packets are recorded by the connection thread and
+/// clientbound packets by the server, so both share it.
+pub type SharedCapture = Arc<Mutex<PacketCapture<BufWriter<File>>>>;
+
+pub fn send_packet(stream: &mut TcpStream, packet: &impl ClientBoundPacket) -> io::Result<()> {
+    packet.encode()?.write_uncompressed(stream)
+}
+
+/// Starts capturing the play session of a player to a new file in `dir`, which is named after the
+/// UUID of the player. Failing to create the capture does not stop the player from joining.
+pub fn start_capture(dir: &Path, username: &str, uuid: u128) -> Option<SharedCapture> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let path = dir.join(format!("{:032x}-{}.capture", uuid, timestamp));
+    let capture = std::fs::create_dir_all(dir)
+        .map_err(Into::into)
+        .and_then(|_| PacketCapture::create(&path));
+    match capture {
+        Ok(capture) => {
+            info!("Capturing packets of {} to {}", username, path.display());
+            Some(Arc::new(Mutex::new(capture)))
+        }
+        Err(err) => {
+            warn!("Could not create capture {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+/// Returns true if `username` is 1 to 16 characters of `[A-Za-z0-9_]`, like vanilla usernames
+pub fn is_valid_username(username: &str) -> bool {
+    (1..=16).contains(&username.len())
+        && username
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+/// The UUID the vanilla server uses for players in offline mode
+pub fn offline_uuid(username: &str) -> u128 {
+    let mut bytes = md5::compute(format!("OfflinePlayer:{}", username)).0;
+    // Version 3, IETF variant
+    bytes[6] = (bytes[6] & 0x0F) | 0x30;
+    bytes[8] = (bytes[8] & 0x3F) | 0x80;
+    u128::from_be_bytes(bytes)
+}
+
+/// A client that finished logging in and is now in the play state
+pub struct LoggedIn {
+    pub username: String,
+    pub uuid: u128,
+}
+
+/// Runs the handshake and either answers a status request or logs the client in. Returns `None`
+/// if the connection was only used for status or the client could not join.
+///
+/// A player who logs in takes up a slot in `players_online`, which the server frees again once
+/// the player leaves.
+pub fn handshake(
+    stream: &mut TcpStream,
+    status: &ServerStatus,
+    players_online: &AtomicUsize,
+    max_players: usize,
+) -> io::Result<Option<LoggedIn>> {
+    let (_, mut decoder) = read_packet(stream)?;
+    let handshake = SHandshake::decode(&mut decoder)?;
+    match handshake.next_state {
+        1 => {
+            let mut status = status.clone();
+            status.set_online_players(players_online.load(Ordering::Relaxed));
+            serve_status(stream, &status)?;
+            Ok(None)
+        }
+        2 => login(
+            stream,
+            handshake.protocol_version,
+            players_online,
+            max_players,
+        ),
+        state => {
+            debug!("Client requested unknown state {}", state);
+            Ok(None)
+        }
+    }
+}
+
+fn login(
+    stream: &mut TcpStream,
+    protocol_version: i32,
+    players_online: &AtomicUsize,
+    max_players: usize,
+) -> io::Result<Option<LoggedIn>> {
+    let (_, mut decoder) = read_packet(stream)?;
+    let login_start = SLoginStart::decode(&mut decoder)?;
+    if protocol_version != PROTOCOL_VERSION {
+        warn!(
+            "{} tried to join using protocol version {}, only {} ({}) is supported",
+            login_start.name, protocol_version, PROTOCOL_VERSION, MINECRAFT_VERSION
+        );
+        let reason = if protocol_version > PROTOCOL_VERSION {
+            format!("Outdated server! I'm still on {}", MINECRAFT_VERSION)
+        } else {
+            format!("Outdated client! Please use {}", MINECRAFT_VERSION)
+        };
+        send_packet(stream, &CDisconnectLogin::from_text(&reason))?;
+        return Ok(None);
+    }
+    if !is_valid_username(&login_start.name) {
+        warn!("Refused login with invalid username {:?}", login_start.name);
+        send_packet(stream, &CDisconnectLogin::from_text("Invalid username!"))?;
+        return Ok(None);
+    }
+
+    let reserved = players_online.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |online| {
+        (online < max_players).then_some(online + 1)
+    });
+    if reserved.is_err() {
+        info!("{} could not join, the server is full", login_start.name);
+        send_packet(stream, &CDisconnectLogin::from_text("The server is full!"))?;
+        return Ok(None);
+    }
+
+    let uuid = offline_uuid(&login_start.name);
+    let login_success = CLoginSuccess {
+        uuid,
+        username: login_start.name.clone(),
+    };
+    if let Err(err) = send_packet(stream, &login_success) {
+        players_online.fetch_sub(1, Ordering::Relaxed);
+        return Err(err);
+    }
+    Ok(Some(LoggedIn {
+        username: login_start.name,
+        uuid,
+    }))
+}
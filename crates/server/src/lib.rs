@@ -0,0 +1,9 @@
+//! A minimal server for building in a single plot with a vanilla client. Players join in offline
+//! mode and can place, break and use blocks, while the plot is ticked without redpiler.
+
+mod connection;
+mod player;
+mod server;
+
+pub use connection::offline_uuid;
+pub use server::{Server, ServerConfig};
@@ -0,0 +1,32 @@
+use mchprs_server::{Server, ServerConfig};
+use std::path::PathBuf;
+
+fn main() {
+    tracing_subscriber::fmt::init();
+
+    // Usage: mchprs_server [bind address] [plot path] [capture directory]
+    let mut config = ServerConfig::default();
+    let mut args = std::env::args().skip(1);
+    if let Some(bind_address) = args.next() {
+        config.bind_address = bind_address;
+    }
+    if let Some(plot_path) = args.next() {
+        config.plot_path = PathBuf::from(plot_path);
+    }
+    if let Some(capture_dir) = args.next() {
+        config.capture_dir = Some(PathBuf::from(capture_dir));
+    }
+    // Like the vanilla server, use the server icon in the working directory if there is one
+    let favicon_path = PathBuf::from("server-icon.png");
+    if favicon_path.exists() {
+        config.favicon_path = Some(favicon_path);
+    }
+
+    match Server::bind(config) {
+        Ok(server) => server.run(),
+        Err(err) => {
+            eprintln!("Could not start server: {:?}", err);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use crate::connection::SharedCapture;
+use mchprs_blocks::items::{Item, ItemStack};
+use mchprs_blocks::BlockDirection;
+use mchprs_network::packets::capture::PacketDirection;
+use mchprs_network::packets::clientbound::ClientBoundPacket;
+use mchprs_network::packets::SlotData;
+use std::io;
+use std::net::TcpStream;
+use tracing::{debug, warn};
+
+/// The number of slots in the player inventory, including armor and the off hand
+const INVENTORY_SIZE: usize = 46;
+/// The inventory slot of the first hotbar slot
+const HOTBAR_START: usize = 36;
+const OFF_HAND_SLOT: usize = 45;
+
+pub struct Player {
+    pub entity_id: i32,
+    pub username: String,
+    pub uuid: u128,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub inventory: Vec<Option<ItemStack>>,
+    /// The selected hotbar slot, from 0 to 8
+    pub selected_slot: usize,
+    stream: TcpStream,
+    connected: bool,
+    /// Records every packet sent to the player, if captures are enabled
+    capture: Option<SharedCapture>,
+}
+
+impl Player {
+    pub fn new(
+        entity_id: i32,
+        username: String,
+        uuid: u128,
+        stream: TcpStream,
+        capture: Option<SharedCapture>,
+    ) -> Player {
+        Player {
+            entity_id,
+            username,
+            uuid,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            yaw: 0.0,
+            pitch: 0.0,
+            inventory: vec![None; INVENTORY_SIZE],
+            selected_slot: 0,
+            stream,
+            connected: true,
+            capture,
+        }
+    }
+
+    /// Sends a packet to the player. If sending fails, the player is marked as disconnected and
+    /// will be removed by the server.
+    pub fn send_packet(&mut self, packet: &impl ClientBoundPacket) {
+        if !self.connected {
+            return;
+        }
+        let result = packet.encode().map_err(io::Error::from).and_then(|packet| {
+            if let Some(capture) = &self.capture {
+                let recorded = capture
+                    .lock()
+                    .unwrap()
+                    .record(PacketDirection::Clientbound, &packet);
+                if let Err(err) = recorded {
+                    warn!("Stopped capturing packets of {}: {}", self.username, err);
+                    self.capture = None;
+                }
+            }
+            packet.write_uncompressed(&mut self.stream)
+        });
+        if let Err(err) = result {
+            debug!("Could not send packet to {}: {}", self.username, err);
+            self.disconnect();
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    pub fn disconnect(&mut self) {
+        self.connected = false;
+        let _ = self.stream.shutdown(std::net::Shutdown::Both);
+    }
+
+    pub fn set_inventory_slot(&mut self, slot: i16, slot_data: Option<SlotData>) {
+        let slot = match usize::try_from(slot) {
+            Ok(slot) if slot < INVENTORY_SIZE => slot,
+            // Slot -1 is used for dropping items out of the creative inventory
+            _ => return,
+        };
+        self.inventory[slot] = slot_data.map(|data| ItemStack {
+            item_type: Item::from_id(data.item_id as u32),
+            count: data.item_count as u8,
+            nbt: data.nbt,
+        });
+    }
+
+    /// The item held in the main hand (`hand` 0) or off hand (`hand` 1)
+    pub fn item_in_hand(&self, hand: i32) -> Option<&ItemStack> {
+        let slot = if hand == 0 {
+            HOTBAR_START + self.selected_slot
+        } else {
+            OFF_HAND_SLOT
+        };
+        self.inventory[slot].as_ref()
+    }
+
+    /// The horizontal direction the player is looking in
+    pub fn facing(&self) -> BlockDirection {
+        match ((self.yaw / 90.0) + 0.5).floor().rem_euclid(4.0) as u32 {
+            0 => BlockDirection::South,
+            1 => BlockDirection::West,
+            2 => BlockDirection::North,
+            _ => BlockDirection::East,
+        }
+    }
+}
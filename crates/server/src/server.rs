@@ -0,0 +1,807 @@
+use crate::connection::{self, LoggedIn, SharedCapture};
+use crate::player::Player;
+use anyhow::{Context, Result};
+use mchprs_blocks::{BlockFace, BlockPos};
+use mchprs_core::blocks::Block;
+use mchprs_core::items::{ActionResult, UseOnBlockContext};
+use mchprs_core::plot::data::{load_plot, sleep_time_for_tps};
+use mchprs_core::plot::{PlotWorld, PLOT_BLOCK_WIDTH, PLOT_WIDTH, WORLD_SEND_RATE};
+use mchprs_core::world::storage::Chunk;
+use mchprs_core::world::World;
+use mchprs_network::nbt_map::NBTMap;
+use mchprs_network::packets::capture::PacketDirection;
+use mchprs_network::packets::clientbound::*;
+use mchprs_network::packets::read_packet;
+use mchprs_network::packets::serverbound::{SPlayPacket, SPlayerBlockPlacement, SPlayerDigging};
+use mchprs_network::status::ServerStatus;
+use mchprs_save_data::plot_data::{PlotData, Tps};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error, info, warn};
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+/// Packets are written from the server thread, so a player who stops reading is disconnected
+/// after this long instead of stalling the server
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+const WORLD_NAME: &str = "mchprs:world";
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    /// The plot save file. A new plot is generated if it does not exist, and it is saved whenever
+    /// the last player leaves.
+    pub plot_path: PathBuf,
+    pub max_players: usize,
+    pub view_distance: i32,
+    /// The message shown below the server name in the server list
+    pub motd: String,
+    /// A 64x64 PNG image shown as the server icon in the server list
+    pub favicon_path: Option<PathBuf>,
+    /// If set, the play session of every player is recorded to a packet capture in this directory
+    pub capture_dir: Option<PathBuf>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            bind_address: "127.0.0.1:25565".to_owned(),
+            plot_path: PathBuf::from("./world/plots/p0,0"),
+            max_players: 20,
+            view_distance: 10,
+            motd: "Minecraft High Performance Redstone Server".to_owned(),
+            favicon_path: None,
+            capture_dir: None,
+        }
+    }
+}
+
+/// Sent from the connection threads to the server
+enum Message {
+    Joined {
+        entity_id: i32,
+        login: LoggedIn,
+        stream: TcpStream,
+        capture: Option<SharedCapture>,
+    },
+    Packet {
+        entity_id: i32,
+        packet: SPlayPacket,
+    },
+    Disconnected {
+        entity_id: i32,
+    },
+}
+
+/// A server hosting a single plot. Connections are accepted and read on their own threads, while
+/// the plot is only ever touched by the thread calling [`Server::update`].
+pub struct Server {
+    config: ServerConfig,
+    local_addr: SocketAddr,
+    world: PlotWorld,
+    tps: Tps,
+    players: Vec<Player>,
+    players_online: Arc<AtomicUsize>,
+    receiver: Receiver<Message>,
+    last_tick: Instant,
+    last_keep_alive: Instant,
+    unsaved_changes: bool,
+}
+
+impl Server {
+    /// Loads the plot and starts accepting connections
+    pub fn bind(config: ServerConfig) -> Result<Server> {
+        let data = load_plot(&config.plot_path)?;
+        let chunks = data
+            .chunk_data
+            .into_iter()
+            .enumerate()
+            .map(|(i, c)| Chunk::load(i as i32 / PLOT_WIDTH, i as i32 % PLOT_WIDTH, c))
+            .collect();
+        let world = PlotWorld {
+            x: 0,
+            z: 0,
+            chunks,
+            to_be_ticked: data.pending_ticks,
+        };
+
+        let listener = TcpListener::bind(&config.bind_address)
+            .with_context(|| format!("could not bind to {}", config.bind_address))?;
+        let local_addr = listener.local_addr()?;
+        info!("Listening on {}", local_addr);
+
+        let mut status = ServerStatus::new(config.motd.clone(), config.max_players);
+        if let Some(favicon_path) = &config.favicon_path {
+            if let Err(err) = status.load_favicon(favicon_path) {
+                warn!(
+                    "Could not load favicon from {}: {}",
+                    favicon_path.display(),
+                    err
+                );
+            }
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        let players_online = Arc::new(AtomicUsize::new(0));
+        let online = players_online.clone();
+        let max_players = config.max_players;
+        let capture_dir = config.capture_dir.clone();
+        thread::Builder::new()
+            .name("listener".to_owned())
+            .spawn(move || {
+                let status = Arc::new(status);
+                accept_connections(listener, sender, status, online, max_players, capture_dir)
+            })?;
+
+        Ok(Server {
+            config,
+            local_addr,
+            world,
+            tps: data.tps,
+            players: Vec::new(),
+            players_online,
+            receiver,
+            last_tick: Instant::now(),
+            last_keep_alive: Instant::now(),
+            unsaved_changes: false,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn world(&self) -> &PlotWorld {
+        &self.world
+    }
+
+    pub fn player_count(&self) -> usize {
+        self.players.len()
+    }
+
+    /// Runs the server forever
+    pub fn run(mut self) -> ! {
+        loop {
+            self.update();
+            thread::sleep(WORLD_SEND_RATE);
+        }
+    }
+
+    /// Handles all received packets, ticks the plot if a tick is due and sends the changed blocks
+    /// to every player
+    pub fn update(&mut self) {
+        while let Ok(message) = self.receiver.try_recv() {
+            match message {
+                Message::Joined {
+                    entity_id,
+                    login,
+                    stream,
+                    capture,
+                } => self.join(entity_id, login, stream, capture),
+                Message::Packet { entity_id, packet } => self.handle_packet(entity_id, packet),
+                Message::Disconnected { entity_id } => {
+                    if let Some(player) = self.players.iter_mut().find(|p| p.entity_id == entity_id)
+                    {
+                        player.disconnect();
+                    }
+                }
+            }
+        }
+
+        let tick_time = sleep_time_for_tps(self.tps);
+        if self.last_tick.elapsed() >= tick_time {
+            self.last_tick = Instant::now();
+            self.world.tick();
+        }
+
+        let changes = self.world.drain_multi_block_changes();
+        if !changes.is_empty() {
+            self.unsaved_changes = true;
+        }
+        for player in &mut self.players {
+            for change in &changes {
+                player.send_packet(change);
+            }
+        }
+
+        if self.last_keep_alive.elapsed() >= KEEP_ALIVE_INTERVAL {
+            self.last_keep_alive = Instant::now();
+            let id = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_millis() as i64);
+            for player in &mut self.players {
+                player.send_packet(&CKeepAlive { id });
+            }
+        }
+
+        let player_count = self.players.len();
+        self.players.retain(|player| {
+            if !player.is_connected() {
+                info!("{} left the game", player.username);
+            }
+            player.is_connected()
+        });
+        // Logins reserve their slot themselves, only free the slots of players who left
+        self.players_online
+            .fetch_sub(player_count - self.players.len(), Ordering::Relaxed);
+        if player_count > 0 && self.players.is_empty() && self.unsaved_changes {
+            self.save();
+        }
+    }
+
+    fn save(&mut self) {
+        let data = PlotData {
+            tps: self.tps,
+            chunk_data: self.world.chunks.iter_mut().map(Chunk::save).collect(),
+            pending_ticks: self.world.to_be_ticked.clone(),
+        };
+        if let Some(dir) = self.config.plot_path.parent() {
+            if let Err(err) = std::fs::create_dir_all(dir) {
+                error!("Could not create plot directory {}: {}", dir.display(), err);
+                return;
+            }
+        }
+        match data.save_to_file(&self.config.plot_path) {
+            Ok(()) => {
+                self.unsaved_changes = false;
+                info!("Saved plot to {}", self.config.plot_path.display());
+            }
+            Err(err) => error!("Could not save plot: {}", err),
+        }
+    }
+
+    fn spawn_pos(&self) -> BlockPos {
+        let (first, _) = self.world.get_corners();
+        let x = first.x + PLOT_BLOCK_WIDTH / 2;
+        let z = first.z + PLOT_BLOCK_WIDTH / 2;
+        let y = self.world.get_chunk(x >> 4, z >> 4).map_or(0, |chunk| {
+            chunk.get_height((x & 0xF) as u32, (z & 0xF) as u32) as i32
+        });
+        BlockPos::new(x, y, z)
+    }
+
+    fn join(
+        &mut self,
+        entity_id: i32,
+        login: LoggedIn,
+        stream: TcpStream,
+        capture: Option<SharedCapture>,
+    ) {
+        let mut player = Player::new(entity_id, login.username, login.uuid, stream, capture);
+        info!("UUID of player {} is {:032x}", player.username, player.uuid);
+        info!("{} joined the game", player.username);
+
+        let mut dimension_types = NBTMap::new("minecraft:dimension_type".to_owned());
+        dimension_types.push_element("minecraft:overworld".to_owned(), overworld());
+        let mut biomes = NBTMap::new("minecraft:worldgen/biome".to_owned());
+        biomes.push_element(
+            "minecraft:plains".to_owned(),
+            CJoinGameBiomeElement {
+                precipitation: "rain".to_owned(),
+                effects: CJoinGameBiomeEffects {
+                    sky_color: 0x78A7FF,
+                    water_fog_color: 0x050533,
+                    fog_color: 0xC0D8FF,
+                    water_color: 0x3F76E4,
+                },
+                temperature: 0.8,
+                downfall: 0.4,
+                category: "plains".to_owned(),
+            },
+        );
+        player.send_packet(&CJoinGame {
+            entity_id,
+            is_hardcore: false,
+            gamemode: 1,
+            // -1, no previous gamemode
+            previous_gamemode: 0xFF,
+            world_names: vec![WORLD_NAME.to_owned()],
+            dimension_codec: CJoinGameDimensionCodec {
+                dimension_types,
+                biomes,
+            },
+            dimension: overworld(),
+            world_name: WORLD_NAME.to_owned(),
+            hashed_seed: 0,
+            max_players: self.config.max_players as i32,
+            view_distance: self.config.view_distance,
+            simulation_distance: self.config.view_distance,
+            reduced_debug_info: false,
+            enable_respawn_screen: false,
+            is_debug: false,
+            is_flat: true,
+        });
+
+        let spawn = self.spawn_pos();
+        player.send_packet(&CUpdateViewPosition {
+            chunk_x: spawn.x >> 4,
+            chunk_z: spawn.z >> 4,
+        });
+        for chunk in &self.world.chunks {
+            match chunk.encode_packet() {
+                Ok(packet) => player.send_packet(&packet),
+                Err(err) => warn!("Could not send chunk {}, {}: {}", chunk.x, chunk.z, err),
+            }
+        }
+        player.send_packet(&CSpawnPosition {
+            x: spawn.x,
+            y: spawn.y,
+            z: spawn.z,
+            angle: 0.0,
+        });
+        player.x = spawn.x as f64 + 0.5;
+        player.y = spawn.y as f64;
+        player.z = spawn.z as f64 + 0.5;
+        player.send_packet(&CPlayerPositionAndLook {
+            x: player.x,
+            y: player.y,
+            z: player.z,
+            yaw: 0.0,
+            pitch: 0.0,
+            flags: 0,
+            teleport_id: 0,
+            dismount_vehicle: false,
+        });
+        self.players.push(player);
+    }
+
+    fn handle_packet(&mut self, entity_id: i32, packet: SPlayPacket) {
+        let player_idx = match self.players.iter().position(|p| p.entity_id == entity_id) {
+            Some(idx) => idx,
+            None => return,
+        };
+        let player = &mut self.players[player_idx];
+        match packet {
+            SPlayPacket::PlayerPosition(position) => {
+                player.x = position.x;
+                player.y = position.y;
+                player.z = position.z;
+            }
+            SPlayPacket::PlayerPositionAndRotation(position) => {
+                player.x = position.x;
+                player.y = position.y;
+                player.z = position.z;
+                player.yaw = position.yaw;
+                player.pitch = position.pitch;
+            }
+            SPlayPacket::PlayerRotation(rotation) => {
+                player.yaw = rotation.yaw;
+                player.pitch = rotation.pitch;
+            }
+            SPlayPacket::HeldItemChange(held_item) => {
+                player.selected_slot = held_item.slot.clamp(0, 8) as usize;
+            }
+            SPlayPacket::CreativeInventoryAction(action) => {
+                player.set_inventory_slot(action.slot, action.clicked_item);
+            }
+            SPlayPacket::ChatMessage(chat) => {
+                info!("<{}> {}", player.username, chat.message);
+            }
+            SPlayPacket::PlayerDigging(digging) => self.handle_digging(player_idx, digging),
+            SPlayPacket::PlayerBlockPlacement(placement) => {
+                self.handle_block_placement(player_idx, placement)
+            }
+            SPlayPacket::KeepAlive(_) | SPlayPacket::UseItem(_) => {}
+            SPlayPacket::Unknown { id, .. } => {
+                debug!("Ignoring packet 0x{:02X} from {}", id, player.username);
+            }
+        }
+    }
+
+    fn in_plot(&self, pos: BlockPos) -> bool {
+        let (first, second) = self.world.get_corners();
+        pos.x >= first.x
+            && pos.x <= second.x
+            && pos.y >= first.y
+            && pos.y <= second.y
+            && pos.z >= first.z
+            && pos.z <= second.z
+    }
+
+    /// Sends the actual block at `pos`, which corrects blocks the client predicted wrong
+    fn send_block(&mut self, player_idx: usize, pos: BlockPos) {
+        let block_id = if self.in_plot(pos) {
+            self.world.get_block_raw(pos)
+        } else {
+            0
+        };
+        self.players[player_idx].send_packet(&CBlockChange {
+            x: pos.x,
+            y: pos.y,
+            z: pos.z,
+            block_id: block_id as i32,
+        });
+    }
+
+    fn handle_digging(&mut self, player_idx: usize, digging: SPlayerDigging) {
+        // Players in creative mode break blocks instantly, so only started digging matters
+        if digging.status != 0 {
+            return;
+        }
+        let pos = BlockPos::new(digging.x, digging.y, digging.z);
+        if self.in_plot(pos) {
+            let block = self.world.get_block(pos);
+            block.destroy(&mut self.world, pos);
+        }
+        self.send_block(player_idx, pos);
+    }
+
+    fn handle_block_placement(&mut self, player_idx: usize, placement: SPlayerBlockPlacement) {
+        let block_pos = BlockPos::new(placement.x, placement.y, placement.z);
+        let block_face = BlockFace::from_id(placement.face as u32);
+        let player = &self.players[player_idx];
+        let item = player.item_in_hand(placement.hand).cloned();
+        let block_direction = player.facing();
+
+        if self.in_plot(block_pos) {
+            let block = self.world.get_block(block_pos);
+            let item_type = item.as_ref().map(|item| item.item_type);
+            if block.on_use(&mut self.world, block_pos, item_type) == ActionResult::Pass {
+                if let Some(item) = item {
+                    let place_pos = if block.can_place_block_in() {
+                        block_pos
+                    } else {
+                        block_pos.offset(block_face)
+                    };
+                    if self.in_plot(place_pos)
+                        && self.world.get_block(place_pos).can_place_block_in()
+                    {
+                        let context = UseOnBlockContext {
+                            block_pos,
+                            block_face,
+                            block_direction,
+                            cursor_y: placement.cursor_y,
+                        };
+                        let block = Block::get_state_for_placement(
+                            &self.world,
+                            place_pos,
+                            item.item_type,
+                            context,
+                        );
+                        if !matches!(block, Block::Air {}) {
+                            block.place_in_world(&mut self.world, place_pos, &item.nbt);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.send_block(player_idx, block_pos);
+        self.send_block(player_idx, block_pos.offset(block_face));
+    }
+}
+
+fn overworld() -> CJoinGameDimensionElement {
+    CJoinGameDimensionElement {
+        piglin_safe: false,
+        natural: true,
+        ambient_light: 1.0,
+        infiniburn: "#minecraft:infiniburn_overworld".to_owned(),
+        respawn_anchor_works: false,
+        has_skylight: true,
+        bed_works: true,
+        effects: "minecraft:overworld".to_owned(),
+        has_raids: false,
+        min_y: 0,
+        height: 256,
+        logical_height: 256,
+        coordinate_scale: 1.0,
+        ultrawarm: false,
+        has_ceiling: false,
+    }
+}
+
+fn accept_connections(
+    listener: TcpListener,
+    sender: Sender<Message>,
+    status: Arc<ServerStatus>,
+    players_online: Arc<AtomicUsize>,
+    max_players: usize,
+    capture_dir: Option<PathBuf>,
+) {
+    let mut next_entity_id = 0;
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("Could not accept connection: {}", err);
+                continue;
+            }
+        };
+        let entity_id = next_entity_id;
+        next_entity_id += 1;
+        let sender = sender.clone();
+        let status = status.clone();
+        let players_online = players_online.clone();
+        let capture_dir = capture_dir.clone();
+        let result = thread::Builder::new()
+            .name(format!("connection {}", entity_id))
+            .spawn(move || {
+                handle_connection(
+                    stream,
+                    entity_id,
+                    sender,
+                    &status,
+                    &players_online,
+                    max_players,
+                    capture_dir.as_deref(),
+                )
+            });
+        if let Err(err) = result {
+            error!("Could not spawn connection thread: {}", err);
+        }
+    }
+}
+
+fn handle_connection(
+    mut stream: TcpStream,
+    entity_id: i32,
+    sender: Sender<Message>,
+    status: &ServerStatus,
+    players_online: &AtomicUsize,
+    max_players: usize,
+    capture_dir: Option<&Path>,
+) {
+    let _ = stream.set_nodelay(true);
+    let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+    let login = match connection::handshake(&mut stream, status, players_online, max_players) {
+        Ok(Some(login)) => login,
+        Ok(None) => return,
+        Err(err) => {
+            debug!("Error during handshake: {}", err);
+            return;
+        }
+    };
+    let write_stream = match stream.try_clone() {
+        Ok(stream) => stream,
+        Err(err) => {
+            error!("Could not clone stream: {}", err);
+            return;
+        }
+    };
+    let mut capture =
+        capture_dir.and_then(|dir| connection::start_capture(dir, &login.username, login.uuid));
+    let joined = Message::Joined {
+        entity_id,
+        login,
+        stream: write_stream,
+        capture: capture.clone(),
+    };
+    if sender.send(joined).is_err() {
+        return;
+    }
+
+    loop {
+        let packet = read_packet(&mut stream).and_then(|(id, mut decoder)| {
+            if let Some(shared) = &capture {
+                let data = &decoder.get_ref()[decoder.position() as usize..];
+                let recorded =
+                    shared
+                        .lock()
+                        .unwrap()
+                        .record_raw(PacketDirection::Serverbound, id, data);
+                if let Err(err) = recorded {
+                    warn!(
+                        "Stopped capturing packets of connection {}: {}",
+                        entity_id, err
+                    );
+                    capture = None;
+                }
+            }
+            SPlayPacket::decode(id, &mut decoder)
+        });
+        let message = match packet {
+            Ok(packet) => Message::Packet { entity_id, packet },
+            Err(err) => {
+                debug!("Closing connection {}: {}", entity_id, err);
+                // The capture is complete once the server drops the player as well
+                capture = None;
+                Message::Disconnected { entity_id }
+            }
+        };
+        let disconnected = matches!(message, Message::Disconnected { .. });
+        if sender.send(message).is_err() || disconnected {
+            return;
+        }
+    }
+}
+
+/// Sends a packet from its id and encoded data, like a client would
+#[cfg(test)]
+fn send(stream: &mut TcpStream, id: u32, body: Vec<u8>) {
+    use mchprs_network::packets::PacketEncoder;
+
+    PacketEncoder::new(body, id)
+        .write_uncompressed(stream)
+        .unwrap();
+}
+
+/// Connects to the server at `addr` and starts logging in as `username`
+#[cfg(test)]
+fn start_login(addr: SocketAddr, protocol_version: i32, username: &str) -> TcpStream {
+    use mchprs_network::packets::serverbound::{SHandshake, SLoginStart};
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let handshake = SHandshake {
+        protocol_version,
+        server_address: "localhost".to_owned(),
+        server_port: addr.port(),
+        next_state: 2,
+    };
+    let login_start = SLoginStart {
+        name: username.to_owned(),
+    };
+    for packet in [handshake.encode(), login_start.encode()] {
+        packet.unwrap().write_uncompressed(&mut client).unwrap();
+    }
+    client
+}
+
+#[test]
+fn build_in_plot() {
+    use mchprs_blocks::items::Item;
+    use mchprs_network::packets::capture::CaptureReader;
+    use mchprs_network::packets::{PacketDecoderExt, PacketEncoderExt, SlotData};
+
+    /// Reads packets until one with `id` is received
+    fn read_until(stream: &mut TcpStream, id: i32) {
+        while read_packet(stream).unwrap().0 != id {}
+    }
+
+    fn update_until(server: &mut Server, condition: impl Fn(&Server) -> bool) {
+        let start = Instant::now();
+        while !condition(server) {
+            assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+            server.update();
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    let dir = std::env::temp_dir().join(format!("mchprs_server_test_{}", std::process::id()));
+    let mut server = Server::bind(ServerConfig {
+        bind_address: "127.0.0.1:0".to_owned(),
+        plot_path: dir.join("p0,0"),
+        capture_dir: Some(dir.join("captures")),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let addr = server.local_addr();
+    let client = thread::spawn(move || {
+        let mut client = start_login(addr, mchprs_network::PROTOCOL_VERSION, "tester");
+
+        let (id, mut login_success) = read_packet(&mut client).unwrap();
+        assert_eq!(id, 0x02);
+        let uuid = login_success.read_bytes(16).unwrap();
+        assert_eq!(uuid, connection::offline_uuid("tester").to_be_bytes());
+        // Player position and look is sent after the chunks
+        read_until(&mut client, 0x38);
+
+        // Break a block of the floor
+        let mut digging = Vec::new();
+        digging.write_varint(0).unwrap();
+        digging.write_position(8, 7, 8).unwrap();
+        digging.write_byte(1).unwrap();
+        send(&mut client, 0x1A, digging);
+        read_until(&mut client, 0x3F);
+
+        // Place stone on top of the floor next to it
+        let mut inventory_action = Vec::new();
+        inventory_action.write_short(36).unwrap();
+        inventory_action
+            .write_slot_data(&Some(SlotData {
+                item_id: Item::Stone {}.get_id() as i32,
+                item_count: 1,
+                nbt: None,
+            }))
+            .unwrap();
+        send(&mut client, 0x28, inventory_action);
+        let mut placement = Vec::new();
+        placement.write_varint(0).unwrap();
+        placement.write_position(9, 7, 8).unwrap();
+        placement.write_varint(1).unwrap();
+        placement.write_float(0.5).unwrap();
+        placement.write_float(1.0).unwrap();
+        placement.write_float(0.5).unwrap();
+        placement.write_bool(false).unwrap();
+        send(&mut client, 0x2E, placement);
+        read_until(&mut client, 0x3F);
+    });
+    update_until(&mut server, |_| client.is_finished());
+    client.join().unwrap();
+    assert!(matches!(
+        server.world().get_block(BlockPos::new(8, 7, 8)),
+        Block::Air {}
+    ));
+    assert!(matches!(
+        server.world().get_block(BlockPos::new(9, 8, 8)),
+        Block::Stone {}
+    ));
+
+    // The plot is saved once the last player leaves
+    update_until(&mut server, |server| server.player_count() == 0);
+    assert!(dir.join("p0,0").exists());
+
+    // The session was captured in both directions
+    let capture = std::fs::read_dir(dir.join("captures"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    let mut reader = CaptureReader::open(capture.path()).unwrap();
+    let mut clientbound = Vec::new();
+    let mut serverbound = Vec::new();
+    while let Some(packet) = reader.read_packet().unwrap() {
+        match packet.direction {
+            PacketDirection::Clientbound => clientbound.push(packet.decode_clientbound().unwrap()),
+            PacketDirection::Serverbound => serverbound.push(packet.decode_serverbound().unwrap()),
+        }
+    }
+    match &clientbound[0] {
+        CPlayPacket::JoinGame(join_game) => assert_eq!(join_game.max_players, 20),
+        packet => panic!("expected join game, got {:?}", packet),
+    }
+    assert!(clientbound.iter().any(
+        |packet| matches!(packet, CPlayPacket::ChunkData(chunk) if chunk.chunk_sections.len() == 16)
+    ));
+    assert!(clientbound.iter().any(|packet| matches!(
+        packet,
+        CPlayPacket::MultiBlockChange(CMultiBlockChange { records, .. }) if !records.is_empty()
+    )));
+    assert!(!clientbound
+        .iter()
+        .any(|packet| matches!(packet, CPlayPacket::Unknown { .. })));
+    assert!(matches!(serverbound[0], SPlayPacket::PlayerDigging(_)));
+    assert!(matches!(
+        serverbound.last(),
+        Some(SPlayPacket::PlayerBlockPlacement(_))
+    ));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn login_is_refused() {
+    use mchprs_network::packets::PacketDecoderExt;
+
+    /// Logs in and returns the reason of the login disconnect packet
+    fn login(addr: SocketAddr, protocol_version: i32, username: &str) -> String {
+        let mut client = start_login(addr, protocol_version, username);
+        let (id, mut disconnect) = read_packet(&mut client).unwrap();
+        assert_eq!(id, 0x00);
+        disconnect.read_string().unwrap()
+    }
+
+    let dir = std::env::temp_dir().join(format!("mchprs_refused_test_{}", std::process::id()));
+    let server = Server::bind(ServerConfig {
+        bind_address: "127.0.0.1:0".to_owned(),
+        plot_path: dir.join("p0,0"),
+        max_players: 0,
+        ..Default::default()
+    })
+    .unwrap();
+    let addr = server.local_addr();
+
+    let protocol_version = mchprs_network::PROTOCOL_VERSION;
+    assert_eq!(
+        login(addr, protocol_version - 1, "tester"),
+        r#"{"text":"Outdated client! Please use 1.18.2"}"#
+    );
+    for username in ["", "../../tester", "tester tester", "seventeen_letters"] {
+        assert_eq!(
+            login(addr, protocol_version, username),
+            r#"{"text":"Invalid username!"}"#
+        );
+    }
+    assert_eq!(
+        login(addr, protocol_version, "tester"),
+        r#"{"text":"The server is full!"}"#
+    );
+    assert_eq!(server.player_count(), 0);
+}
@@ -3,7 +3,7 @@ use std::time::Instant;
 
 use mchprs_core::blocks::{BlockPos, Block, BlockDirection};
 use mchprs_core::plot::{PlotWorld, PLOT_WIDTH, data::empty_plot};
-use mchprs_core::redpiler::{Compiler, CompilerOptions};
+use mchprs_core::redpiler::{Compiler, CompilerOptions, GraphCache};
 use mchprs_core::world::World;
 use mchprs_core::world::storage::Chunk;
 use mchprs_core::blocks::redstone::*;
@@ -70,15 +70,17 @@ fn load_world(path: impl AsRef<Path>) -> PlotWorld {
 }
 
 fn init_compiler() -> (PlotWorld, Compiler) {
-    let mut world = load_world("./chungus_mandelbrot_plot");
+    let path = "./chungus_mandelbrot_plot";
+    let mut world = load_world(path);
     let mut compiler: Compiler = Default::default();
+    compiler.set_graph_cache(Some(GraphCache::next_to_save(path)));
 
     // println!("lever_use result: {:?} {:?}", result, lever);
 
     // let options = CompilerOptions::parse("-O");
     let options = CompilerOptions::default();
     compiler.compile(&mut world, options, Vec::new());
-    compiler.on_use_block(&mut world, START_BUTTON);
+    compiler.on_use_block(&mut world, START_BUTTON).unwrap();
 
     (world, compiler)
 }
@@ -94,7 +96,7 @@ fn mandelbrot_full() {
         if (i % 10000 == 0) {
             println!("{}", i);
         }
-        compiler.tick(&mut world);
+        compiler.tick(&mut world).unwrap();
     }
     println!("Mandelbrot benchmark completed in {:?}", start.elapsed());
 }